@@ -0,0 +1,429 @@
+use std::error;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, Error, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use ::color::{encode_gba, gba_from_hex, Color, ColorParseError};
+use ::data::{self, Character, CHARACTERS};
+use ::manager::palette::{PaletteError, PaletteFormat, PaletteManager};
+
+/// an invocation starting with one of these runs the command line instead of the GUI
+pub const SUBCOMMANDS: &[&str] = &["list", "show", "export", "import", "set", "write", "repl", "help"];
+
+/// the `--format` names, the same as the file extensions
+const FORMATS: &[&str] = &["pal", "gpl", "json", "png", "c", "aseprite"];
+
+const REPL_HELP: &str = "\
+show <character>                 print a palette, reading it from the ROM the first time
+set <character> <index> <color>  change a color, either #RRGGBB or a BGR555 value like 0x7C1F
+undo                             revert the last edit
+redo                             reapply the last undone edit
+write <character>                write one palette to the ROM, edits and all
+save                             write every edited palette to the ROM
+list                             list the characters
+help                             show this
+quit                             leave, edits that weren't written are lost";
+
+#[derive(Debug)]
+pub enum CliError {
+    /// no character has the given name
+    UnknownCharacter(String),
+    /// an argument couldn't be parsed
+    BadArgument { name: &'static str, value: String, reason: String },
+    Palette(PaletteError),
+}
+
+impl From<PaletteError> for CliError {
+    fn from(error: PaletteError) -> CliError {
+        CliError::Palette(error)
+    }
+}
+
+impl From<Error> for CliError {
+    fn from(error: Error) -> CliError {
+        CliError::Palette(PaletteError::from(error))
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CliError::UnknownCharacter(ref name) => {
+                let names: Vec<&str> = CHARACTERS.iter().map(|character| character.name).collect();
+                write!(f, "no character named {}, the characters are: {}", name, names.join(", "))
+            }
+            CliError::BadArgument { name, ref value, ref reason } => write!(f, "invalid {} {}: {}", name, value, reason),
+            CliError::Palette(ref error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl error::Error for CliError {}
+
+fn app() -> App<'static, 'static> {
+    let rom = || Arg::with_name("rom").help("the Sonic Battle ROM").required(true);
+    let character = || Arg::with_name("character").help("the character's name, in any case").required(true);
+
+    App::new("sbrx")
+        .version(::VERSION)
+        .about("Sonic Battle palette editor, run with just a ROM to open the GUI")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("list")
+            .about("List the characters and their palette offsets")
+            .arg(rom()))
+        .subcommand(SubCommand::with_name("show")
+            .about("Print a character's palette")
+            .arg(rom())
+            .arg(character()))
+        .subcommand(SubCommand::with_name("export")
+            .about("Export a character's palette")
+            .arg(rom())
+            .arg(character())
+            .arg(Arg::with_name("format").long("format").short("f").takes_value(true)
+                .possible_values(FORMATS).default_value("pal").help("the file format"))
+            .arg(Arg::with_name("output").long("output").short("o").takes_value(true)
+                .help("where to write the palette, standard output if not given")))
+        .subcommand(SubCommand::with_name("import")
+            .about("Import a palette file and write it to a character")
+            .arg(rom())
+            .arg(character())
+            .arg(Arg::with_name("file").help("the palette file").required(true))
+            .arg(Arg::with_name("format").long("format").short("f").takes_value(true)
+                .possible_values(FORMATS).help("the file format, from the extension if not given")))
+        .subcommand(SubCommand::with_name("set")
+            .about("Change one color of a character's palette")
+            .arg(rom())
+            .arg(character())
+            .arg(Arg::with_name("index").help("the color index").required(true))
+            .arg(Arg::with_name("color").help("#RRGGBB or a BGR555 value like 0x7C1F").required(true)))
+        .subcommand(SubCommand::with_name("write")
+            .about("Write a character's palette back to the ROM, or a whole new one")
+            .arg(rom())
+            .arg(character())
+            .arg(Arg::with_name("colors").help("#RRGGBB or BGR555 values like 0x7C1F, the palette in the ROM is rewritten as is if none are given").multiple(true)))
+        .subcommand(SubCommand::with_name("repl")
+            .about("Edit palettes interactively, nothing is written until write or save")
+            .arg(rom()))
+}
+
+/// Run the command line with the program's arguments and return the exit code
+pub fn run(args: Vec<String>) -> i32 {
+    let matches = app().get_matches_from(args);
+    let stdout = io::stdout();
+    match execute(&matches, &mut stdout.lock()) {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            1
+        }
+    }
+}
+
+fn execute(matches: &ArgMatches, output: &mut impl Write) -> Result<(), CliError> {
+    let (command, arguments) = match matches.subcommand() {
+        (command, Some(arguments)) => (command, arguments),
+        _ => return Ok(()),
+    };
+    let rom = Path::new(arguments.value_of("rom").unwrap());
+
+    match command {
+        "list" => {
+            let manager = open(rom, false)?;
+            for character in CHARACTERS.iter() {
+                match character.palette_offset_for(manager.region()) {
+                    Some(offset) => writeln!(output, "{:<10} {:#08X}", character.name, offset)?,
+                    None => writeln!(output, "{:<10} not mapped for this region", character.name)?,
+                }
+            }
+        }
+        "show" => {
+            let character = character(arguments)?;
+            let mut manager = open(rom, false)?;
+            manager.read_palette(character)?;
+            writeln!(output, "v== {} ==v", character.name)?;
+            for color in manager.load_palette_colors(character.name.to_string())?.iter() {
+                writeln!(output, "{}", color)?;
+            }
+            writeln!(output, "^== {} ==^", character.name)?;
+        }
+        "export" => {
+            let character = character(arguments)?;
+            let format = PaletteFormat::from_extension(arguments.value_of("format").unwrap()).unwrap();
+            let mut manager = open(rom, false)?;
+            manager.read_palette(character)?;
+            match arguments.value_of("output") {
+                Some(path) => manager.export_as(character.name, format, &mut File::create(path)?)?,
+                None => manager.export_as(character.name, format, output)?,
+            }
+        }
+        "import" => {
+            let character = character(arguments)?;
+            let path = Path::new(arguments.value_of("file").unwrap());
+            let format = arguments.value_of("format")
+                .or_else(|| path.extension().and_then(|extension| extension.to_str()))
+                .and_then(PaletteFormat::from_extension)
+                .ok_or_else(|| CliError::BadArgument {
+                    name: "file",
+                    value: path.display().to_string(),
+                    reason: "can't tell the format from the extension, pass --format".to_string(),
+                })?;
+            let mut manager = open(rom, true)?;
+            manager.import_as(character.name.to_string(), format, &mut File::open(path)?)?;
+            manager.write_palette(character)?;
+        }
+        "set" => {
+            let character = character(arguments)?;
+            let index = arguments.value_of("index").unwrap();
+            let index: usize = index.parse().map_err(|_| CliError::BadArgument {
+                name: "index",
+                value: index.to_string(),
+                reason: "not a number".to_string(),
+            })?;
+            let value = parse_color(arguments.value_of("color").unwrap())?;
+            let mut manager = open(rom, true)?;
+            manager.read_palette(character)?;
+            manager.set_color_gba(character.name, index, value)?;
+            manager.write_palette(character)?;
+        }
+        "write" => {
+            let character = character(arguments)?;
+            let mut values = Vec::new();
+            for color in arguments.values_of("colors").into_iter().flatten() {
+                values.push(parse_color(color)?);
+            }
+            let mut manager = open(rom, true)?;
+            if values.is_empty() {
+                manager.read_palette(character)?;
+            } else {
+                manager.store_palette_i32_checked(character.name.to_string(), values)?;
+            }
+            manager.write_palette(character)?;
+        }
+        "repl" => {
+            let mut manager = open(rom, true)?;
+            let stdin = io::stdin();
+            run_repl(&mut manager, &mut stdin.lock(), output)?;
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Read commands from `input` until it ends or says `quit`, printing results to `output`.
+/// Edits stay in the manager until a `write` or `save` command, a failed command is
+/// reported and the session carries on
+pub fn run_repl<S: Read + Write + Seek>(manager: &mut PaletteManager<S>, input: &mut impl BufRead, output: &mut impl Write) -> Result<(), Error> {
+    let mut line = String::new();
+    loop {
+        write!(output, "> ")?;
+        output.flush()?;
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.first() {
+            None => continue,
+            Some(&"quit") | Some(&"exit") => return Ok(()),
+            Some(_) => (),
+        }
+        if let Err(error) = repl_command(manager, &words, output) {
+            writeln!(output, "error: {}", error)?;
+        }
+    }
+}
+
+fn repl_command<S: Read + Write + Seek>(manager: &mut PaletteManager<S>, words: &[&str], output: &mut impl Write) -> Result<(), CliError> {
+    match *words {
+        ["help"] => writeln!(output, "{}", REPL_HELP)?,
+        ["list"] => {
+            for character in CHARACTERS.iter() {
+                let state = if manager.has_palette(character.name) { "loaded" } else { "" };
+                writeln!(output, "{:<10} {}", character.name, state)?;
+            }
+        }
+        ["show", name] => {
+            let character = find_character(name)?;
+            if !manager.has_palette(character.name) {
+                manager.read_palette(character)?;
+            }
+            for (index, color) in manager.load_palette_colors(character.name.to_string())?.iter().enumerate() {
+                writeln!(output, "{:2}: {}", index, color)?;
+            }
+        }
+        ["set", name, index, color] => {
+            let character = find_character(name)?;
+            let index: usize = index.parse().map_err(|_| CliError::BadArgument {
+                name: "index",
+                value: index.to_string(),
+                reason: "not a number".to_string(),
+            })?;
+            if !manager.has_palette(character.name) {
+                manager.read_palette(character)?;
+            }
+            manager.set_color_gba(character.name, index, parse_color(color)?)?;
+        }
+        ["undo"] => manager.undo()?,
+        ["redo"] => manager.redo()?,
+        ["write", name] => {
+            let character = find_character(name)?;
+            manager.write_palette(character)?;
+            writeln!(output, "wrote {}", character.name)?;
+        }
+        ["save"] => {
+            let names: Vec<String> = manager.dirty_names().iter().map(|name| name.to_string()).collect();
+            manager.write_dirty()?;
+            writeln!(output, "wrote {}", if names.is_empty() { "nothing".to_string() } else { names.join(", ") })?;
+        }
+        _ => writeln!(output, "unknown command {:?}, try help", words.join(" "))?,
+    }
+    Ok(())
+}
+
+/// open and validate the ROM. A writable ROM is copied to `<rom>.bak` before the first write,
+/// unless that file already exists, so the backup stays the ROM from before any edit
+fn open(path: &Path, writable: bool) -> Result<PaletteManager<File>, CliError> {
+    let file = OpenOptions::new().read(true).write(writable).open(path)?;
+    let mut manager = PaletteManager::open_strict(Arc::new(Mutex::new(file)))?;
+    if writable {
+        manager.set_auto_backup(Some(PathBuf::from(format!("{}.bak", path.display()))));
+    }
+    Ok(manager)
+}
+
+fn character(arguments: &ArgMatches) -> Result<&'static Character, CliError> {
+    find_character(arguments.value_of("character").unwrap())
+}
+
+fn find_character(name: &str) -> Result<&'static Character, CliError> {
+    data::character_by_name(name).ok_or_else(|| CliError::UnknownCharacter(name.to_string()))
+}
+
+/// parse `#RRGGBB` into its BGR555 value, anything else as a BGR555 value like `0x7C1F`
+fn parse_color(value: &str) -> Result<i32, CliError> {
+    let parsed = if value.starts_with('#') {
+        Color::from_hex(value).map(encode_gba)
+    } else {
+        gba_from_hex(value)
+    };
+    parsed.map_err(|error: ColorParseError| CliError::BadArgument {
+        name: "color",
+        value: value.to_string(),
+        reason: error.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use ::testing;
+
+    fn run_on(rom: &Path, args: &[&str]) -> String {
+        let mut line = vec!["sbrx", args[0]];
+        let path = rom.display().to_string();
+        line.push(&path);
+        line.extend_from_slice(&args[1..]);
+        let mut output = Vec::new();
+        execute(&app().get_matches_from(line), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn sonic_colors(rom: &Path) -> Vec<i32> {
+        let mut manager = open(rom, false).unwrap();
+        manager.read_palette(&CHARACTERS[0]).unwrap();
+        manager.load_palette_i32("Sonic".to_string()).unwrap()
+    }
+
+    #[test]
+    fn colors_parse_as_rgb_hex_or_bgr555() {
+        assert_eq!(parse_color("#FF0000").unwrap(), 0x001F);
+        assert_eq!(parse_color("#0000FF").unwrap(), 0x7C00);
+        assert_eq!(parse_color("0x7C1F").unwrap(), 0x7C1F);
+        assert_eq!(parse_color("3E0").unwrap(), 0x03E0);
+        assert!(parse_color("0x8000").is_err());
+        assert!(parse_color("#GG0000").is_err());
+        assert!(parse_color("").is_err());
+    }
+
+    #[test]
+    fn commands_edit_and_show_a_rom() {
+        let rom = testing::temp_path("cli.gba");
+        let backup = PathBuf::from(format!("{}.bak", rom.display()));
+        fs::write(&rom, testing::blank_rom()).unwrap();
+
+        run_on(&rom, &["set", "sonic", "1", "#FF0000"]);
+        run_on(&rom, &["set", "SONIC", "2", "0x7C00"]);
+        let shown = run_on(&rom, &["show", "sonic"]);
+        let lines: Vec<&str> = shown.lines().collect();
+        assert_eq!(lines.len(), 16 + 2);
+        assert_eq!(lines[0], "v== Sonic ==v");
+        assert_eq!(lines[2], format!("{}", Color::rgb(255, 0, 0)));
+        assert_eq!(lines[3], format!("{}", Color::rgb(0, 0, 255)));
+        assert_eq!(lines[17], "^== Sonic ==^");
+        // the backup is the ROM from before the first set, the second didn't replace it
+        assert_eq!(fs::read(&backup).unwrap(), testing::blank_rom());
+
+        run_on(&rom, &["write", "sonic"]);
+        assert_eq!(&sonic_colors(&rom)[..3], &[0, 0x001F, 0x7C00]);
+        let values: Vec<String> = (0..16).map(|value| format!("{:#X}", value)).collect();
+        let mut args = vec!["write", "sonic"];
+        args.extend(values.iter().map(|value| value.as_str()));
+        run_on(&rom, &args);
+        assert_eq!(sonic_colors(&rom), (0..16).collect::<Vec<i32>>());
+
+        fs::remove_file(&rom).unwrap();
+        fs::remove_file(&backup).unwrap();
+    }
+
+    #[test]
+    fn the_repl_edits_until_write() {
+        let rom = testing::temp_path("repl.gba");
+        let backup = PathBuf::from(format!("{}.bak", rom.display()));
+        fs::write(&rom, testing::blank_rom()).unwrap();
+
+        let mut manager = open(&rom, true).unwrap();
+        let script = "show sonic\nset sonic 0 #00FF00\nset sonic 1 0x001F\nundo\nset sonic 99 0x1\nfly\nwrite sonic\nquit\nshow sonic\n";
+        let mut output = Vec::new();
+        run_repl(&mut manager, &mut script.as_bytes(), &mut output).unwrap();
+        drop(manager);
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(&format!(" 0: {}", Color::rgb(0, 0, 0))));
+        assert!(output.contains("error: "));
+        assert!(output.contains("unknown command \"fly\""));
+        assert!(output.contains("wrote Sonic"));
+        // quit ends the session before the second show
+        assert_eq!(output.matches(" 0: ").count(), 1);
+        assert_eq!(&sonic_colors(&rom)[..2], &[0x03E0, 0]);
+        assert!(!fs::read(&backup).unwrap().is_empty());
+
+        fs::remove_file(&rom).unwrap();
+        fs::remove_file(&backup).unwrap();
+    }
+
+    #[test]
+    fn the_repl_only_saves_when_asked() {
+        let mut manager = PaletteManager::from_bytes(testing::blank_rom());
+        let script = "help\n\nset tails 2 #0000FF\nundo\nredo\nlist\nsave\nsave\n";
+        let mut output = Vec::new();
+        run_repl(&mut manager, &mut script.as_bytes(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(REPL_HELP));
+        assert!(output.contains("Tails      loaded"));
+        assert!(output.contains("wrote Tails\n"));
+        assert!(output.contains("wrote nothing\n"));
+        let offset = CHARACTERS.iter().find(|character| character.name == "Tails").unwrap().palette_offset as usize;
+        assert_eq!(&manager.into_bytes()[offset + 4..offset + 6], &[0x00, 0x7C]);
+
+        // edits without a save never reach the ROM
+        let mut manager = PaletteManager::from_bytes(testing::blank_rom());
+        run_repl(&mut manager, &mut "set tails 2 #0000FF\n".as_bytes(), &mut Vec::new()).unwrap();
+        assert!(manager.into_bytes() == testing::blank_rom());
+    }
+}
@@ -1,56 +1,1054 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::cmp;
+use std::hash::{Hash, Hasher};
+use std::error;
+use std::fmt;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de;
 
-#[derive(Eq, PartialEq, Hash, Copy, Clone, Debug)]
+/// an 8 bit per channel color. Equality and hashing only look at r, g and b, so colors that
+/// encode to the same GBA value compare equal whatever their alpha, see `eq_rgba`
+#[derive(Copy, Clone, Debug)]
 pub struct Color {
     pub r: i32,
     pub g: i32,
     pub b: i32,
+    /// opacity, 255 is opaque. The GBA has no alpha so encoding ignores it
+    pub a: u8,
 }
 
+#[derive(Debug, PartialEq)]
+pub enum ColorParseError {
+    /// the string has the wrong number of hex digits for the format
+    InvalidLength(usize),
+    /// the string contains something that isn't a hex digit
+    InvalidDigit(char),
+    /// the value doesn't fit in 15 bits
+    OutOfRange(i32),
+}
+
+impl fmt::Display for ColorParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ColorParseError::InvalidLength(length) => write!(f, "wrong number of hex digits: {}", length),
+            ColorParseError::InvalidDigit(c) => write!(f, "{:?} is not a hex digit", c),
+            ColorParseError::OutOfRange(value) => write!(f, "{:#X} is larger than the GBA maximum 0x7FFF", value),
+        }
+    }
+}
+
+impl error::Error for ColorParseError {}
+
+/// parse hex digits into a number, rejecting anything else
+fn parse_hex_digits(s: &str) -> Result<i32, ColorParseError> {
+    s.chars().try_fold(0, |value, c| {
+        let digit = c.to_digit(16).ok_or(ColorParseError::InvalidDigit(c))?;
+        Ok(value * 16 + digit as i32)
+    })
+}
+
+/// parse up to 4 hex digits with an optional 0x prefix
+fn parse_gba_hex(s: &str) -> Result<i32, ColorParseError> {
+    let digits = s.trim().trim_start_matches("0x").trim_start_matches("0X");
+    match digits.chars().count() {
+        1..=4 => parse_hex_digits(digits),
+        length => Err(ColorParseError::InvalidLength(length)),
+    }
+}
+
+/// parse a raw BGR555 value like `0x7C1F`, rejecting anything above 0x7FFF
+pub fn gba_from_hex(s: &str) -> Result<i32, ColorParseError> {
+    let value = parse_gba_hex(s)?;
+    if value > GBA_COLOR_MASK {
+        return Err(ColorParseError::OutOfRange(value));
+    }
+    Ok(value)
+}
+
+/// parse a raw BGR555 value like `0x7C1F`, dropping the unused top bit
+pub fn gba_from_hex_masked(s: &str) -> Result<i32, ColorParseError> {
+    Ok(parse_gba_hex(s)? & GBA_COLOR_MASK)
+}
+
+/// format a raw BGR555 value like `0x7C1F`
+pub fn gba_to_hex(value: i32) -> String {
+    format!("0x{:04X}", value & GBA_COLOR_MASK)
+}
+
+impl Color {
+    /// an opaque color
+    pub const fn rgb(r: i32, g: i32, b: i32) -> Color {
+        Color { r, g, b, a: 0xFF }
+    }
+
+    pub const fn rgba(r: i32, g: i32, b: i32, a: u8) -> Color {
+        Color { r, g, b, a }
+    }
+
+    /// parse a color from `#RRGGBB`, `RRGGBB`, or the `#RGB` shorthand
+    pub fn from_hex(s: &str) -> Result<Color, ColorParseError> {
+        let digits = s.trim().trim_start_matches('#');
+        match digits.chars().count() {
+            6 => {
+                let value = parse_hex_digits(digits)?;
+                Ok(Color::rgb((value >> 16) & 0xFF, (value >> 8) & 0xFF, value & 0xFF))
+            }
+            3 => {
+                // each shorthand digit is doubled, so #F80 is #FF8800
+                let value = parse_hex_digits(digits)?;
+                Ok(Color::rgb(((value >> 8) & 0xF) * 0x11, ((value >> 4) & 0xF) * 0x11, (value & 0xF) * 0x11))
+            }
+            length => Err(ColorParseError::InvalidLength(length)),
+        }
+    }
+
+    /// whether the colors match in every channel, alpha included
+    pub fn eq_rgba(&self, other: &Color) -> bool {
+        *self == *other && self.a == other.a
+    }
+
+    /// format as `#RRGGBB`
+    pub fn to_hex(&self) -> String {
+        format!("#{:02X}{:02X}{:02X}", self.r, self.g, self.b)
+    }
+
+    /// round each channel to the nearest of `WEBSAFE_LEVELS`
+    pub fn to_websafe(&self) -> Color {
+        let snap = |c: i32| (c.clamp(0, 255) + 25) / 51 * 51;
+        Color::rgba(snap(self.r), snap(self.g), snap(self.b), self.a)
+    }
+
+    /// add to each channel, stopping at 0 and 255
+    pub fn saturating_add_rgb(&self, r: i16, g: i16, b: i16) -> Color {
+        Color::rgba(self.r + r as i32, self.g + g as i32, self.b + b as i32, self.a).clamp()
+    }
+
+    /// multiply each channel by `factor`, stopping at 0 and 255
+    pub fn scale(&self, factor: f32) -> Color {
+        let scale = |c: i32| (c as f32 * factor).round() as i32;
+        Color::rgba(scale(self.r), scale(self.g), scale(self.b), self.a).clamp()
+    }
+
+    /// pull each channel into 0..=255
+    pub fn clamp(&self) -> Color {
+        let clamp = |c: i32| c.clamp(0, 255);
+        Color::rgba(clamp(self.r), clamp(self.g), clamp(self.b), self.a)
+    }
+
+    /// convert to hue in degrees (0..360), saturation and value (0..1)
+    pub fn to_hsv(&self) -> (f32, f32, f32) {
+        let r = self.r as f32 / 255.0;
+        let g = self.g as f32 / 255.0;
+        let b = self.b as f32 / 255.0;
+
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+
+        let hue = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        let saturation = if max == 0.0 { 0.0 } else { delta / max };
+
+        ((hue + 360.0) % 360.0, saturation, max)
+    }
+
+    /// convert to CIELAB (L, a, b) under the D65 white point
+    pub fn to_lab(&self) -> (f64, f64, f64) {
+        let (r, g, b) = (srgb_to_linear(self.r), srgb_to_linear(self.g), srgb_to_linear(self.b));
+
+        // linear sRGB to XYZ, divided by the D65 white point
+        let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+        let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+        let f = |t: f64| if t > 216.0 / 24389.0 { t.cbrt() } else { (24389.0 / 27.0 * t + 16.0) / 116.0 };
+        let (fx, fy, fz) = (f(x), f(y), f(z));
+        (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+    }
+
+    /// convert from hue in degrees, saturation and value (0..1)
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Color {
+        let hue = ((hue % 360.0) + 360.0) % 360.0;
+        let chroma = value * saturation;
+        let x = chroma * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+        let m = value - chroma;
+
+        let (r, g, b) = match (hue / 60.0) as i32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+
+        let channel = |c: f32| ((c + m) * 255.0).round() as i32;
+        Color::rgb(channel(r), channel(g), channel(b))
+    }
+}
+
+impl PartialEq for Color {
+    fn eq(&self, other: &Color) -> bool {
+        (self.r, self.g, self.b) == (other.r, other.g, other.b)
+    }
+}
+
+impl Eq for Color {}
+
+impl Hash for Color {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.r, self.g, self.b).hash(state);
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "rgb({}, {}, {}) {}", self.r, self.g, self.b, self.to_hex())
+    }
+}
+
+// colors are written as [r, g, b] so palette dumps stay compact and easy to edit by hand,
+// with a fourth alpha value only when the color isn't opaque
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.a == 0xFF {
+            (self.r, self.g, self.b).serialize(serializer)
+        } else {
+            (self.r, self.g, self.b, self.a).serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+        let channels: Vec<i32> = Deserialize::deserialize(deserializer)?;
+        match *channels.as_slice() {
+            [r, g, b] | [r, g, b, _] if [r, g, b].iter().any(|c| !(0..=0xFF).contains(c)) =>
+                Err(de::Error::invalid_value(de::Unexpected::Seq, &"channels from 0 to 255")),
+            [r, g, b] => Ok(Color::rgb(r, g, b)),
+            [r, g, b, a] if (0..=0xFF).contains(&a) => Ok(Color::rgba(r, g, b, a as u8)),
+            [_, _, _, a] => Err(de::Error::invalid_value(de::Unexpected::Signed(a as i64), &"an alpha from 0 to 255")),
+            _ => Err(de::Error::invalid_length(channels.len(), &"[r, g, b] or [r, g, b, a]")),
+        }
+    }
+}
+
+/// GBA colors are BGR555, bit 15 is ignored by the hardware
+pub const GBA_COLOR_MASK: i32 = 0x7FFF;
+
+/// the channel values of the 6x6x6 web-safe color cube
+pub const WEBSAFE_LEVELS: [i32; 6] = [0, 51, 102, 153, 204, 255];
+
 // Special colors used in the editor
-const PURPLE_1: Color = Color { r: 255, g: 0, b: 250 };
-const PURPLE_2: Color = Color { r: 185, g: 0, b: 255 };
-const PURPLE_3: Color = Color { r: 185, g: 0, b: 185 };
+const PURPLE_1: Color = Color::rgb(255, 0, 250);
+const PURPLE_2: Color = Color::rgb(185, 0, 255);
+const PURPLE_3: Color = Color::rgb(185, 0, 185);
+
+/// weighted euclidean distance between two colors, green counts most since the eye is most sensitive to it
+pub fn color_distance(a: Color, b: Color) -> f64 {
+    let dr = (a.r - b.r) as f64;
+    let dg = (a.g - b.g) as f64;
+    let db = (a.b - b.b) as f64;
+    (2.0 * dr * dr + 4.0 * dg * dg + 3.0 * db * db).sqrt()
+}
+
+/// ways of measuring how different two colors look
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorMetric {
+    /// straight line distance between the 8 bit channels
+    EuclideanRgb,
+    /// the CIE's perceptual difference formula, about 1.0 is the smallest difference people notice
+    Ciede2000,
+}
+
+/// how different two colors look by `metric`
+pub fn metric_distance(a: Color, b: Color, metric: ColorMetric) -> f64 {
+    match metric {
+        ColorMetric::EuclideanRgb => {
+            let dr = (a.r - b.r) as f64;
+            let dg = (a.g - b.g) as f64;
+            let db = (a.b - b.b) as f64;
+            (dr * dr + dg * dg + db * db).sqrt()
+        }
+        ColorMetric::Ciede2000 => ciede2000(a.to_lab(), b.to_lab()),
+    }
+}
+
+/// the CIEDE2000 difference between two CIELAB colors
+pub fn ciede2000(lab1: (f64, f64, f64), lab2: (f64, f64, f64)) -> f64 {
+    let (l1, a1, b1) = lab1;
+    let (l2, a2, b2) = lab2;
+
+    let c_bar = ((a1 * a1 + b1 * b1).sqrt() + (a2 * a2 + b2 * b2).sqrt()) / 2.0;
+    let c_bar7 = c_bar.powi(7);
+    let g = 0.5 * (1.0 - (c_bar7 / (c_bar7 + 25f64.powi(7))).sqrt());
+    let a1 = a1 * (1.0 + g);
+    let a2 = a2 * (1.0 + g);
+
+    let c1 = (a1 * a1 + b1 * b1).sqrt();
+    let c2 = (a2 * a2 + b2 * b2).sqrt();
+    let hue = |b: f64, a: f64| if a == 0.0 && b == 0.0 { 0.0 } else { (b.atan2(a).to_degrees() + 360.0) % 360.0 };
+    let h1 = hue(b1, a1);
+    let h2 = hue(b2, a2);
+
+    let delta_l = l2 - l1;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= 180.0 {
+        h2 - h1
+    } else if h2 <= h1 {
+        h2 - h1 + 360.0
+    } else {
+        h2 - h1 - 360.0
+    };
+    let delta_big_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).to_radians().sin();
+
+    let l_bar = (l1 + l2) / 2.0;
+    let c_bar = (c1 + c2) / 2.0;
+    let h_bar = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (h_bar - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * h_bar).to_radians().cos()
+        + 0.32 * (3.0 * h_bar + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * h_bar - 63.0).to_radians().cos();
+    let delta_theta = 30.0 * (-((h_bar - 275.0) / 25.0).powi(2)).exp();
+    let c_bar7 = c_bar.powi(7);
+    let r_c = 2.0 * (c_bar7 / (c_bar7 + 25f64.powi(7))).sqrt();
+    let s_l = 1.0 + 0.015 * (l_bar - 50.0).powi(2) / (20.0 + (l_bar - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_bar;
+    let s_h = 1.0 + 0.015 * c_bar * t;
+    let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+    let l_term = delta_l / s_l;
+    let c_term = delta_c / s_c;
+    let h_term = delta_big_h / s_h;
+    (l_term * l_term + c_term * c_term + h_term * h_term + r_t * c_term * h_term).sqrt()
+}
+
+/// the kinds of color vision deficiency `simulate_cvd` can model
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CvdType {
+    /// no working long wavelength (red) cones
+    Protanopia,
+    /// no working medium wavelength (green) cones
+    Deuteranopia,
+    /// no working short wavelength (blue) cones
+    Tritanopia,
+}
+
+const RGB_TO_LMS: [[f64; 3]; 3] = [
+    [17.8824, 43.5161, 4.11935],
+    [3.45565, 27.1554, 3.86714],
+    [0.0299566, 0.184309, 1.46709],
+];
+
+const LMS_TO_RGB: [[f64; 3]; 3] = [
+    [0.0809444479, -0.130504409, 0.116721066],
+    [-0.0102485335, 0.0540193266, -0.113614708],
+    [-0.000365296938, -0.00412161469, 0.693511405],
+];
+
+fn multiply(matrix: &[[f64; 3]; 3], v: [f64; 3]) -> [f64; 3] {
+    let row = |i: usize| matrix[i][0] * v[0] + matrix[i][1] * v[1] + matrix[i][2] * v[2];
+    [row(0), row(1), row(2)]
+}
+
+/// how `color` looks with a color vision deficiency, using the Vienot, Brettel and Mollon LMS projection
+pub fn simulate_cvd(color: Color, kind: CvdType) -> Color {
+    // the projection works on linear light, not gamma encoded sRGB
+    let lms = multiply(&RGB_TO_LMS, [srgb_to_linear(color.r), srgb_to_linear(color.g), srgb_to_linear(color.b)]);
+    let (l, m, s) = (lms[0], lms[1], lms[2]);
+    let simulated = match kind {
+        CvdType::Protanopia => [2.02344 * m - 2.52581 * s, m, s],
+        CvdType::Deuteranopia => [l, 0.494207 * l + 1.24827 * s, s],
+        CvdType::Tritanopia => [l, m, -0.0122454 * l + 0.0720368 * m],
+    };
+    let rgb = multiply(&LMS_TO_RGB, simulated);
+    Color::rgb(linear_to_srgb(rgb[0]), linear_to_srgb(rgb[1]), linear_to_srgb(rgb[2]))
+}
+
+/// undo the sRGB transfer function on an 8 bit channel, giving linear light from 0 to 1
+fn srgb_to_linear(c: i32) -> f64 {
+    let c = c.clamp(0, 255) as f64 / 255.0;
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+/// apply the sRGB transfer function to linear light, giving an 8 bit channel
+fn linear_to_srgb(c: f64) -> i32 {
+    let c = c.clamp(0.0, 1.0);
+    let c = if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+    (c * 255.0).round() as i32
+}
+
+/// scale a 5 bit channel to 8 bits, so 31 maps to 255
+fn expand_channel(value: i32) -> i32 {
+    (value * 255 + 15) / 31
+}
+
+/// round an 8 bit channel to the nearest 5 bit value, so it survives a round trip through `expand_channel`
+fn reduce_channel(value: i32) -> i32 {
+    let value = value.clamp(0, 255);
+    (value * 31 + 127) / 255
+}
+
+/// reduce `pixels` to at most `max_colors` representative colors with median cut,
+/// the colors covering the most pixels come first
+pub fn median_cut(pixels: &[Color], max_colors: usize) -> Vec<Color> {
+    let mut counts: HashMap<Color, usize> = HashMap::new();
+    for &color in pixels.iter() {
+        *counts.entry(color).or_insert(0) += 1;
+    }
+    if counts.is_empty() || max_colors == 0 {
+        return Vec::new();
+    }
+
+    let channel = |color: &Color, axis: usize| match axis {
+        0 => color.r,
+        1 => color.g,
+        _ => color.b,
+    };
+    let range = |bucket: &[(Color, usize)], axis: usize| {
+        let values = bucket.iter().map(|&(color, _)| channel(&color, axis));
+        values.clone().max().unwrap() - values.min().unwrap()
+    };
+
+    let mut buckets: Vec<Vec<(Color, usize)>> = vec![counts.into_iter().collect()];
+    while buckets.len() < max_colors {
+        // split the bucket spread widest along any channel, until every bucket is a single color
+        let widest = buckets.iter().enumerate()
+            .filter(|&(_, bucket)| bucket.len() > 1)
+            .map(|(index, bucket)| {
+                let axis = (0..3).max_by_key(|&axis| range(bucket, axis)).unwrap();
+                (index, axis, range(bucket, axis))
+            })
+            .max_by_key(|&(_, _, spread)| spread);
+        let (index, axis) = match widest {
+            Some((index, axis, _)) => (index, axis),
+            None => break,
+        };
+
+        let mut bucket = buckets.swap_remove(index);
+        bucket.sort_by_key(|&(color, _)| (channel(&color, axis), color.r, color.g, color.b));
+
+        // cut at the pixel median, keeping at least one color on each side
+        let total: usize = bucket.iter().map(|&(_, count)| count).sum();
+        let mut seen = 0;
+        let mut cut = 1;
+        for (position, &(_, count)) in bucket.iter().enumerate() {
+            seen += count;
+            if seen * 2 >= total {
+                cut = cmp::max(1, cmp::min(position + 1, bucket.len() - 1));
+                break;
+            }
+        }
+        let upper = bucket.split_off(cut);
+        buckets.push(bucket);
+        buckets.push(upper);
+    }
+
+    let mut averages: Vec<(Color, usize)> = buckets.iter().map(|bucket| {
+        let total: usize = bucket.iter().map(|&(_, count)| count).sum();
+        let average = |axis: usize| {
+            let sum: usize = bucket.iter().map(|&(color, count)| channel(&color, axis) as usize * count).sum();
+            ((sum + total / 2) / total) as i32
+        };
+        (Color::rgb(average(0), average(1), average(2)), total)
+    }).collect();
+    averages.sort_by_key(|&(_, total)| cmp::Reverse(total));
+    averages.into_iter().map(|(color, _)| color).collect()
+}
+
+/// convert a color to BGR555, rounding each channel to the nearest 5 bit value
+pub fn encode_gba(color: Color) -> i32 {
+    let dr: i32 = reduce_channel(color.r);
+    let dg: i32 = reduce_channel(color.g);
+    let db: i32 = reduce_channel(color.b);
+
+    cmp::min(0x7FFFi32, (db * 0x400i32) + (dg * 0x20i32) + dr)
+}
+
+/// convert a BGR555 value to a color, ignoring the unused top bit
+pub fn decode_gba(value: i32) -> Color {
+    let r: i32 = expand_channel(value & 0x001f);
+    let g: i32 = expand_channel((value >> 5) & 0x001f);
+    let b: i32 = expand_channel((value >> 10) & 0x001f);
+    Color::rgb(r, g, b)
+}
+
+/// the gamma the GBA screen is treated as having, `rgb_to_gba_srgb` encodes for it
+pub const GBA_GAMMA: f64 = 2.2;
+
+/// convert a color to BGR555 through linear light: each channel is linearized and given the
+/// 5 bit level whose light on the `GBA_GAMMA` screen is nearest. Mid gray (128) becomes 15 where
+/// `encode_gba` gives 16
+pub fn rgb_to_gba_srgb(color: Color) -> i32 {
+    let reduce = |c: i32| {
+        let light = srgb_to_linear(c);
+        // the level found in gamma space and the one above it are the only candidates
+        let below = (light.powf(1.0 / GBA_GAMMA) * 31.0).floor() as i32;
+        let above = cmp::min(below + 1, 31);
+        if light - gba_level_light(below) <= gba_level_light(above) - light { below } else { above }
+    };
+    (reduce(color.b) << 10) | (reduce(color.g) << 5) | reduce(color.r)
+}
+
+/// the linear light a 5 bit level gives on the `GBA_GAMMA` screen, from 0 to 1
+fn gba_level_light(level: i32) -> f64 {
+    (level as f64 / 31.0).powf(GBA_GAMMA)
+}
+
+/// convert a BGR555 value made by `rgb_to_gba_srgb` back to a color, 15 becomes 124
+pub fn gba_to_rgb_srgb(value: i32) -> Color {
+    let expand = |v: i32| linear_to_srgb(gba_level_light(v));
+    Color::rgb(expand(value & 0x1F), expand((value >> 5) & 0x1F), expand((value >> 10) & 0x1F))
+}
+
+lazy_static! {
+    /// every BGR555 value decoded, so decoded colors can be handed out by reference
+    static ref DECODED_GBA_COLORS: Vec<Color> = (0..=GBA_COLOR_MASK).map(decode_gba).collect();
+}
+
+/// `decode_gba` by reference to a shared table
+pub fn decoded_gba_ref(value: i32) -> &'static Color {
+    &DECODED_GBA_COLORS[(value & GBA_COLOR_MASK) as usize]
+}
+
+/// the two bytes a BGR555 value is stored as in the ROM, low byte first
+pub fn gba_to_le_bytes(value: i32) -> [u8; 2] {
+    [(value & 0x00FF) as u8, ((value & 0xFF00) >> 8) as u8]
+}
+
+/// read a BGR555 value stored low byte first, dropping the unused top bit
+pub fn gba_from_le_bytes(bytes: [u8; 2]) -> i32 {
+    ((bytes[1] as i32) << 8 | bytes[0] as i32) & GBA_COLOR_MASK
+}
 
+/// a BGR555 value, always within 0..=0x7FFF
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GbaColor(u16);
+
+impl GbaColor {
+    /// a color from a raw value, failing if the unused top bit is set
+    pub fn new(value: u16) -> Result<GbaColor, ColorParseError> {
+        GbaColor::from_i32(value as i32)
+    }
+
+    /// a color from a raw value, failing if it's outside 0..=0x7FFF
+    pub fn from_i32(value: i32) -> Result<GbaColor, ColorParseError> {
+        if !(0..=GBA_COLOR_MASK).contains(&value) {
+            return Err(ColorParseError::OutOfRange(value));
+        }
+        Ok(GbaColor(value as u16))
+    }
+
+    /// a color from a raw value, dropping the unused top bit like the hardware does
+    pub fn masked(value: i32) -> GbaColor {
+        GbaColor((value & GBA_COLOR_MASK) as u16)
+    }
+
+    pub fn value(self) -> u16 {
+        self.0
+    }
+
+    /// the two bytes the color is stored as in the ROM, low byte first
+    pub fn to_le_bytes(self) -> [u8; 2] {
+        gba_to_le_bytes(self.0 as i32)
+    }
+
+    /// read a color stored low byte first, dropping the unused top bit
+    pub fn from_le_bytes(bytes: [u8; 2]) -> GbaColor {
+        GbaColor(gba_from_le_bytes(bytes) as u16)
+    }
+}
+
+impl From<Color> for GbaColor {
+    fn from(color: Color) -> GbaColor {
+        GbaColor(encode_gba(color) as u16)
+    }
+}
+
+impl From<GbaColor> for Color {
+    fn from(color: GbaColor) -> Color {
+        decode_gba(color.0 as i32)
+    }
+}
+
+impl fmt::Display for GbaColor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", gba_to_hex(self.0 as i32))
+    }
+}
+
+/// conversions each direction of `GBAColorCache` remembers unless configured otherwise
+pub const DEFAULT_CACHE_CAPACITY: usize = 4096;
+
+/// a map that forgets the least recently used entry once it's full
+struct LruCache<K: Hash + Eq + Clone, V: Copy> {
+    capacity: usize,
+    entries: HashMap<K, (V, u64)>,
+    /// keys by the tick they were last used, oldest first
+    usage: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+impl<K: Hash + Eq + Clone, V: Copy> LruCache<K, V> {
+    fn new(capacity: usize) -> LruCache<K, V> {
+        LruCache {
+            capacity,
+            entries: HashMap::new(),
+            usage: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, key: &K) -> Option<V> {
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.entries.get_mut(key)?;
+        self.usage.remove(&entry.1);
+        self.usage.insert(tick, key.clone());
+        entry.1 = tick;
+        Some(entry.0)
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        if let Some((_, old_tick)) = self.entries.insert(key.clone(), (value, self.tick)) {
+            self.usage.remove(&old_tick);
+        }
+        self.usage.insert(self.tick, key);
+
+        while self.entries.len() > self.capacity {
+            let oldest = *self.usage.keys().next().unwrap();
+            let key = self.usage.remove(&oldest).unwrap();
+            self.entries.remove(&key);
+        }
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.usage.clear();
+    }
+}
+
+/// remembers recent conversions, bounded so it can't grow forever in a long session
 pub struct GBAColorCache {
-    from_cache: HashMap<i32, Color>,
-    to_cache: HashMap<Color, i32>,
+    from_cache: LruCache<i32, Color>,
+    to_cache: LruCache<Color, i32>,
+}
+
+impl Default for GBAColorCache {
+    fn default() -> GBAColorCache {
+        GBAColorCache::new()
+    }
 }
 
 impl GBAColorCache {
     pub fn new() -> GBAColorCache {
+        GBAColorCache::with_capacity(DEFAULT_CACHE_CAPACITY)
+    }
+
+    /// a cache remembering up to `capacity` conversions in each direction
+    pub fn with_capacity(capacity: usize) -> GBAColorCache {
         GBAColorCache {
-            from_cache: HashMap::new(),
-            to_cache: HashMap::new(),
+            from_cache: LruCache::new(capacity),
+            to_cache: LruCache::new(capacity),
         }
     }
 
+    /// number of conversions remembered, in both directions
+    pub fn len(&self) -> usize {
+        self.from_cache.len() + self.to_cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.from_cache.clear();
+        self.to_cache.clear();
+    }
+
+    /// whether the GBA to RGB conversion of `value` is remembered
+    pub fn contains_gba(&self, value: i32) -> bool {
+        self.from_cache.contains(&value)
+    }
+
+    /// whether the RGB to GBA conversion of `color` is remembered
+    pub fn contains_rgb(&self, color: Color) -> bool {
+        self.to_cache.contains(&color)
+    }
+
     pub fn gba_to_rgb(&mut self, value: i32) -> Color {
         if let Some(color) = self.from_cache.get(&value) {
-            return color.clone();
+            return color;
         }
 
-        let r: i32 = ((value & (0x001f << 0)) >> 0) * 8 * (24 / 15);
-        let g: i32 = ((value & (0x001f << 5)) >> 5) * 8 * (24 / 15);
-        let b: i32 = ((value & (0x001f << 10)) >> 10) * 8 * (24 / 15);
-
-        let color = Color { r, g, b };
+        let color = decode_gba(value);
         self.from_cache.insert(value, color);
-        color.clone()
+        color
     }
 
     pub fn rgb_to_gba(&mut self, color: Color) -> i32 {
         if let Some(value) = self.to_cache.get(&color) {
-            return value.clone();
+            return value;
         }
 
-        let dr: i32 = cmp::min(31i32, (color.r as f32 / 8f32).ceil() as i32);
-        let dg: i32 = cmp::min(31i32, (color.g as f32 / 8f32).ceil() as i32);
-        let db: i32 = cmp::min(31i32, (color.b as f32 / 8f32).ceil() as i32);
-
-        let i: i32 = cmp::min(0x7FFFi32, ((db * 0x400i32) + (dg * 0x20i32) + dr) as i32);
+        let i = encode_gba(color);
         self.to_cache.insert(color, i);
         i
     }
+
+    /// convert many colors at once, a run of the same color is only looked up once
+    pub fn rgb_to_gba_slice(&mut self, colors: &[Color]) -> Vec<i32> {
+        let mut values = Vec::with_capacity(colors.len());
+        let mut previous: Option<(Color, i32)> = None;
+        for &color in colors.iter() {
+            let value = match previous {
+                Some((last, value)) if last == color => value,
+                _ => self.rgb_to_gba(color),
+            };
+            previous = Some((color, value));
+            values.push(value);
+        }
+        values
+    }
+
+    /// convert many BGR555 values at once, a run of the same value is only looked up once
+    pub fn gba_to_rgb_slice(&mut self, values: &[i32]) -> Vec<Color> {
+        let mut colors = Vec::with_capacity(values.len());
+        let mut previous: Option<(i32, Color)> = None;
+        for &value in values.iter() {
+            let color = match previous {
+                Some((last, color)) if last == value => color,
+                _ => self.gba_to_rgb(value),
+            };
+            previous = Some((value, color));
+            colors.push(color);
+        }
+        colors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decoding_ignores_the_unused_top_bit() {
+        let mut cache = GBAColorCache::new();
+        for &value in [0x0000, 0x001F, 0x7C1F, 0x7FFF].iter() {
+            assert_eq!(decode_gba(value | 0x8000), decode_gba(value));
+            assert_eq!(cache.gba_to_rgb(value | 0x8000), decode_gba(value));
+        }
+        assert_eq!(gba_from_le_bytes([0xFF, 0xFF]), 0x7FFF);
+    }
+
+    #[test]
+    fn every_gba_value_survives_a_round_trip() {
+        let mut cache = GBAColorCache::new();
+        for value in 0..=GBA_COLOR_MASK {
+            let color = cache.gba_to_rgb(value);
+            assert_eq!(cache.rgb_to_gba(color), value, "{:#06X} became {}", value, color);
+            assert_eq!(encode_gba(decode_gba(value)), value);
+        }
+    }
+
+    #[test]
+    fn every_5_bit_channel_is_a_fixed_point() {
+        for channel in 0..32 {
+            let expanded = expand_channel(channel);
+            assert_eq!(reduce_channel(expanded), channel);
+            assert_eq!(expand_channel(reduce_channel(expanded)), expanded);
+        }
+    }
+
+    #[test]
+    fn channels_round_to_the_nearest_5_bit_value() {
+        assert_eq!(reduce_channel(0), 0);
+        assert_eq!(reduce_channel(255), 31);
+        // truncating with >> 3 would give 15 and darken the color
+        assert_eq!(reduce_channel(128), 16);
+        assert_eq!(reduce_channel(-20), 0);
+        assert_eq!(reduce_channel(300), 31);
+        assert_eq!(expand_channel(31), 255);
+    }
+
+    #[test]
+    fn colors_serialize_as_channel_arrays() {
+        use serde_json;
+        assert_eq!(serde_json::to_string(&Color::rgb(1, 2, 3)).unwrap(), "[1,2,3]");
+        assert_eq!(serde_json::to_string(&Color::rgba(1, 2, 3, 4)).unwrap(), "[1,2,3,4]");
+        let color: Color = serde_json::from_str("[255, 0, 128]").unwrap();
+        assert!(color.eq_rgba(&Color::rgb(255, 0, 128)));
+        let color: Color = serde_json::from_str("[0, 0, 0, 7]").unwrap();
+        assert_eq!(color.a, 7);
+
+        assert!(serde_json::from_str::<Color>("[256, 0, 0]").is_err());
+        assert!(serde_json::from_str::<Color>("[0, 0, 0, 300]").is_err());
+        assert!(serde_json::from_str::<Color>("[0, 0]").is_err());
+    }
+
+    #[test]
+    fn hex_codes_parse_in_long_and_short_form() {
+        assert!(Color::from_hex("#FF8000").unwrap().eq_rgba(&Color::rgb(255, 128, 0)));
+        assert_eq!(Color::from_hex("ff8000").unwrap(), Color::rgb(255, 128, 0));
+        assert_eq!(Color::from_hex(" #F80 ").unwrap(), Color::rgb(255, 136, 0));
+        assert_eq!(Color::from_hex("#12345"), Err(ColorParseError::InvalidLength(5)));
+        assert_eq!(Color::from_hex("#GG0000"), Err(ColorParseError::InvalidDigit('G')));
+        assert_eq!(Color::from_hex(""), Err(ColorParseError::InvalidLength(0)));
+        assert_eq!(Color::rgb(255, 128, 0).to_hex(), "#FF8000");
+    }
+
+    #[test]
+    fn raw_gba_values_parse_and_format() {
+        assert_eq!(gba_from_hex("0x7C1F"), Ok(0x7C1F));
+        assert_eq!(gba_from_hex("7c1f"), Ok(0x7C1F));
+        assert_eq!(gba_from_hex("0X1f"), Ok(0x001F));
+        assert_eq!(gba_from_hex("0x8000"), Err(ColorParseError::OutOfRange(0x8000)));
+        assert_eq!(gba_from_hex("0x12345"), Err(ColorParseError::InvalidLength(5)));
+        assert_eq!(gba_from_hex("0x"), Err(ColorParseError::InvalidLength(0)));
+        assert_eq!(gba_from_hex_masked("0xFC1F"), Ok(0x7C1F));
+        assert_eq!(gba_to_hex(0x1F), "0x001F");
+        assert_eq!(gba_to_hex(0xFFFF), "0x7FFF");
+        assert_eq!(gba_from_hex(&gba_to_hex(0x2A5B)), Ok(0x2A5B));
+    }
+
+    #[test]
+    fn colors_display_their_channels_and_hex_code() {
+        assert_eq!(Color::rgb(255, 0, 16).to_string(), "rgb(255, 0, 16) #FF0010");
+        assert_eq!(format!("{}", Color::rgba(0, 0, 0, 0)), "rgb(0, 0, 0) #000000");
+    }
+
+    #[test]
+    fn the_cache_forgets_the_least_recently_used_conversion() {
+        let mut cache = GBAColorCache::with_capacity(2);
+        cache.gba_to_rgb(1);
+        cache.gba_to_rgb(2);
+        cache.gba_to_rgb(1);
+        cache.gba_to_rgb(3);
+        assert!(cache.contains_gba(1) && cache.contains_gba(3));
+        assert!(!cache.contains_gba(2));
+        assert_eq!(cache.len(), 2);
+
+        cache.rgb_to_gba(Color::rgb(255, 0, 0));
+        assert!(cache.contains_rgb(Color::rgb(255, 0, 0)));
+        assert_eq!(cache.len(), 3);
+        for value in 0..100 {
+            cache.gba_to_rgb(value);
+        }
+        assert_eq!(cache.len(), 3);
+        cache.clear();
+        assert!(cache.is_empty());
+
+        let mut disabled = GBAColorCache::with_capacity(0);
+        assert_eq!(disabled.gba_to_rgb(0x7FFF), decode_gba(0x7FFF));
+        assert!(disabled.is_empty());
+    }
+
+    #[test]
+    fn median_cut_splits_the_widest_channel_first() {
+        let red = Color::rgb(255, 0, 0);
+        let dark_red = Color::rgb(245, 0, 0);
+        let blue = Color::rgb(0, 0, 255);
+        let pixels = [red, red, dark_red, blue, blue, blue, blue];
+
+        // the reds are close together, so blue gets a bucket of its own before they split
+        assert_eq!(median_cut(&pixels, 2), vec![blue, Color::rgb(252, 0, 0)]);
+        assert_eq!(median_cut(&pixels, 3), vec![blue, red, dark_red]);
+        assert_eq!(median_cut(&pixels, 10).len(), 3);
+        assert_eq!(median_cut(&pixels, 1), vec![Color::rgb(108, 0, 146)]);
+        assert!(median_cut(&pixels, 0).is_empty());
+        assert!(median_cut(&[], 4).is_empty());
+    }
+
+    #[test]
+    fn simulated_deficiencies_keep_grays_and_confuse_red_with_green() {
+        for &kind in [CvdType::Protanopia, CvdType::Deuteranopia, CvdType::Tritanopia].iter() {
+            assert_eq!(simulate_cvd(Color::rgb(0, 0, 0), kind), Color::rgb(0, 0, 0));
+            let white = simulate_cvd(Color::rgb(255, 255, 255), kind);
+            assert!(white.r >= 250 && white.g >= 250 && white.b >= 250, "{:?} turned white into {}", kind, white);
+        }
+
+        // without red or green cones both land on the same yellow, differing only in brightness
+        for &kind in [CvdType::Protanopia, CvdType::Deuteranopia].iter() {
+            for &color in [Color::rgb(255, 0, 0), Color::rgb(0, 255, 0)].iter() {
+                let simulated = simulate_cvd(color, kind);
+                assert_eq!(simulated.r, simulated.g, "{:?} turned {} into {}", kind, color, simulated);
+                assert!(simulated.b < 0x30);
+            }
+        }
+    }
+
+    #[test]
+    fn websafe_colors_only_use_the_six_levels() {
+        for channel in 0..=255 {
+            let snapped = Color::rgba(channel, 255 - channel, channel / 2, 9).to_websafe();
+            for &(c, original) in [(snapped.r, channel), (snapped.g, 255 - channel), (snapped.b, channel / 2)].iter() {
+                assert!(WEBSAFE_LEVELS.contains(&c), "{} snapped to {}", original, c);
+                assert!((c - original).abs() <= 25, "{} snapped to {}", original, c);
+            }
+            assert_eq!(snapped.a, 9);
+        }
+        assert_eq!(Color::rgb(-40, 300, 0).to_websafe(), Color::rgb(0, 255, 0));
+    }
+
+    #[test]
+    fn free_functions_convert_single_colors_and_bytes() {
+        assert_eq!(encode_gba(Color::rgb(255, 0, 0)), 0x001F);
+        assert_eq!(encode_gba(Color::rgb(0, 0, 255)), 0x7C00);
+        assert_eq!(encode_gba(Color::rgb(300, -4, 0)), 0x001F);
+        assert_eq!(decode_gba(0x03E0), Color::rgb(0, 255, 0));
+        assert_eq!(decode_gba(0x0421), Color::rgb(8, 8, 8));
+
+        assert_eq!(gba_to_le_bytes(0x7C1F), [0x1F, 0x7C]);
+        assert_eq!(gba_from_le_bytes([0x1F, 0x7C]), 0x7C1F);
+        for value in 0..=GBA_COLOR_MASK {
+            assert_eq!(gba_from_le_bytes(gba_to_le_bytes(value)), value);
+        }
+    }
+
+    #[test]
+    fn ciede2000_matches_the_published_test_data() {
+        // pairs 1, 7, 17 and 25 from Sharma, Wu and Dalal's CIEDE2000 test data
+        let pairs = [
+            ((50.0, 2.6772, -79.7751), (50.0, 0.0, -82.7485), 2.0425),
+            ((50.0, 0.0, 0.0), (50.0, -1.0, 2.0), 2.3669),
+            ((50.0, 2.5, 0.0), (73.0, 25.0, -18.0), 27.1492),
+            ((60.2574, -34.0099, 36.2677), (60.4626, -34.1751, 39.4387), 1.2644),
+        ];
+        for &(lab1, lab2, expected) in pairs.iter() {
+            assert!((ciede2000(lab1, lab2) - expected).abs() < 1e-4, "{:?} {:?} gave {}", lab1, lab2, ciede2000(lab1, lab2));
+            assert!((ciede2000(lab2, lab1) - expected).abs() < 1e-4);
+        }
+
+        let (black, white) = (Color::rgb(0, 0, 0), Color::rgb(255, 255, 255));
+        assert_eq!(metric_distance(black, white, ColorMetric::EuclideanRgb), (3.0f64 * 255.0 * 255.0).sqrt());
+        assert!((metric_distance(black, white, ColorMetric::Ciede2000) - 100.0).abs() < 0.01);
+        assert_eq!(metric_distance(white, white, ColorMetric::Ciede2000), 0.0);
+    }
+
+    #[test]
+    fn mid_gray_keeps_its_brightness_through_the_srgb_path() {
+        let gray = Color::rgb(0x80, 0x80, 0x80);
+        // the naive path scales the 8 bit value, the sRGB path goes through linear light
+        // and back out through the screen's gamma, which lands a step darker
+        assert_eq!(encode_gba(gray) & 0x1F, 16);
+        let channel = rgb_to_gba_srgb(gray) & 0x1F;
+        assert!((15..=16).contains(&channel), "mid gray became {}", channel);
+        assert_eq!(rgb_to_gba_srgb(gray), channel * 0x421);
+        assert_eq!(gba_to_rgb_srgb(15 * 0x421), Color::rgb(124, 124, 124));
+
+        // 103 is just past halfway from level 12 to 13 in gamma space, but nearer 12 in linear light
+        let light = srgb_to_linear(103);
+        assert_eq!((light.powf(1.0 / GBA_GAMMA) * 31.0).round(), 13.0);
+        assert!(light - gba_level_light(12) < gba_level_light(13) - light);
+        assert_eq!(rgb_to_gba_srgb(Color::rgb(103, 0, 0)), 12);
+    }
+
+    #[test]
+    fn the_srgb_path_keeps_the_ends_and_round_trips() {
+        assert_eq!(rgb_to_gba_srgb(Color::rgb(0, 0, 0)), 0);
+        assert_eq!(rgb_to_gba_srgb(Color::rgb(255, 255, 255)), 0x7FFF);
+        for channel in 0..32 {
+            assert_eq!(rgb_to_gba_srgb(gba_to_rgb_srgb(channel)), channel);
+        }
+    }
+
+    #[test]
+    fn channel_math_saturates_at_both_ends() {
+        let color = Color::rgba(250, 10, 128, 77);
+        assert_eq!(color.saturating_add_rgb(10, -20, 1), Color::rgba(255, 0, 129, 77));
+        assert_eq!(color.saturating_add_rgb(i16::MIN, i16::MAX, 0), Color::rgba(0, 255, 128, 77));
+        assert_eq!(color.scale(2.0), Color::rgba(255, 20, 255, 77));
+        assert_eq!(color.scale(0.5), Color::rgba(125, 5, 64, 77));
+        assert_eq!(color.scale(-1.0), Color::rgba(0, 0, 0, 77));
+        assert_eq!(Color::rgb(-5, 300, 40).clamp(), Color::rgb(0, 255, 40));
+    }
+
+    #[test]
+    fn equality_ignores_alpha_unless_asked() {
+        use std::collections::HashSet;
+        let opaque = Color::rgb(10, 20, 30);
+        let clear = Color::rgba(10, 20, 30, 0);
+        assert_eq!(opaque, clear);
+        assert!(!opaque.eq_rgba(&clear));
+        assert!(clear.eq_rgba(&Color::rgba(10, 20, 30, 0)));
+        assert!(opaque != Color::rgb(10, 20, 31));
+
+        let set: HashSet<Color> = [opaque, clear, Color::rgb(0, 0, 0)].iter().cloned().collect();
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn gba_encoding_drops_alpha() {
+        assert_eq!(encode_gba(Color::rgba(255, 0, 0, 0)), encode_gba(Color::rgb(255, 0, 0)));
+        let mut cache = GBAColorCache::new();
+        assert_eq!(cache.rgb_to_gba(Color::rgba(0, 0, 255, 7)), 0x7C00);
+        assert_eq!(cache.gba_to_rgb(0x7C00).a, 0xFF);
+    }
+
+    /// a quantized image's worth of colors, with the short runs of repeats pixels tend to have
+    fn image_colors() -> Vec<Color> {
+        (0..4096u32).map(|i| {
+            let i = i / 3;
+            Color::rgb((i * 7 % 256) as i32, (i * 13 % 256) as i32, (i * 29 % 256) as i32)
+        }).collect()
+    }
+
+    #[test]
+    fn slice_conversion_matches_element_wise_conversion() {
+        let colors = image_colors();
+        let values: Vec<i32> = colors.iter().map(|&color| encode_gba(color)).collect();
+
+        let mut cache = GBAColorCache::default();
+        assert_eq!(cache.rgb_to_gba_slice(&colors), values);
+        let decoded = cache.gba_to_rgb_slice(&values);
+        assert_eq!(decoded, values.iter().map(|&value| decode_gba(value)).collect::<Vec<_>>());
+        assert!(decoded.iter().zip(values.iter()).all(|(color, &value)| color.eq_rgba(&decode_gba(value))));
+        assert!(cache.gba_to_rgb_slice(&[]).is_empty());
+    }
+
+    #[test]
+    fn gba_colors_only_hold_15_bits() {
+        assert_eq!(GbaColor::new(0x7FFF).unwrap().value(), 0x7FFF);
+        assert!(GbaColor::new(0x8000).is_err());
+        assert!(GbaColor::from_i32(-1).is_err());
+        assert!(GbaColor::from_i32(0x10000).is_err());
+        assert_eq!(GbaColor::masked(0xFC1F).value(), 0x7C1F);
+
+        let color = GbaColor::new(0x7C1F).unwrap();
+        assert_eq!(color.to_le_bytes(), [0x1F, 0x7C]);
+        assert_eq!(GbaColor::from_le_bytes([0x1F, 0x7C]), color);
+        assert_eq!(GbaColor::from_le_bytes([0x1F, 0xFC]), color);
+        assert_eq!(GbaColor::from(Color::from(color)), color);
+    }
 }
@@ -0,0 +1,238 @@
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::io::{SeekFrom, Seek, Read, Error};
+
+/// first byte of data compressed with the GBA BIOS LZ77 routine
+pub const LZ77_MARKER: u8 = 0x10;
+
+/// shortest back-reference a LZ77 block can encode
+const MIN_MATCH: usize = 3;
+
+/// longest back-reference a LZ77 block can encode
+const MAX_MATCH: usize = 0x0F + MIN_MATCH;
+
+/// how far back a LZ77 block can reference
+const WINDOW_SIZE: usize = 0x1000;
+
+/// largest size the 24 bit header can hold
+pub const LZ77_MAX_SIZE: usize = 0xFFFFFF;
+
+/// largest size `is_lz77` believes, the 256 KiB of work RAM is the biggest place data is decompressed to
+const LZ77_PLAUSIBLE_SIZE: usize = 0x40000;
+
+#[derive(Debug, PartialEq)]
+pub enum CompressionError {
+    /// the data doesn't start with the LZ77 marker
+    InvalidMarker(u8),
+    /// the data ended before the decompressed size was reached
+    UnexpectedEnd,
+    /// a back-reference points before the start of the output
+    CorruptStream,
+}
+
+impl fmt::Display for CompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompressionError::InvalidMarker(marker) => write!(f, "expected LZ77 marker 0x10, found {:#04X}", marker),
+            CompressionError::UnexpectedEnd => write!(f, "compressed data ended early"),
+            CompressionError::CorruptStream => write!(f, "back-reference outside of the decompressed data"),
+        }
+    }
+}
+
+impl error::Error for CompressionError {}
+
+/// whether the data at `offset` looks like LZ77 compressed data: the marker followed by a size
+/// that could fit in memory
+pub fn is_lz77(file: &mut (impl Read + Seek), offset: u64) -> Result<bool, Error> {
+    let mut header = [0u8; 4];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut header)?;
+
+    let size = header[1] as usize | (header[2] as usize) << 8 | (header[3] as usize) << 16;
+    Ok(header[0] == LZ77_MARKER && size > 0 && size <= LZ77_PLAUSIBLE_SIZE)
+}
+
+/// decompress data in the GBA BIOS LZ77 format (type 0x10)
+pub fn lz77_decompress(input: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    if input.len() < 4 {
+        return Err(CompressionError::UnexpectedEnd);
+    }
+    if input[0] != LZ77_MARKER {
+        return Err(CompressionError::InvalidMarker(input[0]));
+    }
+
+    // 24 bit little endian decompressed size
+    let size = input[1] as usize | (input[2] as usize) << 8 | (input[3] as usize) << 16;
+    // the size comes from the data, so don't trust it with more than `is_lz77` would
+    let mut output = Vec::with_capacity(cmp::min(size, LZ77_PLAUSIBLE_SIZE));
+    let mut bytes = input[4..].iter();
+
+    while output.len() < size {
+        let flags = *bytes.next().ok_or(CompressionError::UnexpectedEnd)?;
+
+        // each flag bit describes one block, starting from the highest bit
+        for bit in 0..8 {
+            if output.len() >= size {
+                break;
+            }
+
+            if flags & (0x80 >> bit) == 0 {
+                let byte = *bytes.next().ok_or(CompressionError::UnexpectedEnd)?;
+                output.push(byte);
+            } else {
+                let first = *bytes.next().ok_or(CompressionError::UnexpectedEnd)? as usize;
+                let second = *bytes.next().ok_or(CompressionError::UnexpectedEnd)? as usize;
+
+                let length = (first >> 4) + MIN_MATCH;
+                let distance = ((first & 0x0F) << 8 | second) + 1;
+                if distance > output.len() {
+                    return Err(CompressionError::CorruptStream);
+                }
+
+                // the reference can overlap what it's writing, so copy one byte at a time
+                let start = output.len() - distance;
+                for i in 0..length {
+                    let byte = output[start + i];
+                    output.push(byte);
+                }
+            }
+        }
+    }
+
+    output.truncate(size);
+    Ok(output)
+}
+
+/// compress data in the GBA BIOS LZ77 format (type 0x10), padded to a multiple of 4 bytes
+///
+/// panics if the input is larger than `LZ77_MAX_SIZE`
+pub fn lz77_compress(input: &[u8]) -> Vec<u8> {
+    assert!(input.len() <= LZ77_MAX_SIZE, "input is too large for a LZ77 header");
+
+    let mut output = vec![LZ77_MARKER, input.len() as u8, (input.len() >> 8) as u8, (input.len() >> 16) as u8];
+    let mut position = 0;
+
+    while position < input.len() {
+        let flag_index = output.len();
+        output.push(0);
+
+        for bit in 0..8 {
+            if position >= input.len() {
+                break;
+            }
+
+            let (length, distance) = longest_match(input, position);
+            if length >= MIN_MATCH {
+                output[flag_index] |= 0x80 >> bit;
+                let encoded_distance = distance - 1;
+                output.push(((length - MIN_MATCH) << 4 | encoded_distance >> 8) as u8);
+                output.push(encoded_distance as u8);
+                position += length;
+            } else {
+                output.push(input[position]);
+                position += 1;
+            }
+        }
+    }
+
+    while output.len() % 4 != 0 {
+        output.push(0);
+    }
+    output
+}
+
+/// find the longest earlier run matching the data at `position`, as (length, distance)
+fn longest_match(input: &[u8], position: usize) -> (usize, usize) {
+    let max_length = cmp::min(MAX_MATCH, input.len() - position);
+    let window_start = position.saturating_sub(WINDOW_SIZE);
+    let mut best = (0, 0);
+
+    // a distance of 1 isn't safe when the BIOS decompresses 16 bits at a time into VRAM
+    for start in window_start..position.saturating_sub(1) {
+        let length = (0..max_length)
+            .take_while(|&i| input[start + i] == input[position + i])
+            .count();
+        if length > best.0 {
+            best = (length, position - start);
+            if length == max_length {
+                break;
+            }
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn literals_and_overlapping_references_decompress() {
+        // flags 0b0001_0000: three literals, then a reference 5 long and 2 back
+        let input = [LZ77_MARKER, 8, 0, 0, 0x10, b'a', b'b', b'c', 0x20, 0x01];
+        assert_eq!(lz77_decompress(&input).unwrap(), b"abcbcbcb".to_vec());
+        // a size that ends partway through a reference cuts it short
+        let input = [LZ77_MARKER, 5, 0, 0, 0x10, b'a', b'b', b'c', 0x20, 0x01];
+        assert_eq!(lz77_decompress(&input).unwrap(), b"abcbc".to_vec());
+        assert!(lz77_decompress(&[LZ77_MARKER, 0, 0, 0]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn broken_streams_are_errors() {
+        assert_eq!(lz77_decompress(&[0x11, 1, 0, 0, 0, 0]), Err(CompressionError::InvalidMarker(0x11)));
+        assert_eq!(lz77_decompress(&[LZ77_MARKER, 1]), Err(CompressionError::UnexpectedEnd));
+        assert_eq!(lz77_decompress(&[LZ77_MARKER, 4, 0, 0, 0, b'a']), Err(CompressionError::UnexpectedEnd));
+        assert_eq!(lz77_decompress(&[LZ77_MARKER, 4, 0, 0, 0x80, 0x00, 0x00]), Err(CompressionError::CorruptStream));
+    }
+
+    #[test]
+    fn compression_pads_to_4_bytes_and_shrinks_repeats() {
+        let input = vec![0x42u8; 1000];
+        let compressed = lz77_compress(&input);
+        assert_eq!(compressed.len() % 4, 0);
+        assert_eq!(&compressed[..4], &[LZ77_MARKER, 0xE8, 0x03, 0x00]);
+        assert!(compressed.len() < 200, "{} bytes", compressed.len());
+        assert_eq!(lz77_decompress(&compressed).unwrap(), input);
+
+        assert_eq!(lz77_compress(&[]), vec![LZ77_MARKER, 0, 0, 0]);
+        let text = b"sonic battle sonic battle sonic battle";
+        assert_eq!(lz77_decompress(&lz77_compress(text)).unwrap(), text.to_vec());
+    }
+
+    #[test]
+    fn compression_never_references_the_previous_byte() {
+        let compressed = lz77_compress(&[7u8; 64]);
+        let mut bytes = compressed[4..].iter();
+        let mut produced = 0;
+        while produced < 64 {
+            let flags = *bytes.next().unwrap();
+            for bit in 0..8 {
+                if produced >= 64 {
+                    break;
+                }
+                if flags & (0x80 >> bit) == 0 {
+                    bytes.next();
+                    produced += 1;
+                } else {
+                    let first = *bytes.next().unwrap() as usize;
+                    let second = *bytes.next().unwrap() as usize;
+                    assert!(((first & 0x0F) << 8 | second) + 1 > 1, "distance 1 at byte {}", produced);
+                    produced += (first >> 4) + MIN_MATCH;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn is_lz77_checks_the_marker_and_a_plausible_size() {
+        let mut file = Cursor::new(vec![0, 0, LZ77_MARKER, 0x00, 0x20, 0x00, LZ77_MARKER, 0, 0, 0x10]);
+        assert!(is_lz77(&mut file, 2).unwrap());
+        assert!(!is_lz77(&mut file, 0).unwrap());
+        // 1 MiB doesn't fit anywhere the game decompresses to
+        assert!(!is_lz77(&mut file, 6).unwrap());
+        assert!(is_lz77(&mut file, 8).is_err());
+    }
+}
@@ -6,13 +6,99 @@ pub const SONIC_MINE_PALETTE: i32 = 0xBF20D8;
 pub const TAILS_BLASTER_PALETTE: i32 = 0xBF2098;
 pub const SHIELD_PALETTE: i32 = 0xBF2078;
 
+/// a palette that isn't tied to one character
+#[derive(Copy, Clone)]
+pub struct NamedOffset {
+    /// the name the palette is stored under
+    pub name: &'static str,
+    /// file offset of the palette in the US release
+    pub offset: u64,
+    pub color_count: usize,
+}
+
+/// palettes shared by several characters or used by effects
+pub const COMMON_PALETTES: &[NamedOffset] = &[
+    NamedOffset { name: "Phi", offset: PHI_PALETTE as u64, color_count: 16 },
+    NamedOffset { name: "Dust Cloud", offset: DUST_CLOUD_PALETTE as u64, color_count: 16 },
+    NamedOffset { name: "Shield", offset: SHIELD_PALETTE as u64, color_count: 16 },
+    NamedOffset { name: "Tails Blaster", offset: TAILS_BLASTER_PALETTE as u64, color_count: 16 },
+    NamedOffset { name: "Sonic Mine", offset: SONIC_MINE_PALETTE as u64, color_count: 16 },
+];
+
+/// the releases of Sonic Battle, keyed off the last byte of the header game code
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Region {
+    Usa,
+    Europe,
+    Japan,
+}
+
+impl Region {
+    pub fn from_game_code(code: u8) -> Option<Region> {
+        match code {
+            b'E' => Some(Region::Usa),
+            b'P' => Some(Region::Europe),
+            b'J' => Some(Region::Japan),
+            _ => None,
+        }
+    }
+
+    /// whether every character's palette offset is known in this release
+    pub fn is_mapped(self) -> bool {
+        CHARACTERS.iter().all(|character| character.palette_offset_for(self).is_some())
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct Character {
+    /// the name the game uses, also the key the character's palette is stored under
     pub name: &'static str,
+    /// file offset of the palette in the US release, see `palette_offset_for` for other releases
     pub palette_offset: u64,
+    /// number of colors in the palette, 16 for 4bpp sprites or 256 for 8bpp
+    pub color_count: usize,
+    /// start and end of the character's story text, (-1, -1) if they have none
     pub text_offsets: (i32, i32),
+    /// file offset of the first sprite frame
     pub sprite_offset: i32,
+    /// number of frames in each animation, in order
     pub sprite_frames: &'static [i32],
+    /// where the ROM stores a pointer to the palette, `None` until it's been found. None of the
+    /// US pointers have been found yet, so no character can be repointed
+    pub pointer_location: Option<u32>,
+    /// file offsets of the character's other palettes in the US release, like special move
+    /// variants, in slot order from slot 1
+    pub extra_palette_offsets: &'static [u64],
+}
+
+impl Character {
+    /// where the ROM stores a pointer to the palette, `None` if it hasn't been found yet
+    pub fn pointer(&self) -> Option<u64> {
+        self.pointer_location.map(|location| location as u64)
+    }
+
+    /// number of palettes the character has, the main one in slot 0 and any extras after it
+    pub fn palette_slots(&self) -> usize {
+        1 + self.extra_palette_offsets.len()
+    }
+
+    /// the name a palette slot is stored under, the main palette keeps the character's name
+    /// and the others get `#slot` after it, like "Sonic#1"
+    pub fn slot_name(&self, slot: usize) -> String {
+        match slot {
+            0 => self.name.to_string(),
+            slot => format!("{}#{}", self.name, slot),
+        }
+    }
+
+    /// the palette offset in a specific release, `None` if it hasn't been mapped yet
+    pub fn palette_offset_for(&self, region: Region) -> Option<u64> {
+        // the offsets in this file come from the US release
+        match region {
+            Region::Usa => Some(self.palette_offset),
+            Region::Europe | Region::Japan => None,
+        }
+    }
 }
 
 pub const CHARACTERS: [Character; 10] = [
@@ -23,100 +109,157 @@ pub const CHARACTERS: [Character; 10] = [
 pub const SONIC_DATA: Character = Character {
     name: "Sonic",
     palette_offset: 0x47AFB8,
+    color_count: 16,
     text_offsets: (0x1DB3FC, 0x1E1467),
     sprite_offset: 0x47AFD8,
     sprite_frames: &[8, 4, 8, 4, 8, 4, 4, 4, 8, 4, 8, 8, 8, 8, 16, 12, 12, 8, 12, 8, 8, 16, 8, 12,
         8, 8, 4, 8, 4, 4, 8, 8, 4, 8, 4, 8, 4, 4],
+    pointer_location: None,
+    extra_palette_offsets: &[],
 };
 
 pub const KNUCKLES_DATA: Character = Character {
     name: "Knuckles",
     palette_offset: 0x4CADD8,
+    color_count: 16,
     text_offsets: (0x1ED2C4, 0x1F417F),
     sprite_offset: 0x4CADF8,
     sprite_frames: &[8, 4, 8, 4, 8, 4, 4, 4, 8, 4, 8, 8, 8, 12, 16, 12, 12, 8, 12, 8, 8, 8, 8, 8,
         12, 8, 8, 4, 8, 8, 12, 8, 4, 8, 4, 4, 8, 8, 4, 8, 4, 4, 8, 4, 4],
+    pointer_location: None,
+    extra_palette_offsets: &[],
 };
 
 pub const TAILS_DATA: Character = Character {
     name: "Tails",
     palette_offset: 0x5283F8,
+    color_count: 16,
     text_offsets: (0x1E146A, 0x1E6FA7),
     sprite_offset: 0x528418,
     sprite_frames: &[8, 4, 8, 4, 8, 4, 4, 4, 8, 4, 8, 8, 8, 8, 28, 12, 12, 8, 8, 8, 8, 20, 8, 20,
         16, 8, 8, 4, 8, 8, 8, 8, 4, 8, 8, 4, 8, 8, 8, 8, 8, 4, 4],
+    pointer_location: None,
+    extra_palette_offsets: &[],
 };
 
 pub const SHADOW_DATA: Character = Character {
     name: "Shadow",
     palette_offset: 0x58D818,
+    color_count: 16,
     text_offsets: (0x1FE870, 0x206103),
     sprite_offset: 0x58D838,
     sprite_frames: &[8, 4, 28, 12, 8, 4, 4, 8, 8, 8, 4, 8, 8, 8, 12, 24, 16, 20, 8, 4, 8, 12, 12,
         8, 24, 8, 12, 8, 4, 8, 4, 4, 8, 8, 12, 4, 4, 4, 4, 4],
+    pointer_location: None,
+    extra_palette_offsets: &[],
 };
 
 pub const ROUGE_DATA: Character = Character {
     name: "Rouge",
     palette_offset: 0x5F3E38,
+    color_count: 16,
     text_offsets: (0x1E6FA8, 0x1ED2C3),
     sprite_offset: 0x5F3E58,
     sprite_frames: &[8, 4, 8, 4, 8, 4, 8, 4, 8, 4, 8, 12, 16, 12, 8, 8, 12, 8, 4, 12, 8, 4, 8, 4,
         4, 8, 8, 12, 4, 4, 4, 4, 4],
+    pointer_location: None,
+    extra_palette_offsets: &[],
 };
 
 pub const AMY_DATA: Character = Character {
     name: "Amy",
     palette_offset: 0x636458,
+    color_count: 16,
     text_offsets: (0x1F4180, 0x1F9CDB),
     sprite_offset: 0x636478,
     sprite_frames: &[8, 4, 8, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 8, 8, 16, 16, 8, 8, 12, 12, 8, 8, 8,
         12, 8, 4, 8, 8, 8, 8, 8, 4, 4, 8, 4, 4],
+    pointer_location: None,
+    extra_palette_offsets: &[],
 };
 
 pub const E102_DATA: Character = Character {
     name: "E-102",
     palette_offset: 0x681A78,
+    color_count: 16,
     text_offsets: (-1, -1),
     sprite_offset: 0x681A98,
     sprite_frames: &[8, 4, 8, 4, 4, 4, 4, 4, 4, 8, 4, 8, 8, 8, 12, 16, 12, 12, 8, 12, 8, 8, 16,
         12, 12, 16, 12, 12, 28, 4, 4, 20, 40, 4, 8, 4, 4, 4, 4, 8, 4, 4, 8, 4, 8, 4, 4],
+    pointer_location: None,
+    extra_palette_offsets: &[],
 };
 
 pub const CREAM_DATA: Character = Character {
     name: "Cream",
     palette_offset: 0x6F6A98,
+    color_count: 16,
     text_offsets: (0x1F9CDC, 0x1FE86F),
     sprite_offset: 0x6F6AB8,
     sprite_frames: &[8, 4, 20, 4, 4, 8, 8, 12, 8, 8, 8, 16, 8, 12, 8, 16, 12, 4, 16, 12, 4, 8, 4,
         4],
+    pointer_location: None,
+    extra_palette_offsets: &[],
 };
 
 pub const CHAOS_DATA: Character = Character {
     name: "Chaos",
     palette_offset: 0x7336B8,
+    color_count: 16,
     text_offsets: (-1, -1),
     sprite_offset: 0x7336D8,
     sprite_frames: &[8, 4, 8, 8, 12, 4, 8, 8, 4, 8, 8, 12, 16, 16, 8, 8, 8, 8, 20, 8, 8, 12, 8, 4,
         8, 8, 8, 8, 8, 4, 4, 8, 4, 4],
+    pointer_location: None,
+    extra_palette_offsets: &[],
 };
 
 pub const EMERL_DATA: Character = Character {
     name: "Emerl",
     palette_offset: 0x47AB38,
+    color_count: 16,
     text_offsets: (0x206104, 0x20B131),
     sprite_offset: 0x787D18,
     sprite_frames: &[-1],
+    pointer_location: None,
+    extra_palette_offsets: &[],
 };
 
 pub const EGGMAN_DATA: Character = Character {
     name: "Eggman",
     palette_offset: 0x7822D8,
+    color_count: 16,
     text_offsets: (-1, -1),
     sprite_offset: 0x7822F8,
     sprite_frames: &[4, 4, 4, 4, 4],
+    pointer_location: None,
+    extra_palette_offsets: &[],
 };
 
+/// find a character by name, ignoring case
+pub fn character_by_name(name: &str) -> Option<&'static Character> {
+    let characters: &'static [Character] = &CHARACTERS;
+    characters.iter().find(|character| character.name.eq_ignore_ascii_case(name))
+}
+
+/// pairs of characters whose palettes live at the same offset, writing one changes both
+pub fn shared_palette_offsets() -> Vec<(&'static str, &'static str)> {
+    find_shared_palette_offsets(&CHARACTERS)
+}
+
+/// pairs of characters in `characters` whose palettes live at the same offset
+pub fn find_shared_palette_offsets(characters: &[Character]) -> Vec<(&'static str, &'static str)> {
+    let mut pairs = Vec::new();
+    for (i, a) in characters.iter().enumerate() {
+        for b in characters[i + 1..].iter() {
+            if a.palette_offset == b.palette_offset {
+                pairs.push((a.name, b.name));
+            }
+        }
+    }
+    pairs
+}
+
 pub fn compute_sprite_offsets(character: &Character) -> Vec<(i32, i32)> {
     let mut data = Vec::new();
     let mut o = 0;
@@ -126,3 +269,53 @@ pub fn compute_sprite_offsets(character: &Character) -> Vec<(i32, i32)> {
     }
     data
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn characters_are_found_by_name_in_any_case() {
+        assert_eq!(character_by_name("Sonic").map(|character| character.name), Some("Sonic"));
+        assert_eq!(character_by_name("e-102").map(|character| character.name), Some("E-102"));
+        assert_eq!(character_by_name("KNUCKLES").map(|character| character.palette_offset), Some(KNUCKLES_DATA.palette_offset));
+        assert!(character_by_name("Metal Sonic").is_none());
+        assert!(character_by_name("").is_none());
+    }
+
+    #[test]
+    fn regions_come_from_the_game_code() {
+        assert_eq!(Region::from_game_code(b'E'), Some(Region::Usa));
+        assert_eq!(Region::from_game_code(b'P'), Some(Region::Europe));
+        assert_eq!(Region::from_game_code(b'J'), Some(Region::Japan));
+        assert_eq!(Region::from_game_code(b'X'), None);
+    }
+
+    #[test]
+    fn only_the_us_release_is_mapped() {
+        assert!(Region::Usa.is_mapped());
+        assert!(!Region::Europe.is_mapped());
+        assert!(!Region::Japan.is_mapped());
+        assert_eq!(SONIC_DATA.palette_offset_for(Region::Usa), Some(SONIC_DATA.palette_offset));
+        assert_eq!(SONIC_DATA.palette_offset_for(Region::Europe), None);
+    }
+
+    #[test]
+    fn characters_sharing_an_offset_are_paired() {
+        let shadow = Character { palette_offset: SONIC_DATA.palette_offset, ..SHADOW_DATA };
+        let rouge = Character { palette_offset: SONIC_DATA.palette_offset, ..ROUGE_DATA };
+        let characters = [SONIC_DATA, TAILS_DATA, shadow, rouge];
+        assert_eq!(find_shared_palette_offsets(&characters), vec![("Sonic", "Shadow"), ("Sonic", "Rouge"), ("Shadow", "Rouge")]);
+        assert!(find_shared_palette_offsets(&[SONIC_DATA, TAILS_DATA]).is_empty());
+        // the shipped table has no overlaps
+        assert!(shared_palette_offsets().is_empty());
+    }
+
+    #[test]
+    fn pointers_are_only_reported_once_found() {
+        assert!(CHARACTERS.iter().all(|character| character.pointer().is_none()));
+        let found = Character { pointer_location: Some(0x0012_3456), ..SONIC_DATA };
+        assert_eq!(found.pointer(), Some(0x123456));
+        assert_eq!((found.name, found.palette_offset, found.color_count), ("Sonic", SONIC_DATA.palette_offset, 16));
+    }
+}
@@ -22,8 +22,9 @@ impl Engine {
         }
     }
 
-    pub fn start(&mut self) -> Result<(), Error> {
+    pub fn start(&mut self) -> Result<(), palette::PaletteError> {
         let engine_timer = Instant::now();
+        self.palette_manager.detect_region()?;
         self.palette_manager.read_palettes()?;
         println!("Palette ROM loading: {:?}", engine_timer.elapsed());
         self.sprite_manager.read_sprites()?;
@@ -0,0 +1,231 @@
+// Property tests throwing arbitrary input at everything that decodes ROM data or files, to make
+// sure corrupt input gives an error rather than a panic. The files under tests/seeds are inputs
+// that once found problems or cover an edge case, each is rerun as is and mutated
+
+use proptest::prelude::*;
+use proptest::collection::vec;
+
+use ::compression::{lz77_compress, lz77_decompress, LZ77_MARKER};
+use ::data::CHARACTERS;
+use ::manager::palette::{decode_png_swatch, PaletteFormat, PaletteManager};
+use ::patch::{apply_ips, apply_ips_strict, diff_to_ips};
+use ::tiles::{decode_tiles_4bpp, TILE_4BPP_BYTES};
+
+const LZ77_SEEDS: &[&[u8]] = &[
+    include_bytes!("../tests/seeds/lz77/overlap.bin"),
+    include_bytes!("../tests/seeds/lz77/reference_before_start.bin"),
+    include_bytes!("../tests/seeds/lz77/huge_size.bin"),
+    include_bytes!("../tests/seeds/lz77/truncated_reference.bin"),
+];
+
+const IPS_SEEDS: &[&[u8]] = &[
+    include_bytes!("../tests/seeds/ips/records.ips"),
+    include_bytes!("../tests/seeds/ips/past_the_end.ips"),
+    include_bytes!("../tests/seeds/ips/truncated_data.ips"),
+    include_bytes!("../tests/seeds/ips/truncated_run.ips"),
+];
+
+const FORMATS: &[PaletteFormat] = &[
+    PaletteFormat::Jasc, PaletteFormat::Gpl, PaletteFormat::Json, PaletteFormat::PngSwatch,
+    PaletteFormat::CArray, PaletteFormat::Aseprite,
+];
+
+/// one of `seeds` with some of its bytes replaced
+fn mutated(seeds: &'static [&'static [u8]]) -> impl Strategy<Value = Vec<u8>> {
+    (0..seeds.len(), vec((any::<prop::sample::Index>(), any::<u8>()), 0..4)).prop_map(move |(seed, changes)| {
+        let mut bytes = seeds[seed].to_vec();
+        for (index, byte) in changes {
+            let index = index.index(bytes.len());
+            bytes[index] = byte;
+        }
+        bytes
+    })
+}
+
+/// bytes that start like LZ77 data, so the decoder gets past the header
+fn lz77_like() -> impl Strategy<Value = Vec<u8>> {
+    (0u32..0x400, vec(any::<u8>(), 0..256)).prop_map(|(size, body)| {
+        let mut bytes = vec![LZ77_MARKER, size as u8, (size >> 8) as u8, (size >> 16) as u8];
+        bytes.extend(body);
+        bytes
+    })
+}
+
+/// bytes that start like an IPS patch
+fn ips_like() -> impl Strategy<Value = Vec<u8>> {
+    vec(any::<u8>(), 0..64).prop_map(|body| {
+        let mut bytes = b"PATCH".to_vec();
+        bytes.extend(body);
+        bytes
+    })
+}
+
+#[test]
+fn the_seeds_decode_without_panicking() {
+    assert_eq!(lz77_decompress(LZ77_SEEDS[0]).unwrap(), b"abababab".to_vec());
+    for seed in LZ77_SEEDS[1..].iter() {
+        assert!(lz77_decompress(seed).is_err());
+    }
+
+    let mut rom = vec![0u8; 16];
+    apply_ips(&mut rom, IPS_SEEDS[0]).unwrap();
+    assert_eq!(&rom[..11], &[0, 0, 0xAA, 0xBB, 0, 0, 0, 0, 0xCC, 0xCC, 0xCC]);
+    assert!(apply_ips_strict(&mut vec![0u8; 16], IPS_SEEDS[1]).is_err());
+    for seed in IPS_SEEDS[2..].iter() {
+        assert!(apply_ips(&mut vec![0u8; 16], seed).is_err());
+    }
+}
+
+proptest! {
+    #[test]
+    fn lz77_decompress_never_panics(bytes in prop_oneof![vec(any::<u8>(), 0..256), lz77_like(), mutated(LZ77_SEEDS)]) {
+        if let Ok(output) = lz77_decompress(&bytes) {
+            let size = bytes[1] as usize | (bytes[2] as usize) << 8 | (bytes[3] as usize) << 16;
+            prop_assert_eq!(output.len(), size);
+        }
+    }
+
+    #[test]
+    fn lz77_round_trips(input in vec(any::<u8>(), 0..2048)) {
+        prop_assert_eq!(lz77_decompress(&lz77_compress(&input)).unwrap(), input);
+    }
+
+    #[test]
+    fn ips_patches_never_panic(patch in prop_oneof![vec(any::<u8>(), 0..64), ips_like(), mutated(IPS_SEEDS)],
+                               rom in vec(any::<u8>(), 0..64)) {
+        let _ = apply_ips_strict(&mut rom.clone(), &patch);
+        let mut grown = rom.clone();
+        if apply_ips(&mut grown, &patch).is_ok() {
+            prop_assert!(grown.len() >= rom.len());
+        }
+    }
+
+    #[test]
+    fn ips_diffs_round_trip(original in vec(any::<u8>(), 0..512), modified in vec(any::<u8>(), 0..512)) {
+        let patch = diff_to_ips(&original, &modified).unwrap();
+        let mut rom = original.clone();
+        apply_ips(&mut rom, &patch).unwrap();
+        // IPS can't shrink a file, so a shorter `modified` keeps the original's tail
+        prop_assert_eq!(&rom[..modified.len()], &modified[..]);
+        prop_assert_eq!(rom.len(), original.len().max(modified.len()));
+    }
+
+    #[test]
+    fn tiles_decode_whole_tiles_only(bytes in vec(any::<u8>(), 0..200)) {
+        let tiles = decode_tiles_4bpp(&bytes);
+        prop_assert_eq!(tiles.is_ok(), bytes.len() % TILE_4BPP_BYTES == 0);
+        if let Ok(tiles) = tiles {
+            prop_assert_eq!(tiles.len(), bytes.len() / TILE_4BPP_BYTES);
+            prop_assert!(tiles.iter().all(|tile| tile.iter().all(|row| row.iter().all(|&index| index < 16))));
+        }
+    }
+
+    #[test]
+    fn palette_files_never_panic(bytes in vec(any::<u8>(), 0..256), format in 0..FORMATS.len()) {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        let _ = manager.import_as("Fuzz".to_string(), FORMATS[format], &mut &bytes[..]);
+        let _ = decode_png_swatch(&bytes);
+    }
+
+    // every character palette is past the end of these
+    #[test]
+    fn palette_reads_from_a_short_rom_fail_cleanly(length in 0usize..0x47AFB8) {
+        let mut manager = PaletteManager::from_bytes(vec![0u8; length]);
+        prop_assert!(manager.read_palettes().is_err());
+        prop_assert!(manager.read_palettes_bulk().is_err());
+        prop_assert!(manager.peek_palette(&CHARACTERS[0]).is_err());
+    }
+}
+
+#[cfg(feature = "memmap2")]
+mod mmap {
+    use super::*;
+    use std::cmp;
+    use std::fs;
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use ::manager::mmap::MmapRom;
+    use ::testing;
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Seek(SeekFrom),
+        Read(usize),
+        Write(Vec<u8>),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (0u64..96).prop_map(|offset| Op::Seek(SeekFrom::Start(offset))),
+            (-80i64..80).prop_map(|offset| Op::Seek(SeekFrom::Current(offset))),
+            (-80i64..16).prop_map(|offset| Op::Seek(SeekFrom::End(offset))),
+            (0usize..80).prop_map(Op::Read),
+            vec(any::<u8>(), 0..40).prop_map(Op::Write),
+        ]
+    }
+
+    /// a fixed size buffer like the mapping: reads and writes stop at the end, seeks can pass it
+    struct Model {
+        bytes: Vec<u8>,
+        position: u64,
+    }
+
+    impl Model {
+        fn start(&self) -> usize {
+            cmp::min(self.position, self.bytes.len() as u64) as usize
+        }
+
+        fn remaining(&self) -> usize {
+            self.bytes.len() - self.start()
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn mapped_roms_behave_like_a_fixed_size_buffer(ops in vec(op(), 0..32)) {
+            let path = testing::temp_path("fuzz.gba");
+            fs::write(&path, vec![0x55u8; 64]).unwrap();
+            let mut rom = MmapRom::open(&path).unwrap();
+            let mut model = Model { bytes: vec![0x55; 64], position: 0 };
+
+            for op in ops {
+                match op {
+                    Op::Seek(from) => {
+                        let expected = match from {
+                            SeekFrom::Start(offset) => Some(offset),
+                            SeekFrom::Current(offset) => (model.position as i64).checked_add(offset).filter(|&p| p >= 0).map(|p| p as u64),
+                            SeekFrom::End(offset) => (model.bytes.len() as i64).checked_add(offset).filter(|&p| p >= 0).map(|p| p as u64),
+                        };
+                        match expected {
+                            Some(position) => {
+                                prop_assert_eq!(rom.seek(from).unwrap(), position);
+                                model.position = position;
+                            }
+                            None => prop_assert!(rom.seek(from).is_err()),
+                        }
+                    }
+                    Op::Read(length) => {
+                        let mut buf = vec![0u8; length];
+                        let read = rom.read(&mut buf).unwrap();
+                        let expected = cmp::min(length, model.remaining());
+                        prop_assert_eq!(read, expected);
+                        let start = model.start();
+                        prop_assert_eq!(&buf[..read], &model.bytes[start..start + read]);
+                        model.position += read as u64;
+                    }
+                    Op::Write(bytes) => {
+                        let written = rom.write(&bytes).unwrap();
+                        let expected = cmp::min(bytes.len(), model.remaining());
+                        prop_assert_eq!(written, expected);
+                        let start = model.start();
+                        model.bytes[start..start + written].copy_from_slice(&bytes[..written]);
+                        model.position += written as u64;
+                    }
+                }
+            }
+            rom.flush().unwrap();
+            drop(rom);
+            prop_assert_eq!(fs::read(&path).unwrap(), model.bytes);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+}
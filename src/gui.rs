@@ -129,13 +129,17 @@ pub fn gui(display: &glium::Display, image_map: &mut conrod::image::Map<glium::t
                     let file_result = OpenOptions::new()
                         .read(true)
                         .write(true)
-                        .open(file_name);
+                        .open(&file_name);
                     match file_result {
                         Ok(file) => {
                             let mut engine = engine::Engine::new(Arc::new(Mutex::new(file)));
-                            if let Ok(_) = engine.start() {
-                                app.engine = Some(engine);
-                                app.selected_character_index = None;
+                            engine.palette_manager.set_auto_backup(Some(PathBuf::from(format!("{}.bak", file_name))));
+                            match engine.start() {
+                                Ok(()) => {
+                                    app.engine = Some(engine);
+                                    app.selected_character_index = None;
+                                }
+                                Err(error) => println!("Error occurred while reading the ROM: {}", error),
                             }
                         }
                         Err(error) => {
@@ -172,7 +176,7 @@ pub fn gui(display: &glium::Display, image_map: &mut conrod::image::Map<glium::t
             let o_image = (if let Some(ref mut engine) = app.engine {
                 println!("Loading character data");
                 let character = CHARACTERS[selected_index];
-                let palette = engine.palette_manager.load_palette_colors(character.name.to_string());
+                let palette = engine.palette_manager.load_palette_colors(character.name.to_string()).unwrap();
                 Some(engine.sprite_manager.load_spritesheet(&character).unwrap().to_img(&palette[..]))
             } else {
                 None
@@ -237,7 +241,7 @@ pub fn gui(display: &glium::Display, image_map: &mut conrod::image::Map<glium::t
             println!("Save Spritesheet to File");
             if let Some(character) = app.get_character() {
                 if let Some(ref mut engine) = app.engine {
-                    let palette = engine.palette_manager.load_palette_colors(character.name.to_string());
+                    let palette = engine.palette_manager.load_palette_colors(character.name.to_string()).unwrap();
                     let image = { engine.sprite_manager.load_spritesheet(&character).unwrap().to_img(&palette[..]) };
 
                     let result = nfd::dialog_save().filter("png").open().unwrap_or_else(|e| {
@@ -282,7 +286,7 @@ pub fn gui(display: &glium::Display, image_map: &mut conrod::image::Map<glium::t
                     println!("Reading {} sprites & palette from ROM ({:?})", character.name, timer.elapsed());
 
                     timer = Instant::now();
-                    let palette = engine.palette_manager.load_palette_colors(character.name.to_string());
+                    let palette = engine.palette_manager.load_palette_colors(character.name.to_string()).unwrap();
                     let image = { engine.sprite_manager.load_spritesheet(&character).unwrap().to_img(&palette[..]) };
                     println!("Converting {} spritesheet to an image ({:?})", character.name, timer.elapsed());
                     Some(image)
@@ -5,6 +5,19 @@ extern crate lazy_static;
 #[macro_use]
 extern crate conrod;
 extern crate image;
+extern crate gif;
+extern crate clap;
+extern crate serde;
+extern crate serde_json;
+#[cfg(feature = "log")]
+#[macro_use]
+extern crate log;
+#[cfg(feature = "zip")]
+extern crate zip;
+#[cfg(feature = "memmap2")]
+extern crate memmap2;
+#[cfg(test)]
+extern crate proptest;
 
 use conrod::backend::glium::glium;
 use conrod::backend::glium::glium::Surface;
@@ -14,22 +27,44 @@ use std::fs::{File, OpenOptions, create_dir_all};
 use std::io::Read;
 use std::error::Error;
 use std::env;
+use std::process;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::rc::Rc;
 use std::time::Instant;
 use self::image::{open, ImageBuffer, Rgb, DynamicImage, ImageRgb8, ImageRgba8, ConvertBuffer};
 
+#[macro_use]
+mod trace;
 mod gui;
+mod cli;
 mod data;
 mod color;
 mod engine;
 mod manager;
+mod compression;
+mod rom;
+mod random;
+mod patch;
+mod tiles;
+mod presets;
+#[cfg(test)]
+mod testing;
+#[cfg(test)]
+mod fuzz;
 
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 const WINDOW_WIDTH: u32 = gui::WINDOW_WIDTH;
 const WINDOW_HEIGHT: u32 = gui::WINDOW_HEIGHT;
 
 pub fn main() {
+    // a subcommand or flag runs the command line, a ROM path on its own opens the GUI
+    if let Some(first) = env::args().nth(1) {
+        if first.starts_with('-') || cli::SUBCOMMANDS.contains(&first.as_str()) {
+            process::exit(cli::run(env::args().collect()));
+        }
+    }
+
     let mut events_loop = glium::glutin::EventsLoop::new();
 
     let window = glium::glutin::WindowBuilder::new()
@@ -57,14 +92,17 @@ pub fn main() {
         let file_result = OpenOptions::new()
             .read(true)
             .write(true)
-            .open(file_name);
+            .open(&file_name);
         match file_result {
             Ok(file) => {
                 let mut engine = engine::Engine::new(Arc::new(Mutex::new(file)));
-                if let Ok(_) = engine.start() {
-                    Some(engine)
-                } else {
-                    None
+                engine.palette_manager.set_auto_backup(Some(PathBuf::from(format!("{}.bak", file_name))));
+                match engine.start() {
+                    Ok(()) => Some(engine),
+                    Err(error) => {
+                        println!("Error occurred while reading the ROM: {}", error);
+                        None
+                    }
                 }
             }
             Err(error) => {
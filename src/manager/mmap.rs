@@ -0,0 +1,170 @@
+use std::cmp;
+use std::fs::OpenOptions;
+use std::io::{self, SeekFrom, Seek, Read, Error, ErrorKind, Write};
+use std::path::Path;
+use memmap2::MmapMut;
+
+/// a ROM file mapped into memory, read and written like a file
+///
+/// The mapping is only valid while the file keeps its size, so nothing else may truncate or
+/// grow the ROM while it's open. Writes can't go past the end of the file either, they stop
+/// short and `write_all` reports `WriteZero`.
+pub struct MmapRom {
+    map: MmapMut,
+    position: u64,
+}
+
+impl MmapRom {
+    /// map the ROM at `path` for reading and writing
+    pub fn open(path: &Path) -> Result<MmapRom, Error> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        // the caller is trusted not to resize the file while it's mapped, see above
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        Ok(MmapRom { map, position: 0 })
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// the position as an index into the mapping, held at the end once it's seeked past it
+    fn start(&self) -> usize {
+        cmp::min(self.position, self.map.len() as u64) as usize
+    }
+}
+
+impl Read for MmapRom {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = self.start();
+        let length = cmp::min(buf.len(), self.map.len() - start);
+        buf[..length].copy_from_slice(&self.map[start..start + length]);
+        self.position += length as u64;
+        Ok(length)
+    }
+}
+
+impl Write for MmapRom {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let start = self.start();
+        let length = cmp::min(buf.len(), self.map.len() - start);
+        self.map[start..start + length].copy_from_slice(&buf[..length]);
+        self.position += length as u64;
+        Ok(length)
+    }
+
+    /// push the written pages out to the file
+    fn flush(&mut self) -> io::Result<()> {
+        self.map.flush()
+    }
+}
+
+impl Seek for MmapRom {
+    fn seek(&mut self, from: SeekFrom) -> io::Result<u64> {
+        let position = match from {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => offset_by(self.map.len() as u64, offset),
+            SeekFrom::Current(offset) => offset_by(self.position, offset),
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(Error::new(ErrorKind::InvalidInput, "seek before the start of the ROM")),
+        }
+    }
+}
+
+fn offset_by(base: u64, offset: i64) -> Option<u64> {
+    if offset < 0 {
+        base.checked_sub(offset.wrapping_neg() as u64)
+    } else {
+        base.checked_add(offset as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use ::data::SONIC_DATA;
+    use ::manager::palette::PaletteManager;
+    use ::testing;
+
+    fn temp_rom(name: &str, len: usize) -> PathBuf {
+        let path = testing::temp_path(name);
+        fs::write(&path, (0..len).map(|i| i as u8).collect::<Vec<u8>>()).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_and_writes_stop_at_the_end() {
+        let path = temp_rom("end", 16);
+        let mut rom = MmapRom::open(&path).unwrap();
+        assert_eq!(rom.len(), 16);
+        assert!(!rom.is_empty());
+
+        rom.seek(SeekFrom::Start(12)).unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(rom.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], &[12, 13, 14, 15]);
+        assert_eq!(rom.write(&[1, 2]).unwrap(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn seeking_past_the_end_reads_and_writes_nothing() {
+        let path = temp_rom("past", 16);
+        let mut rom = MmapRom::open(&path).unwrap();
+
+        assert_eq!(rom.seek(SeekFrom::Start(rom.len() as u64 + 1)).unwrap(), 17);
+        let mut buf = [0xAA; 4];
+        assert_eq!(rom.read(&mut buf).unwrap(), 0);
+        assert_eq!(buf, [0xAA; 4]);
+        assert_eq!(rom.write(&[1, 2, 3]).unwrap(), 0);
+        assert_eq!(rom.write_all(&[1]).unwrap_err().kind(), ErrorKind::WriteZero);
+
+        rom.seek(SeekFrom::End(1)).unwrap();
+        assert_eq!(rom.read(&mut buf).unwrap(), 0);
+        drop(rom);
+        assert_eq!(fs::read(&path).unwrap(), (0..16).collect::<Vec<u8>>());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn seeking_before_the_start_fails() {
+        let path = temp_rom("before", 16);
+        let mut rom = MmapRom::open(&path).unwrap();
+        rom.seek(SeekFrom::Start(4)).unwrap();
+        assert_eq!(rom.seek(SeekFrom::Current(-5)).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(rom.seek(SeekFrom::End(-16)).unwrap(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reads_the_same_palette_as_a_file() {
+        let path = testing::temp_path("mmap.gba");
+        let mut bytes = testing::blank_rom();
+        let offset = SONIC_DATA.palette_offset as usize;
+        for (i, byte) in bytes[offset..offset + 32].iter_mut().enumerate() {
+            *byte = (i * 7) as u8 & 0x7F;
+        }
+        fs::write(&path, &bytes).unwrap();
+
+        let mut mapped = PaletteManager::open_mmap(&path).unwrap();
+        mapped.read_palette(&SONIC_DATA).unwrap();
+        let mut file = PaletteManager::new(Arc::new(Mutex::new(File::open(&path).unwrap())));
+        file.read_palette(&SONIC_DATA).unwrap();
+
+        let colors = mapped.load_palette_i32("Sonic".to_string()).unwrap();
+        assert_eq!(colors, file.load_palette_i32("Sonic".to_string()).unwrap());
+        assert_eq!(colors[1], 0x150E);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -4,3 +4,5 @@
 
 pub mod palette;
 pub mod sprite;
+#[cfg(feature = "memmap2")]
+pub mod mmap;
@@ -1,95 +1,4612 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{SeekFrom, Seek, Read, Error, Write};
+use std::{cmp, iter, slice};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::error;
+use std::fmt;
+use std::fs::{File, OpenOptions, self};
+use std::mem;
+use std::io::{SeekFrom, Seek, Read, Error, ErrorKind, Write, Cursor};
+use std::path::{Path, PathBuf, Component};
 use std::result::Result;
 use std::sync::{Arc, Mutex};
+use std::ops::Index;
+use std::thread;
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+#[cfg(windows)]
+use std::os::windows::fs::FileExt;
+use serde_json;
+use image::{ImageBuffer, Rgba, RGBA};
+use image::{self, ImageFormat};
+use image::png::PNGEncoder;
+use gif::{self, SetParameter};
 
 use ::data::*;
 use ::color::*;
 use ::engine::*;
+use ::rom::{self, PointerError, RomError};
+use ::random::{self, SplitMix64};
+use ::tiles::{self, TILE_SIZE, TILE_4BPP_BYTES};
+use ::manager::sprite::FRAME_SIZE;
+use ::presets;
+#[cfg(feature = "zip")]
+use zip::{self, ZipArchive, ZipWriter};
+#[cfg(feature = "zip")]
+use zip::write::FileOptions;
+#[cfg(feature = "memmap2")]
+use ::manager::mmap::MmapRom;
 
-pub struct PaletteManager {
-    file: Arc<Mutex<File>>,
-    color_cache: GBAColorCache,
+/// number of colors in a character palette
+pub const PALETTE_SIZE: usize = 16;
+
+/// no palette starts inside the cartridge header, an offset below this is a character that
+/// was never filled in, and writing there would break the ROM
+pub const MIN_PALETTE_OFFSET: u64 = 0xC0;
+
+/// largest span of the ROM `read_palettes_bulk` reads in one go, enough for the US table
+/// which runs from 0x47AFB8 to 0x7822F8
+pub const BULK_READ_LIMIT: u64 = 4 << 20;
+
+/// size of each color square in `export_montage_png` and `export_all` swatches
+pub const MONTAGE_SWATCH_SIZE: u32 = 16;
+
+/// number of edits kept for undo unless configured otherwise
+pub const DEFAULT_HISTORY_LIMIT: usize = 100;
+
+/// what `sorted_colors` orders a palette by
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    /// hue angle, grays first
+    Hue,
+    /// CIELAB lightness, darkest first
+    Luminance,
+    /// the BGR555 value
+    RawValue,
+}
+
+/// the order the two bytes of each color are written in by `export_raw`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// low byte first, the way the ROM stores colors
+    Little,
+    Big,
+}
+
+/// the file formats palettes can be exported to and imported from in bulk
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PaletteFormat {
+    Jasc,
+    Gpl,
+    Json,
+    PngSwatch,
+    CArray,
+    Aseprite,
+}
+
+impl PaletteFormat {
+    /// the file extension used for the format, without the dot
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            PaletteFormat::Jasc => "pal",
+            PaletteFormat::Gpl => "gpl",
+            PaletteFormat::Json => "json",
+            PaletteFormat::PngSwatch => "png",
+            PaletteFormat::CArray => "c",
+            PaletteFormat::Aseprite => "aseprite",
+        }
+    }
+
+    /// the format a file extension belongs to, ignoring case
+    pub fn from_extension(extension: &str) -> Option<PaletteFormat> {
+        [PaletteFormat::Jasc, PaletteFormat::Gpl, PaletteFormat::Json, PaletteFormat::PngSwatch, PaletteFormat::CArray,
+            PaletteFormat::Aseprite]
+            .iter()
+            .cloned()
+            .find(|format| format.extension().eq_ignore_ascii_case(extension))
+    }
+}
+
+/// a borrowed view of a stored palette that decodes colors as they're read, without copying the palette
+#[derive(Copy, Clone)]
+pub struct PaletteRef<'a> {
+    values: &'a [i32],
+}
+
+impl<'a> PaletteRef<'a> {
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<Color> {
+        self.values.get(index).map(|&value| decode_gba(value))
+    }
+
+    /// the raw BGR555 values
+    pub fn values(&self) -> &'a [i32] {
+        self.values
+    }
+
+    pub fn iter(&self) -> iter::Map<slice::Iter<'a, i32>, fn(&i32) -> Color> {
+        fn decode(value: &i32) -> Color {
+            decode_gba(*value)
+        }
+        self.values.iter().map(decode as fn(&i32) -> Color)
+    }
+}
+
+impl<'a> Index<usize> for PaletteRef<'a> {
+    type Output = Color;
+
+    fn index(&self, index: usize) -> &Color {
+        decoded_gba_ref(self.values[index])
+    }
+}
+
+impl<'a> IntoIterator for PaletteRef<'a> {
+    type Item = Color;
+    type IntoIter = iter::Map<slice::Iter<'a, i32>, fn(&i32) -> Color>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// the state of a palette before an edit, its colors and the name it was stored under,
+/// `None` if it didn't exist yet
+type Snapshot = (String, Option<Vec<i32>>, Option<String>);
+
+/// a file `import_all_lenient` skipped and why
+pub type ImportFailure = (PathBuf, PaletteError);
+
+#[derive(Debug)]
+pub enum PaletteError {
+    /// no palette is stored under the given name
+    PaletteNotFound(String),
+    /// a palette is already stored under the given name
+    PaletteExists(String),
+    /// the color index is past the end of the palette
+    IndexOutOfBounds { name: String, index: usize, len: usize },
+    /// an imported palette has fewer colors than a character palette needs
+    PaletteTooShort { expected: usize, found: usize },
+    /// a palette doesn't have the number of colors the operation needs
+    WrongLength { expected: usize, actual: usize },
+    /// an imported palette file couldn't be parsed
+    Malformed(String),
+    NothingToUndo,
+    NothingToRedo,
+    /// an io error, with the palette and ROM offset it happened at when it was reading or writing one
+    Io { source: Error, at: Option<(String, u64)> },
+    Json(serde_json::Error),
+    Rom(RomError),
+    /// a palette pointer in the ROM doesn't point into the cartridge
+    Pointer(PointerError),
+    /// the character has no known palette pointer to rewrite
+    NoPointer(String),
+    /// the palette offsets of this release haven't been mapped
+    UnsupportedRegion(Region),
+    /// a color uses bits outside the 15 the GBA reads
+    InvalidGbaColor { index: usize, value: i32 },
+    /// reading a palette back after writing it gave different bytes
+    VerifyFailed { character: String, offset: u64 },
+    /// some files in a bulk import couldn't be imported, the rest were stored
+    ImportFailed(Vec<ImportFailure>),
+    /// no bundled preset has the given name
+    UnknownPreset { name: String, available: Vec<&'static str> },
+    /// a ROM archive couldn't be read or written
+    Archive(String),
+    /// the pixel data doesn't match the image dimensions
+    ImageSize { expected: usize, actual: usize },
+    /// the frames of an animation have different numbers of colors
+    FrameLength { name: String, expected: usize, found: usize },
+    /// a palette offset is below `MIN_PALETTE_OFFSET`
+    OffsetInHeader { character: String, offset: u64 },
+    /// a character's palette isn't the 16 or 256 colors a GBA palette holds
+    BadColorCount { character: String, count: usize },
+    /// the character's sprite frame counts aren't known, `-1` in its frame table
+    UnknownSpriteFrames(String),
+    /// a palette name isn't a plain file name, so exporting it would write outside the directory
+    UnsafeFileName(String),
+}
+
+impl PaletteError {
+    /// say which palette an io error was for, any other error is left alone
+    fn at(self, character: &str, offset: u64) -> PaletteError {
+        match self {
+            PaletteError::Io { source, .. } => PaletteError::Io { source, at: Some((character.to_string(), offset)) },
+            error => error,
+        }
+    }
+}
+
+impl From<Error> for PaletteError {
+    fn from(error: Error) -> PaletteError {
+        PaletteError::Io { source: error, at: None }
+    }
+}
+
+impl From<RomError> for PaletteError {
+    fn from(error: RomError) -> PaletteError {
+        PaletteError::Rom(error)
+    }
+}
+
+impl From<PointerError> for PaletteError {
+    fn from(error: PointerError) -> PaletteError {
+        match error {
+            PointerError::Io(error) => PaletteError::from(error),
+            error => PaletteError::Pointer(error),
+        }
+    }
+}
+
+impl From<serde_json::Error> for PaletteError {
+    fn from(error: serde_json::Error) -> PaletteError {
+        PaletteError::Json(error)
+    }
+}
+
+#[cfg(feature = "zip")]
+impl From<zip::result::ZipError> for PaletteError {
+    fn from(error: zip::result::ZipError) -> PaletteError {
+        match error {
+            zip::result::ZipError::Io(error) => PaletteError::from(error),
+            error => PaletteError::Archive(error.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PaletteError::PaletteNotFound(ref name) => write!(f, "no palette stored for {}", name),
+            PaletteError::PaletteExists(ref name) => write!(f, "a palette is already stored for {}", name),
+            PaletteError::IndexOutOfBounds { ref name, index, len } =>
+                write!(f, "index {} is out of bounds for {} ({} colors)", index, name, len),
+            PaletteError::PaletteTooShort { expected, found } =>
+                write!(f, "palette has {} colors, expected at least {}", found, expected),
+            PaletteError::WrongLength { expected, actual } =>
+                write!(f, "palette has {} colors, expected {}", actual, expected),
+            PaletteError::Malformed(ref reason) => write!(f, "malformed palette: {}", reason),
+            PaletteError::NothingToUndo => write!(f, "nothing to undo"),
+            PaletteError::NothingToRedo => write!(f, "nothing to redo"),
+            PaletteError::Io { ref source, at: None } => write!(f, "io error: {}", source),
+            PaletteError::Io { ref source, at: Some((ref character, offset)) } =>
+                write!(f, "io error on {}'s palette at {:#X}: {}", character, offset, source),
+            PaletteError::Json(ref error) => write!(f, "json error: {}", error),
+            PaletteError::Rom(ref error) => write!(f, "{}", error),
+            PaletteError::Pointer(ref error) => write!(f, "bad palette pointer: {}", error),
+            PaletteError::NoPointer(ref character) => write!(f, "the palette pointer for {} isn't known", character),
+            PaletteError::UnsupportedRegion(region) =>
+                write!(f, "the palette offsets of the {:?} release aren't known yet, only US ROMs can be edited", region),
+            PaletteError::InvalidGbaColor { index, value } =>
+                write!(f, "color {} is {:#X}, larger than the GBA maximum 0x7FFF", index, value),
+            PaletteError::VerifyFailed { ref character, offset } =>
+                write!(f, "{}'s palette at {:#X} didn't read back as written", character, offset),
+            PaletteError::ImportFailed(ref failures) => {
+                write!(f, "{} files couldn't be imported", failures.len())?;
+                for (path, error) in failures.iter() {
+                    write!(f, "\n{}: {}", path.display(), error)?;
+                }
+                Ok(())
+            }
+            PaletteError::UnknownPreset { ref name, ref available } =>
+                write!(f, "no preset named {}, the presets are: {}", name, available.join(", ")),
+            PaletteError::Archive(ref reason) => write!(f, "archive error: {}", reason),
+            PaletteError::ImageSize { expected, actual } =>
+                write!(f, "image has {} bytes of pixel data, expected {}", actual, expected),
+            PaletteError::FrameLength { ref name, expected, found } =>
+                write!(f, "frame {} has {} colors, the first frame has {}", name, found, expected),
+            PaletteError::OffsetInHeader { ref character, offset } =>
+                write!(f, "{}'s palette offset {:#X} is inside the ROM header", character, offset),
+            PaletteError::BadColorCount { ref character, count } =>
+                write!(f, "{} has a {} color palette, GBA palettes have 16 or 256", character, count),
+            PaletteError::UnknownSpriteFrames(ref character) =>
+                write!(f, "the sprite frames of {} aren't known", character),
+            PaletteError::UnsafeFileName(ref name) => write!(f, "{:?} can't be used as a file name", name),
+        }
+    }
+}
+
+impl error::Error for PaletteError {}
+
+/// reads and writes palettes from a ROM, usually a `File` but any seekable stream works
+pub struct PaletteManager<S = File> {
+    file: Arc<Mutex<S>>,
+    color_cache: Mutex<GBAColorCache>,
+    /// palettes keyed by their lowercased name, see `palette_key`
     palettes: HashMap<String, Vec<i32>>,
+    /// the name each palette was last stored under, for display
+    display_names: HashMap<String, String>,
+    /// keys of palettes edited since they were last read or written
+    dirty: HashSet<String>,
+    undo_stack: VecDeque<Snapshot>,
+    redo_stack: Vec<Snapshot>,
+    history_limit: usize,
+    region: Region,
+    /// where to copy the ROM before the first write, if anywhere
+    auto_backup: Option<PathBuf>,
+    backed_up: bool,
+    /// leave index 0, the transparent color, alone when recoloring
+    preserve_transparency: bool,
+    /// palette offsets of characters that have been repointed
+    repointed: HashMap<String, u64>,
+    /// record writes in `pending_writes` instead of touching the ROM
+    dry_run: bool,
+    pending_writes: Vec<(u64, Vec<u8>)>,
+    /// writes held back until the running `transaction` succeeds
+    transaction: Option<Vec<(u64, Vec<u8>)>>,
+    /// palettes `write_palette_verified` wrote inside the running transaction, with their
+    /// offsets and bytes, checked once the transaction's writes are made
+    pending_verifications: Vec<(String, u64, Vec<u8>)>,
+    /// edited copies of palettes that haven't been applied to `palettes` yet
+    staged: HashMap<String, Vec<i32>>,
+}
+
+/// configures a `PaletteManager`, anything left unset gets the same default as `PaletteManager::new`
+#[derive(Default)]
+pub struct PaletteManagerBuilder {
+    region: Option<Region>,
+    dry_run: bool,
+    auto_backup: Option<PathBuf>,
+    cache_capacity: Option<usize>,
+    strict: bool,
+}
+
+impl PaletteManagerBuilder {
+    pub fn new() -> PaletteManagerBuilder {
+        PaletteManagerBuilder::default()
+    }
+
+    /// look up palette offsets for this release instead of detecting or defaulting it
+    pub fn region(mut self, region: Region) -> PaletteManagerBuilder {
+        self.region = Some(region);
+        self
+    }
+
+    pub fn dry_run(mut self, dry_run: bool) -> PaletteManagerBuilder {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn auto_backup(mut self, path: PathBuf) -> PaletteManagerBuilder {
+        self.auto_backup = Some(path);
+        self
+    }
+
+    /// how many color conversions the cache remembers in each direction
+    pub fn cache_capacity(mut self, capacity: usize) -> PaletteManagerBuilder {
+        self.cache_capacity = Some(capacity);
+        self
+    }
+
+    /// check the header belongs to Sonic Battle, and detect the region unless one was given
+    pub fn strict(mut self, strict: bool) -> PaletteManagerBuilder {
+        self.strict = strict;
+        self
+    }
+
+    pub fn build<S: Read + Write + Seek>(self, file: Arc<Mutex<S>>) -> Result<PaletteManager<S>, PaletteError> {
+        if self.strict {
+            rom::validate_rom(&mut *file.lock().unwrap())?;
+        }
+
+        let mut manager = PaletteManager::new(file);
+        match self.region {
+            Some(region) => manager.set_region(region),
+            None if self.strict => { manager.detect_region()?; }
+            None => (),
+        }
+        if let Some(capacity) = self.cache_capacity {
+            manager.color_cache = Mutex::new(GBAColorCache::with_capacity(capacity));
+        }
+        manager.set_auto_backup(self.auto_backup);
+        manager.set_dry_run(self.dry_run);
+        Ok(manager)
+    }
+}
+
+impl PaletteManager<Cursor<Vec<u8>>> {
+    /// Create a manager over a ROM held in memory
+    pub fn from_bytes(bytes: Vec<u8>) -> PaletteManager<Cursor<Vec<u8>>> {
+        PaletteManager::new(Arc::new(Mutex::new(Cursor::new(bytes))))
+    }
+
+    /// Take back the ROM bytes, including anything written to them
+    pub fn into_bytes(self) -> Vec<u8> {
+        match Arc::try_unwrap(self.file) {
+            Ok(file) => file.into_inner().unwrap().into_inner(),
+            // someone else still holds the ROM, so hand back a copy
+            Err(file) => file.lock().unwrap().get_ref().clone(),
+        }
+    }
 }
 
-impl PaletteManager {
-    pub fn new(file: Arc<Mutex<File>>) -> PaletteManager {
+#[cfg(feature = "zip")]
+impl PaletteManager<Cursor<Vec<u8>>> {
+    /// Create a manager over a ROM unpacked from a zip archive into memory, either the
+    /// entry named `entry` or the only .gba file in the archive
+    pub fn from_zip(path: &Path, entry: Option<&str>) -> Result<PaletteManager<Cursor<Vec<u8>>>, PaletteError> {
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+        let name = match entry {
+            Some(name) => name.to_string(),
+            None => {
+                let mut roms = Vec::new();
+                for index in 0..archive.len() {
+                    let name = archive.by_index(index)?.name().to_string();
+                    if name.to_lowercase().ends_with(".gba") {
+                        roms.push(name);
+                    }
+                }
+                match roms.len() {
+                    1 => roms.remove(0),
+                    0 => return Err(PaletteError::Archive("no .gba file in the archive".to_string())),
+                    count => return Err(PaletteError::Archive(format!("{} .gba files in the archive, pick one", count))),
+                }
+            }
+        };
+
+        let mut file = archive.by_name(&name)?;
+        let mut bytes = Vec::with_capacity(cmp::min(file.size(), rom::MAX_ROM_SIZE) as usize);
+        file.read_to_end(&mut bytes)?;
+        Ok(PaletteManager::from_bytes(bytes))
+    }
+
+    /// Pack the ROM, including anything written to it, into a new zip archive as `entry`
+    pub fn save_to_zip(&self, path: &Path, entry: &str) -> Result<(), PaletteError> {
+        let mut writer = ZipWriter::new(File::create(path)?);
+        writer.start_file(entry, FileOptions::default())?;
+        writer.write_all(self.file.lock().unwrap().get_ref())?;
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "memmap2")]
+impl PaletteManager<MmapRom> {
+    /// Create a manager over a ROM mapped into memory, which is faster than a `File` for
+    /// many scattered reads. The file must not be resized while it's open, see `MmapRom`
+    pub fn open_mmap(path: &Path) -> Result<PaletteManager<MmapRom>, PaletteError> {
+        Ok(PaletteManager::new(Arc::new(Mutex::new(MmapRom::open(path)?))))
+    }
+}
+
+impl PaletteManager<File> {
+    /// Flush and wait until everything written so far is on disk, for callers
+    /// that batch many writes and only want to pay for the sync once
+    pub fn sync(&self) -> Result<(), PaletteError> {
+        let mut file = self.file.lock().unwrap();
+        file.flush()?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// Read every character palette like `read_palettes`, split across `threads` worker threads.
+    /// The workers share a second handle to the ROM and only use positional reads, so none of
+    /// them depend on or move a file position. Nothing is stored unless every read succeeds.
+    ///
+    /// This is only for files because other streams have no positional reads: workers sharing
+    /// one behind the lock would have to take turns seeking and reading, which is all
+    /// `read_palettes` does anyway
+    pub fn read_palettes_parallel(&mut self, threads: usize) -> Result<(), PaletteError> {
+        let mut jobs = Vec::new();
+        for character in CHARACTERS.iter() {
+            for slot in 0..character.palette_slots() {
+                jobs.push((character.slot_name(slot), self.slot_offset(character, slot)?, character.color_count));
+            }
+        }
+
+        let file = Arc::new(self.file.lock().unwrap().try_clone()?);
+        let per_worker = jobs.len().div_ceil(threads.max(1));
+        let mut workers = Vec::new();
+        while !jobs.is_empty() {
+            let rest = jobs.split_off(cmp::min(per_worker, jobs.len()));
+            let batch = mem::replace(&mut jobs, rest);
+            let file = file.clone();
+            workers.push(thread::spawn(move || -> Result<Vec<(String, Vec<i32>)>, PaletteError> {
+                let mut palettes = Vec::with_capacity(batch.len());
+                for (name, offset, count) in batch {
+                    let mut bytes = vec![0u8; count * 2];
+                    read_exact_at(&file, &mut bytes, offset).map_err(|error| PaletteError::from(error).at(&name, offset))?;
+                    palettes.push((name, decode_palette(&bytes)?));
+                }
+                Ok(palettes)
+            }));
+        }
+
+        // join every worker before giving up on one, so none are left running
+        let results: Vec<_> = workers.into_iter()
+            .map(|worker| worker.join().unwrap_or_else(|_| Err(PaletteError::from(Error::other("a palette reading thread panicked")))))
+            .collect();
+        let results = results.into_iter().collect::<Result<Vec<_>, _>>()?;
+        for (name, colors) in results.into_iter().flatten() {
+            self.store_from_rom(name, colors);
+        }
+        Ok(())
+    }
+}
+
+/// fill `buffer` from `offset` without using or moving the file position
+#[cfg(unix)]
+fn read_exact_at(file: &File, buffer: &mut [u8], offset: u64) -> Result<(), Error> {
+    file.read_exact_at(buffer, offset)
+}
+
+/// fill `buffer` from `offset`, `seek_read` reads at the offset it's given even while other
+/// threads read through the same handle
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buffer: &mut [u8], mut offset: u64) -> Result<(), Error> {
+    while !buffer.is_empty() {
+        match file.seek_read(buffer, offset)? {
+            0 => return Err(Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+            read => {
+                let rest = buffer;
+                buffer = &mut rest[read..];
+                offset += read as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl<S: Read + Write + Seek> PaletteManager<S> {
+    pub fn new(file: Arc<Mutex<S>>) -> PaletteManager<S> {
         PaletteManager {
             file: file.clone(),
-            color_cache: GBAColorCache::new(),
+            color_cache: Mutex::new(GBAColorCache::new()),
             palettes: HashMap::new(),
+            display_names: HashMap::new(),
+            dirty: HashSet::new(),
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            history_limit: DEFAULT_HISTORY_LIMIT,
+            region: Region::Usa,
+            auto_backup: None,
+            backed_up: false,
+            preserve_transparency: true,
+            repointed: HashMap::new(),
+            dry_run: false,
+            pending_writes: Vec::new(),
+            transaction: None,
+            staged: HashMap::new(),
+            pending_verifications: Vec::new(),
+        }
+    }
+
+    /// Create a manager after checking the header belongs to Sonic Battle, detecting the region
+    pub fn new_validated(file: Arc<Mutex<S>>) -> Result<PaletteManager<S>, PaletteError> {
+        rom::validate_rom(&mut *file.lock().unwrap())?;
+        let mut manager = PaletteManager::new(file);
+        manager.detect_region()?;
+        Ok(manager)
+    }
+
+    /// Like `new_validated`, but first check the file is the size of a GBA cartridge and the
+    /// character table has GBA sized palettes, so a truncated or unrelated file fails here
+    /// rather than at the first read
+    pub fn open_strict(file: Arc<Mutex<S>>) -> Result<PaletteManager<S>, PaletteError> {
+        check_character_table(&CHARACTERS)?;
+        rom::check_rom_size(&mut *file.lock().unwrap())?;
+        PaletteManager::new_validated(file)
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Set which release the character palette offsets are looked up for
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// Set the region from the ROM header, keeping the current one if the header doesn't name one.
+    /// In a release whose offsets haven't been mapped, character palettes fail with
+    /// `UnsupportedRegion` instead of being read at the US offsets
+    pub fn detect_region(&mut self) -> Result<Region, PaletteError> {
+        if let Some(region) = rom::detect_region(&mut *self.file.lock().unwrap())? {
+            self.region = region;
+        }
+        Ok(self.region)
+    }
+
+    /// The palette offset of a character in the current region. When the location of the
+    /// character's palette pointer is known the pointer is followed, so a palette repointed in an
+    /// earlier session is found where it was moved to
+    fn palette_offset(&self, character: &Character) -> Result<u64, PaletteError> {
+        if let Some(&offset) = self.repointed.get(character.name) {
+            return Ok(offset);
+        }
+        let mut offset = character.palette_offset_for(self.region).ok_or(PaletteError::UnsupportedRegion(self.region))?;
+        if let Some(pointer) = character.pointer() {
+            offset = rom::read_rom_pointer(&mut *self.file.lock().unwrap(), pointer)
+                .map_err(|error| PaletteError::from(error).at(character.name, pointer))?;
+        }
+        check_offset(character.name, offset)
+    }
+
+    /// Copy the whole ROM to `path`, leaving the stream position where it was
+    pub fn backup(&self, path: &Path) -> Result<(), PaletteError> {
+        let contents = self.rom_contents()?;
+        File::create(path)?.write_all(&contents)?;
+        Ok(())
+    }
+
+    /// Copy the whole ROM to `path` unless a file is already there, so a backup from an
+    /// earlier session isn't replaced by a copy of the ROM it edited
+    fn backup_once(&self, path: &Path) -> Result<(), PaletteError> {
+        let mut backup = match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(backup) => backup,
+            Err(ref error) if error.kind() == ErrorKind::AlreadyExists => return Ok(()),
+            Err(error) => return Err(error.into()),
+        };
+        backup.write_all(&self.rom_contents()?)?;
+        Ok(())
+    }
+
+    /// Read the whole ROM, leaving the stream position where it was
+    fn rom_contents(&self) -> Result<Vec<u8>, Error> {
+        let mut contents = Vec::new();
+        let mut file = self.file.lock().unwrap();
+        let position = file.stream_position()?;
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_end(&mut contents)?;
+        file.seek(SeekFrom::Start(position))?;
+        Ok(contents)
+    }
+
+    /// Back up the ROM to `path` right before the first write, or never with `None`.
+    /// A file already at `path` is kept, so it stays a copy of the ROM from before any session edited it
+    pub fn set_auto_backup(&mut self, path: Option<PathBuf>) {
+        self.auto_backup = path;
+        self.backed_up = false;
+    }
+
+    /// Write bytes into the ROM, making the automatic backup first if one is due
+    fn write_at(&mut self, offset: u64, bytes: &[u8]) -> Result<(), PaletteError> {
+        if self.dry_run {
+            self.pending_writes.push((offset, bytes.to_vec()));
+            return Ok(());
+        }
+        if let Some(ref mut writes) = self.transaction {
+            writes.push((offset, bytes.to_vec()));
+            return Ok(());
+        }
+        if !self.backed_up {
+            if let Some(path) = self.auto_backup.clone() {
+                self.backup_once(&path)?;
+                self.backed_up = true;
+            }
+        }
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(bytes)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Run `f` as one all or nothing batch: ROM writes made inside it are held back until it returns `Ok`,
+    /// and thrown away along with every stored palette, name, undo step, dirty and repointing change
+    /// it made if it returns `Err`. Reads inside `f` see the ROM as it was before the transaction, so
+    /// palettes written with `write_palette_verified` are read back once the writes are made, and a
+    /// failed read back undoes the whole batch too
+    pub fn transaction<F>(&mut self, f: F) -> Result<(), PaletteError>
+        where F: FnOnce(&mut PaletteManager<S>) -> Result<(), PaletteError> {
+        // a nested transaction is just part of the outer one
+        if self.transaction.is_some() {
+            return f(self);
+        }
+
+        let palettes = self.palettes.clone();
+        let display_names = self.display_names.clone();
+        let undo_stack = self.undo_stack.clone();
+        let redo_stack = self.redo_stack.clone();
+        let dirty = self.dirty.clone();
+        let repointed = self.repointed.clone();
+        self.transaction = Some(Vec::new());
+        let result = f(self);
+        let writes = self.transaction.take().unwrap_or_default();
+        let verifications = mem::take(&mut self.pending_verifications);
+
+        let result = result.and_then(|_| self.commit_writes(&writes, &verifications));
+        if result.is_err() {
+            self.palettes = palettes;
+            self.display_names = display_names;
+            self.undo_stack = undo_stack;
+            self.redo_stack = redo_stack;
+            self.dirty = dirty;
+            self.repointed = repointed;
+        }
+        result
+    }
+
+    /// Make a transaction's writes and read back the verified palettes, putting back the bytes
+    /// already written if a write or a read back fails
+    fn commit_writes(&mut self, writes: &[(u64, Vec<u8>)], verifications: &[(String, u64, Vec<u8>)]) -> Result<(), PaletteError> {
+        let mut originals = Vec::with_capacity(writes.len());
+        {
+            let mut file = self.file.lock().unwrap();
+            for &(offset, ref bytes) in writes.iter() {
+                let mut original = Vec::with_capacity(bytes.len());
+                file.seek(SeekFrom::Start(offset))?;
+                Read::by_ref(&mut *file).take(bytes.len() as u64).read_to_end(&mut original)?;
+                originals.push((offset, original));
+            }
+        }
+
+        let mut attempted = 0;
+        let mut result = Ok(());
+        for &(offset, ref bytes) in writes.iter() {
+            attempted += 1;
+            result = self.write_at(offset, bytes);
+            if result.is_err() {
+                break;
+            }
+        }
+        if result.is_ok() {
+            for &(ref character, offset, ref expected) in verifications.iter() {
+                result = self.verify_at(character, offset, expected);
+                if result.is_err() {
+                    break;
+                }
+            }
+        }
+        if result.is_err() {
+            for &(offset, ref original) in originals[..attempted].iter().rev() {
+                let _ = self.write_at(offset, original);
+            }
+        }
+        result
+    }
+
+    /// Set whether writes are only recorded in `pending_writes`, leaving the ROM untouched
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// The (offset, bytes) writes recorded in dry run mode, oldest first
+    pub fn pending_writes(&self) -> &[(u64, Vec<u8>)] {
+        &self.pending_writes
+    }
+
+    /// Forget the writes recorded in dry run mode
+    pub fn clear_pending_writes(&mut self) {
+        self.pending_writes.clear();
+    }
+
+    /// Set whether recoloring skips index 0, the transparent color on the GBA
+    pub fn set_preserve_transparency(&mut self, preserve: bool) {
+        self.preserve_transparency = preserve;
+    }
+
+    /// Set how many edits are kept for undo, dropping the oldest ones past the limit
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history_limit = limit;
+        while self.undo_stack.len() > limit {
+            self.undo_stack.pop_front();
+        }
+    }
+
+    pub fn undo_depth(&self) -> usize {
+        self.undo_stack.len()
+    }
+
+    pub fn redo_depth(&self) -> usize {
+        self.redo_stack.len()
+    }
+
+    /// Revert the last edit
+    pub fn undo(&mut self) -> Result<(), PaletteError> {
+        let (name, colors, display_name) = self.undo_stack.pop_back().ok_or(PaletteError::NothingToUndo)?;
+        let (current, current_name) = self.restore_snapshot(name.clone(), colors, display_name);
+        self.redo_stack.push((name, current, current_name));
+        Ok(())
+    }
+
+    /// Reapply the last undone edit
+    pub fn redo(&mut self) -> Result<(), PaletteError> {
+        let (name, colors, display_name) = self.redo_stack.pop().ok_or(PaletteError::NothingToRedo)?;
+        let (current, current_name) = self.restore_snapshot(name.clone(), colors, display_name);
+        self.undo_stack.push_back((name, current, current_name));
+        Ok(())
+    }
+
+    /// Put a palette and its name back to a snapshot, returning what they replaced
+    fn restore_snapshot(&mut self, name: String, colors: Option<Vec<i32>>, display_name: Option<String>)
+        -> (Option<Vec<i32>>, Option<String>) {
+        self.dirty.insert(name.clone());
+        let current_name = match display_name {
+            Some(display_name) => self.display_names.insert(name.clone(), display_name),
+            None => self.display_names.remove(&name),
+        };
+        let current = match colors {
+            Some(colors) => self.palettes.insert(name, colors),
+            None => self.palettes.remove(&name),
+        };
+        (current, current_name)
+    }
+
+    /// Snapshot a palette before it's edited, a new edit can't be redone past
+    fn record_undo(&mut self, name: &str) {
+        if self.history_limit == 0 {
+            return;
+        }
+        let key = palette_key(name);
+        let snapshot = (key.clone(), self.palettes.get(&key).cloned(), self.display_names.get(&key).cloned());
+        self.undo_stack.push_back(snapshot);
+        while self.undo_stack.len() > self.history_limit {
+            self.undo_stack.pop_front();
         }
+        self.redo_stack.clear();
     }
 
-    /// Store the palette of GBA encoded numbers
+    /// Store the palette of GBA encoded numbers. Nothing is checked, the low 16 bits of each
+    /// value are written as they are, unused top bit included
     pub fn store_palette_i32(&mut self, name: String, colors: Vec<i32>) {
-        self.palettes.insert(name, colors);
+        let key = palette_key(&name);
+        self.record_undo(&key);
+        self.dirty.insert(key.clone());
+        self.display_names.insert(key.clone(), name);
+        self.palettes.insert(key, colors);
+    }
+
+    /// Store a palette of GBA colors
+    pub fn store_palette_gba(&mut self, name: String, colors: Vec<GbaColor>) {
+        self.store_palette_i32(name, colors.iter().map(|color| color.value() as i32).collect());
+    }
+
+    /// Store GBA encoded colors, failing if any is outside 0..=0x7FFF
+    pub fn store_palette_i32_checked(&mut self, name: String, colors: Vec<i32>) -> Result<(), PaletteError> {
+        check_gba_colors(&colors)?;
+        self.store_palette_i32(name, colors);
+        Ok(())
     }
 
     /// Convert the color structs to GBA encoded numbers and store them
     pub fn store_palette_colors(&mut self, name: String, colors: Vec<Color>) {
-        let gba_colors: Vec<i32> = colors.iter().map(|&c| self.color_cache.rgb_to_gba(c)).collect();
+        let gba_colors = self.encode_colors(&colors);
         self.store_palette_i32(name, gba_colors);
     }
 
+    /// Derive a palette of up to `max_colors` colors from an RGBA image with median cut and store it.
+    /// Index 0 is the color of the most transparent pixels, or the most common color in an opaque image
+    pub fn store_palette_from_image(&mut self, name: String, rgba: &[u8], max_colors: usize) {
+        let pixels: Vec<(Color, u8)> = rgba.chunks(4)
+            .filter(|pixel| pixel.len() == 4)
+            .map(|pixel| (Color::rgb(pixel[0] as i32, pixel[1] as i32, pixel[2] as i32), pixel[3]))
+            .collect();
+
+        let lowest_alpha = pixels.iter().map(|&(_, alpha)| alpha).min().unwrap_or(0xFF);
+        let colors = if lowest_alpha < 0xFF {
+            // the background keeps index 0 and the visible pixels share what's left
+            let background = pixels.iter().find(|&&(_, alpha)| alpha == lowest_alpha).unwrap().0;
+            let opaque: Vec<Color> = pixels.iter()
+                .filter(|&&(_, alpha)| alpha > lowest_alpha)
+                .map(|&(color, _)| color)
+                .collect();
+            let mut colors = vec![background];
+            colors.extend(median_cut(&opaque, max_colors.saturating_sub(1)));
+            colors.truncate(max_colors);
+            colors
+        } else {
+            let all: Vec<Color> = pixels.iter().map(|&(color, _)| color).collect();
+            median_cut(&all, max_colors)
+        };
+        self.store_palette_colors(name, colors);
+    }
+
+    /// Store a linear gradient of `steps` colors running from `start` to `end`
+    pub fn store_gradient(&mut self, name: String, start: Color, end: Color, steps: usize) {
+        let colors: Vec<Color> = (0..steps).map(|step| {
+            if steps == 1 {
+                return start;
+            }
+            let t = step as f32 / (steps - 1) as f32;
+            let lerp = |a: i32, b: i32| (a as f32 + (b - a) as f32 * t).round() as i32;
+            Color::rgb(lerp(start.r, end.r), lerp(start.g, end.g), lerp(start.b, end.b))
+        }).collect();
+        self.store_palette_colors(name, colors);
+    }
+
+    /// Borrow a stored palette without copying it
+    pub fn palette<'a>(&'a self, name: &str) -> Result<PaletteRef<'a>, PaletteError> {
+        self.get_palette(name).map(|values| PaletteRef { values })
+    }
+
+    /// Borrow the colors in GBA encoding, the borrow keeps the manager from being edited
+    /// until it's dropped, so use `load_palette_i32` for a copy to hold on to
+    pub fn load_palette_ref<'a>(&'a self, name: &str) -> Result<&'a [i32], PaletteError> {
+        self.get_palette(name).map(|colors| colors.as_slice())
+    }
+
+    /// Load the colors as GBA colors, dropping the unused top bit of any value stored with it set
+    pub fn load_palette_gba(&self, name: &str) -> Result<Vec<GbaColor>, PaletteError> {
+        Ok(self.load_palette_ref(name)?.iter().map(|&value| GbaColor::masked(value)).collect())
+    }
+
     /// Load the colors in GBA encoding
-    pub fn load_palette_i32<'a>(&'a self, name: String) -> Vec<i32> {
-        self.palettes.get(&name).unwrap().clone()
+    pub fn load_palette_i32(&self, name: String) -> Result<Vec<i32>, PaletteError> {
+        self.get_palette(&name).cloned()
     }
 
-    /// Load the colors as Color structs
-    pub fn load_palette_colors(&mut self, name: String) -> Vec<Color> {
-        let values: Vec<i32> = self.load_palette_i32(name);
-        values.iter().map(|&i| self.color_cache.gba_to_rgb(i)).collect()
+    /// Load the colors as Color structs. The color cache is behind a lock, so this only needs `&self`
+    pub fn load_palette_colors(&self, name: String) -> Result<Vec<Color>, PaletteError> {
+        Ok(self.decode_values(self.load_palette_ref(&name)?))
     }
 
-    /// Read all the palettes in the ROM and store them
-    pub fn read_palettes(&mut self) -> Result<(), Error> {
-        for character in CHARACTERS.iter() {
-            self.read_palette(character)?
-        }
-        Ok(())
+    /// Decode BGR555 values through the color cache
+    fn decode_values(&self, values: &[i32]) -> Vec<Color> {
+        self.color_cache.lock().unwrap().gba_to_rgb_slice(values)
+    }
+
+    /// Encode colors through the color cache
+    fn encode_colors(&self, colors: &[Color]) -> Vec<i32> {
+        self.color_cache.lock().unwrap().rgb_to_gba_slice(colors)
+    }
+
+    /// Decode both values of each (index, a, b) change from `diff_values`
+    fn decode_changes(&self, changes: &[(usize, i32, i32)]) -> Vec<(usize, Color, Color)> {
+        let a_values: Vec<i32> = changes.iter().map(|&(_, a, _)| a).collect();
+        let b_values: Vec<i32> = changes.iter().map(|&(_, _, b)| b).collect();
+        changes.iter().zip(self.decode_values(&a_values)).zip(self.decode_values(&b_values))
+            .map(|((&(index, _, _), a), b)| (index, a, b))
+            .collect()
+    }
+
+    /// The name a palette was stored under, keys without one are shown as is
+    fn display_name<'a>(&'a self, key: &'a str) -> &'a str {
+        self.display_names.get(key).map(|name| name.as_str()).unwrap_or(key)
+    }
+
+    /// Whether a palette is stored under `name`, ignoring case
+    pub fn has_palette(&self, name: &str) -> bool {
+        self.palettes.contains_key(&palette_key(name))
     }
 
-    /// Read a palette for a specific character and store it
-    pub fn read_palette(&mut self, character: &Character) -> Result<(), Error> {
-        self.file.lock().unwrap().seek(SeekFrom::Start(character.palette_offset))?;
+    /// Names of every stored palette, sorted
+    pub fn palette_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.palettes.keys().map(|key| self.display_name(key)).collect();
+        names.sort();
+        names
+    }
+
+    /// Characters whose palette hasn't been read or stored yet
+    pub fn unloaded_characters(&self) -> Vec<&'static Character> {
+        CHARACTERS.iter().filter(|character| !self.has_palette(character.name)).collect()
+    }
+
+    /// Number of colors in a stored palette
+    pub fn palette_len(&self, name: &str) -> Option<usize> {
+        self.palettes.get(&palette_key(name)).map(|colors| colors.len())
+    }
+
+    /// Drop a stored palette, along with its pending write and undo history
+    pub fn remove_palette(&mut self, name: &str) -> Option<Vec<i32>> {
+        let key = palette_key(name);
+        self.dirty.remove(&key);
+        self.display_names.remove(&key);
+        self.undo_stack.retain(|(snapshot_key, _, _)| *snapshot_key != key);
+        self.redo_stack.retain(|(snapshot_key, _, _)| *snapshot_key != key);
+        self.staged.remove(&key);
+        self.palettes.remove(&key)
+    }
+
+    /// Drop every stored palette, along with all pending writes and undo history
+    pub fn clear(&mut self) {
+        self.palettes.clear();
+        self.display_names.clear();
+        self.dirty.clear();
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.staged.clear();
+    }
+
+    fn get_palette(&self, name: &str) -> Result<&Vec<i32>, PaletteError> {
+        self.palettes.get(&palette_key(name)).ok_or_else(|| PaletteError::PaletteNotFound(name.to_string()))
+    }
+
+    fn get_palette_mut(&mut self, name: &str) -> Result<&mut Vec<i32>, PaletteError> {
+        self.palettes.get_mut(&palette_key(name)).ok_or_else(|| PaletteError::PaletteNotFound(name.to_string()))
+    }
 
-        let mut color_buffer: [u8; 32] = [0; 32];
-        self.file.lock().unwrap().read(&mut color_buffer[..])?;
+    /// Get a single color from a stored palette
+    pub fn get_color(&mut self, name: &str, index: usize) -> Result<Color, PaletteError> {
+        let value = {
+            let colors = self.get_palette(name)?;
+            match colors.get(index) {
+                Some(&value) => value,
+                None => return Err(PaletteError::IndexOutOfBounds { name: name.to_string(), index, len: colors.len() }),
+            }
+        };
+        Ok(self.color_cache.lock().unwrap().gba_to_rgb(value))
+    }
 
-        let mut colors = [0; 16];
-        for i in 0..16 {
-            let a = color_buffer[i * 2] as i32;
-            let b = color_buffer[i * 2 + 1] as i32;
+    /// Replace a single color in a stored palette
+    pub fn set_color(&mut self, name: &str, index: usize, color: Color) -> Result<(), PaletteError> {
+        let value = self.color_cache.lock().unwrap().rgb_to_gba(color);
+        self.set_color_gba(name, index, value)
+    }
 
-            // swap the bytes
-            let color: i32 = (b << 8) | a;
-            colors[i] = color;
+    /// Replace a single color in a stored palette with a raw BGR555 value
+    pub fn set_color_gba(&mut self, name: &str, index: usize, value: i32) -> Result<(), PaletteError> {
+        let value = value & GBA_COLOR_MASK;
+        let len = self.get_palette(name)?.len();
+        if index >= len {
+            return Err(PaletteError::IndexOutOfBounds { name: name.to_string(), index, len });
         }
-        self.store_palette_i32(String::from(character.name), colors.to_vec());
+        self.record_undo(name);
+        self.get_palette_mut(name)?[index] = value;
+        self.dirty.insert(palette_key(name));
         Ok(())
     }
 
-    /// Write the palette stored for a character into the ROM
-    pub fn write_palette(&mut self, character: &Character) -> Result<(), Error> {
-        self.file.lock().unwrap().seek(SeekFrom::Start(character.palette_offset))?;
-        for i in self.load_palette_i32(character.name.to_string()).iter() {
-            let b = (i & 0xFF00) >> 8;
-            let a = i & 0x00FF;
-            self.file.lock().unwrap().write(&[a as u8, b as u8])?;
+    /// Replace a single color in the staged copy of a palette, leaving the stored palette
+    /// alone until `commit_staged`
+    pub fn stage_color(&mut self, name: &str, index: usize, color: Color) -> Result<(), PaletteError> {
+        let value = self.color_cache.lock().unwrap().rgb_to_gba(color);
+        let key = palette_key(name);
+        if !self.staged.contains_key(&key) {
+            let colors = self.get_palette(name)?.clone();
+            self.staged.insert(key.clone(), colors);
+        }
+
+        let colors = self.staged.get_mut(&key).unwrap();
+        let len = colors.len();
+        match colors.get_mut(index) {
+            Some(slot) => *slot = value,
+            None => return Err(PaletteError::IndexOutOfBounds { name: name.to_string(), index, len }),
         }
         Ok(())
     }
 
-    pub fn print_palette(&mut self, character: &Character) {
-        let converted_colors = self.load_palette_colors(character.name.to_string());
-        println!("v== {} ==v", character.name);
-        for convcol in converted_colors.iter() {
-            println!("{:?}", convcol)
+    /// A palette with its staged edits, or as stored if none are staged
+    pub fn staged_palette<'a>(&'a self, name: &str) -> Result<&'a [i32], PaletteError> {
+        match self.staged.get(&palette_key(name)) {
+            Some(colors) => Ok(colors),
+            None => self.load_palette_ref(name),
         }
-        println!("^== {} ==^", character.name);
+    }
+
+    /// Names of the palettes with staged edits, sorted
+    pub fn staged_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.staged.keys().map(|key| self.display_name(key)).collect();
+        names.sort();
+        names
+    }
+
+    /// Apply every staged edit to the stored palettes, each as one undoable edit.
+    /// The palettes are marked dirty, so `write_dirty` puts them in the ROM
+    pub fn commit_staged(&mut self) {
+        let staged: Vec<(String, Vec<i32>)> = self.staged.drain().collect();
+        for (key, colors) in staged {
+            self.record_undo(&key);
+            self.dirty.insert(key.clone());
+            self.palettes.insert(key, colors);
+        }
+    }
+
+    /// Throw away every staged edit
+    pub fn discard_staged(&mut self) {
+        self.staged.clear();
+    }
+
+    /// The colors of a stored palette in sorted order, each with its index in the palette.
+    /// The palette itself isn't reordered, since sprites refer to colors by index
+    pub fn sorted_colors(&self, name: &str, key: SortKey) -> Result<Vec<(usize, Color)>, PaletteError> {
+        let values = self.load_palette_ref(name)?;
+        let mut colors: Vec<(usize, i32, Color)> = values.iter().zip(self.decode_values(values)).enumerate()
+            .map(|(index, (&value, color))| (index, value, color))
+            .collect();
+
+        // grays have no hue, put them before the reds
+        let hue = |color: &Color| match color.to_hsv() {
+            (_, 0.0, _) => -1.0,
+            (hue, _, _) => hue,
+        };
+
+        // the sort is stable, so equal colors stay in index order
+        match key {
+            SortKey::Hue => colors.sort_by(|a, b| hue(&a.2).partial_cmp(&hue(&b.2)).unwrap_or(cmp::Ordering::Equal)),
+            SortKey::Luminance => colors.sort_by(|a, b| {
+                a.2.to_lab().0.partial_cmp(&b.2.to_lab().0).unwrap_or(cmp::Ordering::Equal)
+            }),
+            SortKey::RawValue => colors.sort_by_key(|&(_, value, _)| value & GBA_COLOR_MASK),
+        }
+        Ok(colors.into_iter().map(|(index, _, color)| (index, color)).collect())
+    }
+
+    /// Names of the palettes edited since they were last read or written, sorted
+    pub fn dirty_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.dirty.iter().map(|key| self.display_name(key)).collect();
+        names.sort();
+        names
+    }
+
+    /// A hash of a stored palette's raw values that's the same on every run and machine,
+    /// for recognizing known palettes
+    pub fn palette_fingerprint(&self, name: &str) -> Result<u64, PaletteError> {
+        let values = self.get_palette(name)?;
+        Ok(random::fnv1a(values.iter().flat_map(|&value| (0..4).map(move |i| (value >> (i * 8)) as u8))))
+    }
+
+    /// The distinct colors in a stored palette, in the order they first appear
+    pub fn unique_colors(&mut self, name: &str) -> Result<Vec<Color>, PaletteError> {
+        let colors = self.load_palette_colors(name.to_string())?;
+        let mut seen = HashSet::new();
+        Ok(colors.into_iter().filter(|color| seen.insert(*color)).collect())
+    }
+
+    /// Every (palette name, index) in the stored palettes holding a color within `tolerance` of
+    /// `target` on each channel, by palette name. A tolerance of 0 compares the GBA encoded values
+    pub fn find_color(&self, target: Color, tolerance: u8) -> Vec<(String, usize)> {
+        let target_value = encode_gba(target);
+        let tolerance = tolerance as i32;
+        let mut found = Vec::new();
+        for name in self.palette_names() {
+            let values = &self.palettes[&palette_key(name)];
+            let colors = if tolerance == 0 { Vec::new() } else { self.decode_values(values) };
+            for (index, &value) in values.iter().enumerate() {
+                let matches = if tolerance == 0 {
+                    value & GBA_COLOR_MASK == target_value
+                } else {
+                    let color = colors[index];
+                    (color.r - target.r).abs() <= tolerance
+                        && (color.g - target.g).abs() <= tolerance
+                        && (color.b - target.b).abs() <= tolerance
+                };
+                if matches {
+                    found.push((name.to_string(), index));
+                }
+            }
+        }
+        found
+    }
+
+    /// How many pixels of the character's sprite frames use each palette index, an index
+    /// that's never used is free to recolor or reuse
+    pub fn index_usage(&self, character: &Character) -> Result<[u32; PALETTE_SIZE], PaletteError> {
+        if character.sprite_frames.iter().any(|&frames| frames < 0) {
+            return Err(PaletteError::UnknownSpriteFrames(character.name.to_string()));
+        }
+        let frames: i32 = character.sprite_frames.iter().sum();
+        let mut bytes = vec![0u8; frames as usize * FRAME_SIZE * FRAME_SIZE * TILE_4BPP_BYTES];
+        let read = {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(character.sprite_offset as u64)).and_then(|_| file.read_exact(&mut bytes))
+        };
+        read.map_err(|error| PaletteError::from(error).at(character.name, character.sprite_offset as u64))?;
+
+        let mut usage = [0u32; PALETTE_SIZE];
+        let tiles = tiles::decode_tiles_4bpp(&bytes).map_err(|error| PaletteError::Malformed(error.to_string()))?;
+        for tile in tiles.iter() {
+            for &index in tile.iter().flat_map(|row| row.iter()) {
+                usage[index as usize] += 1;
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Find the index of the color in a stored palette closest to `target`
+    pub fn nearest_index(&mut self, name: &str, target: Color) -> Result<usize, PaletteError> {
+        let colors = self.load_palette_colors(name.to_string())?;
+        let mut best: Option<(usize, f64)> = None;
+        for (index, &color) in colors.iter().enumerate() {
+            let distance = color_distance(color, target);
+            match best {
+                Some((_, best_distance)) if best_distance <= distance => (),
+                _ => best = Some((index, distance)),
+            }
+        }
+        best.map(|(index, _)| index).ok_or(PaletteError::PaletteTooShort { expected: 1, found: 0 })
+    }
+
+    /// Render a tile of palette indices as 8x8 RGB bytes using a stored palette
+    pub fn render_tile(&mut self, palette: &str, indices: &[[u8; TILE_SIZE]; TILE_SIZE]) -> Result<Vec<u8>, PaletteError> {
+        self.render_tile_pixels(palette, indices, false)
+    }
+
+    /// Render a tile of palette indices as 8x8 RGBA bytes, index 0 is fully transparent
+    pub fn render_tile_rgba(&mut self, palette: &str, indices: &[[u8; TILE_SIZE]; TILE_SIZE]) -> Result<Vec<u8>, PaletteError> {
+        self.render_tile_pixels(palette, indices, true)
+    }
+
+    fn render_tile_pixels(&mut self, palette: &str, indices: &[[u8; TILE_SIZE]; TILE_SIZE], alpha: bool) -> Result<Vec<u8>, PaletteError> {
+        let colors = self.load_palette_colors(palette.to_string())?;
+        let channels = if alpha { 4 } else { 3 };
+        let mut pixels = Vec::with_capacity(TILE_SIZE * TILE_SIZE * channels);
+
+        for &index in indices.iter().flat_map(|row| row.iter()) {
+            let color = colors.get(index as usize).ok_or_else(|| PaletteError::IndexOutOfBounds {
+                name: palette.to_string(),
+                index: index as usize,
+                len: colors.len(),
+            })?;
+            pixels.extend_from_slice(&[color.r as u8, color.g as u8, color.b as u8]);
+            if alpha {
+                pixels.push(if index == 0 { 0 } else { 0xFF });
+            }
+        }
+        Ok(pixels)
+    }
+
+    /// Map each pixel of an RGBA image to the index of the nearest color in a stored palette,
+    /// fully transparent pixels map to index 0
+    pub fn quantize_image(&mut self, name: &str, rgba: &[u8], width: u32, height: u32) -> Result<Vec<u8>, PaletteError> {
+        let expected = width as usize * height as usize * 4;
+        if rgba.len() != expected {
+            return Err(PaletteError::ImageSize { expected, actual: rgba.len() });
+        }
+
+        // sprite art reuses a handful of colors, so only search the palette once for each
+        let mut indices: HashMap<Color, u8> = HashMap::new();
+        let mut pixels = Vec::with_capacity(rgba.len() / 4);
+        for pixel in rgba.chunks(4) {
+            if pixel[3] == 0 {
+                pixels.push(0);
+                continue;
+            }
+
+            let color = Color::rgb(pixel[0] as i32, pixel[1] as i32, pixel[2] as i32);
+            let index = match indices.get(&color) {
+                Some(&index) => index,
+                None => self.nearest_index(name, color)? as u8,
+            };
+            indices.insert(color, index);
+            pixels.push(index);
+        }
+        Ok(pixels)
+    }
+
+    /// How a stored palette looks with a color vision deficiency, the palette itself is left alone
+    pub fn simulate_cvd(&mut self, name: &str, kind: CvdType) -> Result<Vec<Color>, PaletteError> {
+        let colors = self.load_palette_colors(name.to_string())?;
+        Ok(colors.into_iter().map(|color| simulate_cvd(color, kind)).collect())
+    }
+
+    /// Copy a stored palette under a new name, replacing anything stored there
+    pub fn clone_palette(&mut self, src: &str, dst: String) -> Result<(), PaletteError> {
+        let colors = self.load_palette_i32(src.to_string())?;
+        self.store_palette_i32(dst, colors);
+        Ok(())
+    }
+
+    /// Copy a stored palette under a new name, failing if the name is taken
+    pub fn clone_palette_checked(&mut self, src: &str, dst: String) -> Result<(), PaletteError> {
+        if self.has_palette(&dst) {
+            return Err(PaletteError::PaletteExists(dst));
+        }
+        self.clone_palette(src, dst)
+    }
+
+    /// Replace every color in a stored palette with the result of `f`,
+    /// leaving index 0 alone while `preserve_transparency` is set
+    fn map_colors<F: FnMut(Color) -> Color>(&mut self, name: &str, mut f: F) -> Result<(), PaletteError> {
+        let preserve_transparency = self.preserve_transparency;
+        let colors: Vec<Color> = self.load_palette_colors(name.to_string())?
+            .into_iter()
+            .enumerate()
+            .map(|(index, color)| if index == 0 && preserve_transparency { color } else { f(color) })
+            .collect();
+        self.store_palette_colors(name.to_string(), colors);
+        Ok(())
+    }
+
+    /// Lighten (positive `delta`) or darken (negative `delta`) every color in a stored palette
+    pub fn adjust_brightness(&mut self, name: &str, delta: i16) -> Result<(), PaletteError> {
+        self.map_colors(name, |color| color.saturating_add_rgb(delta, delta, delta))
+    }
+
+    /// Rotate the hue of every color in a stored palette, leaving grays alone
+    pub fn rotate_hue(&mut self, name: &str, degrees: f32) -> Result<(), PaletteError> {
+        self.map_colors(name, |color| {
+            let (hue, saturation, value) = color.to_hsv();
+            if saturation == 0.0 {
+                color
+            } else {
+                Color::from_hsv(hue + degrees, saturation, value)
+            }
+        })
+    }
+
+    /// Replace every color in a stored palette with its luminance
+    pub fn to_grayscale(&mut self, name: &str) -> Result<(), PaletteError> {
+        self.map_colors(name, |color| {
+            let luminance = (0.299 * color.r as f32 + 0.587 * color.g as f32 + 0.114 * color.b as f32).round() as i32;
+            Color::rgb(luminance, luminance, luminance)
+        })
+    }
+
+    /// Invert every color in a stored palette
+    pub fn invert(&mut self, name: &str) -> Result<(), PaletteError> {
+        self.map_colors(name, |color| {
+            let color = color.clamp();
+            Color::rgb(255 - color.r, 255 - color.g, 255 - color.b)
+        })
+    }
+
+    /// Replace every color in a stored palette that encodes to the same GBA value as `from`,
+    /// returning how many were replaced
+    pub fn replace_color(&mut self, name: &str, from: Color, to: Color) -> Result<usize, PaletteError> {
+        // compare encoded values so a color that rounds to the same GBA color still matches
+        let encoded = self.encode_colors(&[from, to]);
+        let (from, to) = (encoded[0], encoded[1]);
+        let mut colors = self.load_palette_i32(name.to_string())?;
+
+        let mut replaced = 0;
+        for value in colors.iter_mut().filter(|value| **value == from) {
+            *value = to;
+            replaced += 1;
+        }
+        if replaced > 0 {
+            self.store_palette_i32(name.to_string(), colors);
+        }
+        Ok(replaced)
+    }
+
+    /// Snap every color in a stored palette to the 6x6x6 web-safe cube. Palettes hold GBA colors,
+    /// which can't show most of the cube exactly, so what's stored is the GBA color nearest each
+    /// web-safe one. Its channels read back a few steps off the web-safe levels, and snapping
+    /// it again changes nothing
+    pub fn snap_websafe(&mut self, name: &str) -> Result<(), PaletteError> {
+        self.map_colors(name, |color| color.to_websafe())
+    }
+
+    /// Shuffle the colors in a stored palette, the same seed always gives the same order
+    pub fn shuffle_palette(&mut self, name: &str, seed: u64) -> Result<(), PaletteError> {
+        let mut colors = self.load_palette_i32(name.to_string())?;
+        let skip = if self.preserve_transparency { cmp::min(1, colors.len()) } else { 0 };
+        SplitMix64::new(seed).shuffle(&mut colors[skip..]);
+        self.store_palette_i32(name.to_string(), colors);
+        Ok(())
+    }
+
+    /// Shuffle every stored character palette from one master seed and write them into the ROM
+    pub fn randomize_all(&mut self, seed: u64) -> Result<(), PaletteError> {
+        for character in CHARACTERS.iter() {
+            if self.has_palette(character.name) {
+                self.shuffle_palette(character.name, random::derive_seed(seed, character.name))?;
+                self.write_palette(character)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Mix two stored palettes of the same length color by color and store the result under `dst`,
+    /// `t` of 0 gives `a` and 1 gives `b`
+    pub fn blend_palettes(&mut self, a: &str, b: &str, t: f32, dst: String) -> Result<(), PaletteError> {
+        let a_colors = self.load_palette_colors(a.to_string())?;
+        let b_colors = self.load_palette_colors(b.to_string())?;
+        if a_colors.len() != b_colors.len() {
+            return Err(PaletteError::WrongLength { expected: a_colors.len(), actual: b_colors.len() });
+        }
+
+        let lerp = |a: i32, b: i32| (a as f32 * (1.0 - t) + b as f32 * t).round() as i32;
+        let colors = a_colors.iter().zip(b_colors.iter())
+            .map(|(a, b)| Color::rgb(lerp(a.r, b.r), lerp(a.g, b.g), lerp(a.b, b.b)).clamp())
+            .collect();
+        self.store_palette_colors(dst, colors);
+        Ok(())
+    }
+
+    /// The mean distance by `metric` between the colors at each index of two stored palettes
+    pub fn palette_distance(&mut self, a: &str, b: &str, metric: ColorMetric) -> Result<f64, PaletteError> {
+        let a_colors = self.load_palette_colors(a.to_string())?;
+        let b_colors = self.load_palette_colors(b.to_string())?;
+        if a_colors.len() != b_colors.len() {
+            return Err(PaletteError::WrongLength { expected: a_colors.len(), actual: b_colors.len() });
+        }
+        if a_colors.is_empty() {
+            return Ok(0.0);
+        }
+
+        let total: f64 = a_colors.iter().zip(b_colors.iter())
+            .map(|(&a, &b)| metric_distance(a, b, metric))
+            .sum();
+        Ok(total / a_colors.len() as f64)
+    }
+
+    /// Compare two stored palettes, returning the index and both colors of every slot that differs
+    pub fn diff(&mut self, a: &str, b: &str) -> Result<Vec<(usize, Color, Color)>, PaletteError> {
+        let a_colors = self.load_palette_i32(a.to_string())?;
+        let b_colors = self.load_palette_i32(b.to_string())?;
+        let changes = diff_values(&a_colors, &b_colors)?;
+        Ok(self.decode_changes(&changes))
+    }
+
+    /// Every character with a stored palette, as (name, palette offset, colors)
+    pub fn iter_character_palettes(&mut self) -> impl Iterator<Item = (&'static str, u64, Vec<Color>)> {
+        let mut entries = Vec::new();
+        for character in CHARACTERS.iter() {
+            if !self.has_palette(character.name) {
+                continue;
+            }
+            if let Ok(offset) = self.palette_offset(character) {
+                let colors = self.load_palette_colors(character.name.to_string()).unwrap();
+                entries.push((character.name, offset, colors));
+            }
+        }
+        entries.into_iter()
+    }
+
+    /// Read all the palettes in the ROM and store them
+    pub fn read_palettes(&mut self) -> Result<(), PaletteError> {
+        self.read_palettes_with_progress(|_, _| ())
+    }
+
+    /// Read all the palettes in the ROM like `read_palettes`, calling `progress` with the number
+    /// of characters read so far and the total after each one
+    pub fn read_palettes_with_progress(&mut self, mut progress: impl FnMut(usize, usize)) -> Result<(), PaletteError> {
+        io_debug!("reading {} character palettes", CHARACTERS.len());
+        for (index, character) in CHARACTERS.iter().enumerate() {
+            self.read_palette(character)?;
+            progress(index + 1, CHARACTERS.len());
+        }
+        Ok(())
+    }
+
+    /// The palette offset of one of a character's palette slots in the current region
+    fn slot_offset(&self, character: &Character, slot: usize) -> Result<u64, PaletteError> {
+        if slot >= character.palette_slots() {
+            return Err(PaletteError::IndexOutOfBounds {
+                name: character.name.to_string(),
+                index: slot,
+                len: character.palette_slots(),
+            });
+        }
+        if slot == 0 {
+            return self.palette_offset(character);
+        }
+        // like the main offsets, the extra ones come from the US release
+        match self.region {
+            Region::Usa => check_offset(&character.slot_name(slot), character.extra_palette_offsets[slot - 1]),
+            region => Err(PaletteError::UnsupportedRegion(region)),
+        }
+    }
+
+    /// Read every palette slot of a character and store each under `Character::slot_name`
+    pub fn read_palette(&mut self, character: &Character) -> Result<(), PaletteError> {
+        for slot in 0..character.palette_slots() {
+            self.read_palette_slot(character, slot)?;
+        }
+        Ok(())
+    }
+
+    /// Throw away every edit to a character's palettes by reading them from the ROM again,
+    /// along with their undo history and anything staged for them
+    pub fn revert(&mut self, character: &Character) -> Result<(), PaletteError> {
+        self.read_palette(character)?;
+        for slot in 0..character.palette_slots() {
+            let key = palette_key(&character.slot_name(slot));
+            self.undo_stack.retain(|(snapshot_key, _, _)| *snapshot_key != key);
+            self.redo_stack.retain(|(snapshot_key, _, _)| *snapshot_key != key);
+            self.staged.remove(&key);
+        }
+        Ok(())
+    }
+
+    /// Read one of a character's palette slots and store it under `Character::slot_name`
+    pub fn read_palette_slot(&mut self, character: &Character, slot: usize) -> Result<(), PaletteError> {
+        let offset = self.slot_offset(character, slot)?;
+        let name = character.slot_name(slot);
+        io_debug!("reading {} bytes of {}'s palette at {:#X}", character.color_count * 2, name, offset);
+        self.read_palette_at(name.clone(), offset, character.color_count).map_err(|error| {
+            io_warn!("reading {}'s palette at {:#X} failed: {}", name, offset, error);
+            error
+        })
+    }
+
+    /// Read every palette in `COMMON_PALETTES` and store each under its name
+    pub fn read_common_palettes(&mut self) -> Result<(), PaletteError> {
+        for palette in COMMON_PALETTES.iter() {
+            // like the character offsets, these come from the US release
+            if self.region != Region::Usa {
+                return Err(PaletteError::UnsupportedRegion(self.region));
+            }
+            io_debug!("reading the {} palette at {:#X}", palette.name, palette.offset);
+            self.read_palette_at(String::from(palette.name), palette.offset, palette.color_count)?;
+        }
+        Ok(())
+    }
+
+    /// Read `count` colors starting at any offset in the ROM and store them under `name`
+    pub fn read_palette_at(&mut self, name: String, offset: u64, count: usize) -> Result<(), PaletteError> {
+        let colors = self.read_values_at(offset, count).map_err(|error| error.at(&name, offset))?;
+        self.store_from_rom(name, colors);
+        Ok(())
+    }
+
+    /// Read up to `max_frames` 16 color palettes stored back to back from `start_offset`, stopping
+    /// at the first block that doesn't look like a palette, and store them as `name#0`, `name#1`...
+    /// Returns how many frames were found
+    pub fn detect_animation_frames(&mut self, name: &str, start_offset: u64, max_frames: usize) -> Result<usize, PaletteError> {
+        let frame_bytes = PALETTE_SIZE * 2;
+        for frame in 0..max_frames {
+            let mut bytes = vec![0u8; frame_bytes];
+            let offset = start_offset + (frame * frame_bytes) as u64;
+            let read = {
+                let mut file = self.file.lock().unwrap();
+                file.seek(SeekFrom::Start(offset)).and_then(|_| file.read_exact(&mut bytes))
+            };
+            match read {
+                Ok(()) => (),
+                Err(ref error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(frame),
+                Err(error) => return Err(PaletteError::from(error).at(&format!("{}#{}", name, frame), offset)),
+            }
+            if !looks_like_palette(&bytes) {
+                return Ok(frame);
+            }
+            let colors = decode_palette(&bytes)?;
+            self.store_from_rom(format!("{}#{}", name, frame), colors);
+        }
+        Ok(max_frames)
+    }
+
+    /// Read a character's palette as it is in the ROM, without storing it or touching the stored copy
+    pub fn peek_palette(&mut self, character: &Character) -> Result<Vec<Color>, PaletteError> {
+        let offset = self.palette_offset(character)?;
+        let values = self.read_values_at(offset, character.color_count).map_err(|error| error.at(character.name, offset))?;
+        Ok(self.decode_values(&values))
+    }
+
+    /// Read and decode `count` colors starting at `offset`
+    fn read_values_at(&self, offset: u64, count: usize) -> Result<Vec<i32>, PaletteError> {
+        // hold the lock across the seek and read so no one can move the position in between
+        let mut color_buffer = vec![0u8; count * 2];
+        {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut color_buffer[..])?;
+        }
+        decode_palette(&color_buffer)
+    }
+
+    /// Store a palette fresh from the ROM, it has nothing to write back
+    fn store_from_rom(&mut self, name: String, colors: Vec<i32>) {
+        let key = palette_key(&name);
+        self.dirty.remove(&key);
+        self.display_names.insert(key.clone(), name);
+        self.palettes.insert(key, colors);
+    }
+
+    /// Read every character palette with a single seek and read when they sit close together,
+    /// instead of a seek and read per palette, falling back to `read_palettes` otherwise.
+    /// The US table spans about 3 MiB, so it's read in one go with 1 seek instead of 10. Reading
+    /// the whole span is slower than 10 small reads once the ROM is in the page cache, so this
+    /// only pays off where each seek is expensive, like a cold disk or a network share
+    pub fn read_palettes_bulk(&mut self) -> Result<(), PaletteError> {
+        self.read_characters_bulk(&CHARACTERS)
+    }
+
+    /// Read every palette slot of `characters` like `read_palettes_bulk`
+    fn read_characters_bulk(&mut self, characters: &[Character]) -> Result<(), PaletteError> {
+        let mut slots = Vec::new();
+        for character in characters.iter() {
+            for slot in 0..character.palette_slots() {
+                slots.push((character.slot_name(slot), self.slot_offset(character, slot)?, character.color_count * 2));
+            }
+        }
+
+        let start = slots.iter().map(|&(_, offset, _)| offset).min().unwrap_or(0);
+        let end = slots.iter().map(|&(_, offset, length)| offset + length as u64).max().unwrap_or(0);
+        if end - start > BULK_READ_LIMIT {
+            for character in characters.iter() {
+                self.read_palette(character)?;
+            }
+            return Ok(());
+        }
+
+        let mut buffer = vec![0u8; (end - start) as usize];
+        let read = {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(start)).and_then(|_| file.read_exact(&mut buffer[..]))
+        };
+        read.map_err(|error| PaletteError::from(error).at("every character", start))?;
+
+        for (name, offset, length) in slots {
+            let from = (offset - start) as usize;
+            let colors = decode_palette(&buffer[from..from + length])?;
+            self.store_from_rom(name, colors);
+        }
+        Ok(())
+    }
+
+    /// Write every stored character palette into the ROM
+    pub fn write_palettes(&mut self) -> Result<(), PaletteError> {
+        self.write_palettes_with_progress(|_, _| ())
+    }
+
+    /// Write every stored character palette like `write_palettes`, calling `progress` with the
+    /// number of characters handled so far and the total after each one, stored or not
+    pub fn write_palettes_with_progress(&mut self, mut progress: impl FnMut(usize, usize)) -> Result<(), PaletteError> {
+        for (index, character) in CHARACTERS.iter().enumerate() {
+            if self.has_palette(character.name) {
+                self.write_palette(character)?;
+            }
+            progress(index + 1, CHARACTERS.len());
+        }
+        Ok(())
+    }
+
+    /// Write the character palettes that were edited since they were last read or written
+    pub fn write_dirty(&mut self) -> Result<(), PaletteError> {
+        for character in CHARACTERS.iter() {
+            for slot in 0..character.palette_slots() {
+                if self.dirty.contains(&palette_key(&character.slot_name(slot))) {
+                    self.write_palette_slot(character, slot)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Store a copy of one palette for every target character and write them all into the ROM
+    pub fn apply_palette_to(&mut self, src_name: &str, targets: &[&Character]) -> Result<(), PaletteError> {
+        // check everything up front so a bad name doesn't leave half the targets written
+        let colors = self.load_palette_i32(src_name.to_string())?;
+        for character in targets.iter() {
+            self.palette_offset(character)?;
+            check_length(character, colors.len())?;
+        }
+
+        for character in targets.iter() {
+            self.store_palette_i32(character.name.to_string(), colors.clone());
+            self.write_palette(character)?;
+        }
+        Ok(())
+    }
+
+    /// Exchange the stored palettes of two characters and write both into the ROM
+    pub fn swap_palettes(&mut self, a: &Character, b: &Character) -> Result<(), PaletteError> {
+        let a_colors = self.load_palette_i32(a.name.to_string())?;
+        let b_colors = self.load_palette_i32(b.name.to_string())?;
+        self.palette_offset(a)?;
+        self.palette_offset(b)?;
+        check_length(a, b_colors.len())?;
+        check_length(b, a_colors.len())?;
+
+        // swapping a character with itself changes nothing
+        if a.name == b.name {
+            return Ok(());
+        }
+
+        self.store_palette_i32(a.name.to_string(), b_colors);
+        self.store_palette_i32(b.name.to_string(), a_colors);
+        self.write_palette(a)?;
+        self.write_palette(b)
+    }
+
+    /// Write the palette stored for a character into the ROM
+    pub fn write_palette(&mut self, character: &Character) -> Result<(), PaletteError> {
+        self.write_palette_slot(character, 0)?;
+        // the other slots only go back if they were read or stored
+        for slot in 1..character.palette_slots() {
+            if self.has_palette(&character.slot_name(slot)) {
+                self.write_palette_slot(character, slot)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the palette stored under `Character::slot_name` to that slot's offset
+    pub fn write_palette_slot(&mut self, character: &Character, slot: usize) -> Result<(), PaletteError> {
+        let offset = self.slot_offset(character, slot)?;
+        let name = character.slot_name(slot);
+        let colors = self.load_palette_i32(name.clone())?;
+        check_length(character, colors.len())?;
+
+        let bytes = encode_palette(&colors);
+        io_debug!("writing {} bytes of {}'s palette at {:#X}", bytes.len(), name, offset);
+        self.write_at(offset, &bytes).map_err(|error| {
+            io_warn!("writing {}'s palette at {:#X} failed: {}", name, offset, error);
+            error.at(&name, offset)
+        })?;
+        if !self.dry_run {
+            self.dirty.remove(&palette_key(&name));
+        }
+        Ok(())
+    }
+
+    /// Write the palette stored for a character, then read it back to make sure it landed.
+    /// Inside a transaction the read back waits until the transaction's writes are made
+    pub fn write_palette_verified(&mut self, character: &Character) -> Result<(), PaletteError> {
+        self.write_palette(character)?;
+        if self.dry_run {
+            return Ok(());
+        }
+
+        let offset = self.palette_offset(character)?;
+        let expected = encode_palette(&self.load_palette_i32(character.name.to_string())?);
+        if self.transaction.is_some() {
+            self.pending_verifications.push((character.name.to_string(), offset, expected));
+            return Ok(());
+        }
+        self.verify_at(character.name, offset, &expected)
+    }
+
+    /// Read back the palette of `character` at `offset`, failing if it isn't `expected`
+    fn verify_at(&mut self, character: &str, offset: u64, expected: &[u8]) -> Result<(), PaletteError> {
+        let mut actual = vec![0u8; expected.len()];
+        let read_back = {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(offset)).and_then(|_| file.read_exact(&mut actual[..]))
+        };
+        read_back.map_err(|error| PaletteError::from(error).at(character, offset))?;
+
+        if actual != expected {
+            // the ROM still doesn't have the palette, so it still needs writing
+            self.dirty.insert(palette_key(character));
+            return Err(PaletteError::VerifyFailed { character: character.to_string(), offset });
+        }
+        Ok(())
+    }
+
+    /// Store a bundled preset for a character and write it into the ROM
+    pub fn apply_preset(&mut self, character: &Character, preset: &str) -> Result<(), PaletteError> {
+        let colors = presets::preset_by_name(preset).ok_or_else(|| PaletteError::UnknownPreset {
+            name: preset.to_string(),
+            available: presets::preset_names(),
+        })?;
+        check_length(character, colors.len())?;
+        self.store_palette_i32(character.name.to_string(), colors.to_vec());
+        self.write_palette(character)
+    }
+
+    /// Point characters whose stored palette is identical to an earlier character's at the
+    /// earlier copy, returning the (duplicate, canonical) names merged. Characters whose palette
+    /// pointer isn't known, or that already share the canonical offset, are left alone, and if
+    /// no character's pointer is known it fails with `NoPointer` rather than merging nothing
+    pub fn deduplicate(&mut self) -> Result<Vec<(String, String)>, PaletteError> {
+        self.deduplicate_characters(&CHARACTERS)
+    }
+
+    /// Merge identical palettes like `deduplicate`, among `characters` only
+    pub fn deduplicate_characters(&mut self, characters: &[Character]) -> Result<Vec<(String, String)>, PaletteError> {
+        if characters.iter().all(|character| character.pointer().is_none()) {
+            if let Some(character) = characters.first() {
+                return Err(PaletteError::NoPointer(character.name.to_string()));
+            }
+        }
+        let mut canonical: HashMap<u64, Vec<&Character>> = HashMap::new();
+        let mut merged = Vec::new();
+        for character in characters.iter() {
+            if !self.has_palette(character.name) {
+                continue;
+            }
+            let fingerprint = self.palette_fingerprint(character.name)?;
+            let original = {
+                let values = self.load_palette_ref(character.name)?;
+                let candidates = canonical.entry(fingerprint).or_default();
+                // the fingerprint narrows it down, the values have to match exactly
+                let found = candidates.iter().cloned()
+                    .find(|original| self.load_palette_ref(original.name).ok() == Some(values));
+                if found.is_none() {
+                    candidates.push(character);
+                }
+                found
+            };
+
+            let original = match original {
+                Some(original) => original,
+                None => continue,
+            };
+            let offset = self.palette_offset(original)?;
+            if character.pointer().is_none() || self.palette_offset(character)? == offset {
+                continue;
+            }
+            self.repoint_palette(character, offset)?;
+            merged.push((character.name.to_string(), original.name.to_string()));
+        }
+        Ok(merged)
+    }
+
+    /// Move a character's palette to `new_offset` and point the ROM at the new copy
+    pub fn repoint_palette(&mut self, character: &Character, new_offset: u64) -> Result<(), PaletteError> {
+        let pointer = character.pointer().ok_or_else(|| PaletteError::NoPointer(character.name.to_string()))?;
+        let bytes = encode_palette(&self.load_palette_i32(character.name.to_string())?);
+        check_offset(character.name, new_offset)?;
+
+        self.write_at(new_offset, &bytes).map_err(|error| error.at(character.name, new_offset))?;
+        self.write_at(pointer, &rom::rom_pointer_bytes(new_offset)).map_err(|error| error.at(character.name, pointer))?;
+        if !self.dry_run {
+            self.repointed.insert(character.name.to_string(), new_offset);
+            self.dirty.remove(&palette_key(character.name));
+        }
+        Ok(())
+    }
+
+    /// Export a stored palette as raw BGR555 values, two bytes each. The ROM is always
+    /// little endian, big endian is only for tools that expect it
+    pub fn export_raw(&self, name: &str, order: ByteOrder, writer: &mut impl Write) -> Result<(), PaletteError> {
+        let mut bytes = encode_palette(self.load_palette_ref(name)?);
+        if order == ByteOrder::Big {
+            for pair in bytes.chunks_mut(2) {
+                pair.swap(0, 1);
+            }
+        }
+        writer.write_all(&bytes)?;
+        Ok(())
+    }
+
+    /// Export a stored palette in the JASC-PAL text format used by Paint Shop Pro
+    pub fn export_jasc_pal(&mut self, name: &str, writer: &mut impl Write) -> Result<(), PaletteError> {
+        let colors = self.load_palette_colors(name.to_string())?;
+        writeln!(writer, "JASC-PAL")?;
+        writeln!(writer, "0100")?;
+        writeln!(writer, "{}", colors.len())?;
+        for color in colors.iter() {
+            writeln!(writer, "{} {} {}", color.r, color.g, color.b)?;
+        }
+        Ok(())
+    }
+
+    /// Export a stored palette as a GIMP .gpl file
+    pub fn export_gpl(&mut self, name: &str, writer: &mut impl Write) -> Result<(), PaletteError> {
+        let colors = self.load_palette_colors(name.to_string())?;
+        writeln!(writer, "GIMP Palette")?;
+        writeln!(writer, "Name: {}", name)?;
+        writeln!(writer, "Columns: {}", PALETTE_SIZE)?;
+        writeln!(writer, "#")?;
+        for (index, color) in colors.iter().enumerate() {
+            writeln!(writer, "{:3} {:3} {:3}\tIndex {}", color.r, color.g, color.b, index)?;
+        }
+        Ok(())
+    }
+
+    /// Export a stored palette as a JSON array of [r, g, b] colors
+    pub fn export_palette_json(&mut self, name: &str, writer: &mut impl Write) -> Result<(), PaletteError> {
+        let colors = self.load_palette_colors(name.to_string())?;
+        serde_json::to_writer_pretty(writer, &colors)?;
+        Ok(())
+    }
+
+    /// Export every stored palette to `dir/<name>.<extension>`, creating `dir` if it's missing.
+    /// Nothing is written if any name has a path separator or is `.` or `..`
+    pub fn export_all(&mut self, dir: &Path, format: PaletteFormat) -> Result<(), PaletteError> {
+        let names: Vec<String> = self.palette_names().iter().map(|name| name.to_string()).collect();
+        if let Some(name) = names.iter().find(|name| !is_plain_file_name(name)) {
+            return Err(PaletteError::UnsafeFileName(name.to_string()));
+        }
+        fs::create_dir_all(dir)?;
+        for name in names {
+            let mut file = File::create(dir.join(format!("{}.{}", name, format.extension())))?;
+            self.export_as(&name, format, &mut file)?;
+        }
+        Ok(())
+    }
+
+    /// Export a stored palette in any of the bulk formats
+    pub fn export_as(&mut self, name: &str, format: PaletteFormat, writer: &mut impl Write) -> Result<(), PaletteError> {
+        match format {
+            PaletteFormat::Jasc => self.export_jasc_pal(name, writer),
+            PaletteFormat::Gpl => self.export_gpl(name, writer),
+            PaletteFormat::Json => self.export_palette_json(name, writer),
+            PaletteFormat::PngSwatch => self.export_png_swatch(name, writer, MONTAGE_SWATCH_SIZE),
+            PaletteFormat::CArray => self.export_c_array(name, writer),
+            PaletteFormat::Aseprite => self.export_aseprite_palette(name, writer),
+        }
+    }
+
+    /// Import a palette in any of the bulk formats and store it under `name`
+    pub fn import_as(&mut self, name: String, format: PaletteFormat, reader: &mut impl Read) -> Result<(), PaletteError> {
+        match format {
+            PaletteFormat::Jasc => self.import_jasc_pal(name, reader),
+            PaletteFormat::Gpl => self.import_gpl(name, reader),
+            PaletteFormat::Json => self.import_palette_json(name, reader),
+            PaletteFormat::PngSwatch => self.import_png_swatch(name, reader),
+            PaletteFormat::CArray => self.import_c_array(name, reader),
+            PaletteFormat::Aseprite => self.import_aseprite_palette(name, reader),
+        }
+    }
+
+    /// Export a stored palette as a PNG strip with one `swatch_size` square per color
+    pub fn export_png_swatch(&mut self, name: &str, writer: &mut impl Write, swatch_size: u32) -> Result<(), PaletteError> {
+        let colors = self.load_palette_colors(name.to_string())?;
+        write_swatch_png(&[colors], writer, swatch_size)
+    }
+
+    /// Export every stored palette as one PNG, a row per palette in `palette_names` order
+    pub fn export_montage_png(&mut self, writer: &mut impl Write) -> Result<(), PaletteError> {
+        let names: Vec<String> = self.palette_names().iter().map(|name| name.to_string()).collect();
+        let mut rows = Vec::with_capacity(names.len());
+        for name in names {
+            rows.push(self.load_palette_colors(name)?);
+        }
+        write_swatch_png(&rows, writer, MONTAGE_SWATCH_SIZE)
+    }
+
+    /// Export stored palettes as a looping GIF, each one a frame shown for `delay_ms` drawn
+    /// as a strip of swatches. Every frame needs the same number of colors
+    pub fn export_animation_gif(&self, frame_names: &[&str], writer: &mut impl Write, delay_ms: u16) -> Result<(), PaletteError> {
+        let mut frames = Vec::with_capacity(frame_names.len());
+        for &name in frame_names.iter() {
+            let colors = self.load_palette_colors(name.to_string())?;
+            if let Some(first) = frames.first().map(|first: &Vec<Color>| first.len()) {
+                if colors.len() != first {
+                    return Err(PaletteError::FrameLength { name: name.to_string(), expected: first, found: colors.len() });
+                }
+            }
+            frames.push(colors);
+        }
+
+        let count = match frames.first() {
+            Some(first) if !first.is_empty() => first.len(),
+            _ => return Err(PaletteError::Malformed("an animation needs at least one frame of colors".to_string())),
+        };
+        let swatch_size = MONTAGE_SWATCH_SIZE as usize;
+        let width = count * swatch_size;
+        // every row of the strip is the same, each pixel is the index of its swatch
+        let row: Vec<u8> = (0..width).map(|x| (x / swatch_size) as u8).collect();
+        let buffer: Vec<u8> = row.iter().cycle().take(width * swatch_size).cloned().collect();
+
+        let mut encoder = gif::Encoder::new(writer, width as u16, swatch_size as u16, &[])?;
+        encoder.set(gif::Repeat::Infinite)?;
+        for colors in frames {
+            let frame = gif::Frame {
+                width: width as u16,
+                height: swatch_size as u16,
+                // GIF delays are in hundredths of a second
+                delay: delay_ms / 10,
+                palette: Some(colors.iter().flat_map(|c| vec![c.r as u8, c.g as u8, c.b as u8]).collect()),
+                buffer: buffer.as_slice().into(),
+                ..gif::Frame::default()
+            };
+            encoder.write_frame(&frame)?;
+        }
+        Ok(())
+    }
+
+    /// Import a palette from a PNG strip of square swatches written by `export_png_swatch`,
+    /// sampling the center of each swatch
+    pub fn import_png_swatch(&mut self, name: String, reader: &mut impl Read) -> Result<(), PaletteError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let colors = decode_png_swatch(&bytes)?;
+        self.store_palette_colors(name, colors);
+        Ok(())
+    }
+
+    /// Import every `<name>.<extension>` file in `dir`, storing each under its name, and return
+    /// the names imported. Fails with `ImportFailed` listing every file that couldn't be imported
+    pub fn import_all(&mut self, dir: &Path, format: PaletteFormat) -> Result<Vec<String>, PaletteError> {
+        let (names, failures) = self.import_all_lenient(dir, format)?;
+        if !failures.is_empty() {
+            return Err(PaletteError::ImportFailed(failures));
+        }
+        Ok(names)
+    }
+
+    /// Import every `<name>.<extension>` file in `dir` like `import_all`, but skip the files that
+    /// can't be imported, returning them alongside the names that were
+    pub fn import_all_lenient(&mut self, dir: &Path, format: PaletteFormat) -> Result<(Vec<String>, Vec<ImportFailure>), PaletteError> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let matches = path.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| extension.eq_ignore_ascii_case(format.extension()));
+            if matches && path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut names = Vec::new();
+        let mut failures = Vec::new();
+        for path in paths {
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(stem) => stem.to_string(),
+                None => continue,
+            };
+            let result = File::open(&path).map_err(PaletteError::from)
+                .and_then(|mut file| self.import_as(name.clone(), format, &mut file));
+            match result {
+                Ok(()) => names.push(name),
+                Err(error) => failures.push((path, error)),
+            }
+        }
+        Ok((names, failures))
+    }
+
+    /// Import a palette from a JASC-PAL file written by `export_jasc_pal`
+    pub fn import_jasc_pal(&mut self, name: String, reader: &mut impl Read) -> Result<(), PaletteError> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+
+        let mut lines = text.lines().map(|line| line.trim());
+        if lines.next() != Some("JASC-PAL") || lines.next() != Some("0100") {
+            return Err(PaletteError::Malformed("missing JASC-PAL header".to_string()));
+        }
+        let count: usize = lines.next()
+            .and_then(|count| count.parse().ok())
+            .ok_or_else(|| PaletteError::Malformed("missing color count".to_string()))?;
+
+        // the count comes from the file, a bogus one shouldn't allocate all of memory
+        let mut colors = Vec::with_capacity(cmp::min(count, 256));
+        for (line_index, line) in lines.filter(|line| !line.is_empty()).take(count).enumerate() {
+            let channels: Vec<Option<i32>> = line.split_whitespace()
+                .map(|c| c.parse().ok().filter(|v| 0 <= *v && *v <= 255))
+                .collect();
+            match channels.as_slice() {
+                &[Some(r), Some(g), Some(b)] => colors.push(Color::rgb(r, g, b)),
+                _ => return Err(PaletteError::Malformed(format!("invalid color {}", line_index))),
+            }
+        }
+        if colors.len() < count {
+            return Err(PaletteError::PaletteTooShort { expected: count, found: colors.len() });
+        }
+
+        self.store_palette_colors(name, colors);
+        Ok(())
+    }
+
+    /// Import a palette from a GIMP .gpl file and store it
+    pub fn import_gpl(&mut self, name: String, reader: &mut impl Read) -> Result<(), PaletteError> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+
+        let mut lines = text.lines();
+        match lines.next() {
+            Some(header) if header.trim() == "GIMP Palette" => (),
+            _ => return Err(PaletteError::Malformed("missing GIMP Palette header".to_string())),
+        }
+
+        let mut colors = Vec::new();
+        for (line_index, line) in lines.enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("Name:") || line.starts_with("Columns:") {
+                continue;
+            }
+
+            // entries are "R G B optional-name", separated by spaces or tabs
+            let channels: Vec<Option<i32>> = line.split_whitespace()
+                .take(3)
+                .map(|c| c.parse().ok().filter(|v| 0 <= *v && *v <= 255))
+                .collect();
+            match channels.as_slice() {
+                &[Some(r), Some(g), Some(b)] => colors.push(Color::rgb(r, g, b)),
+                _ => return Err(PaletteError::Malformed(format!("invalid color on line {}", line_index + 2))),
+            }
+        }
+
+        if colors.len() < PALETTE_SIZE {
+            return Err(PaletteError::PaletteTooShort { expected: PALETTE_SIZE, found: colors.len() });
+        }
+
+        self.store_palette_colors(name, colors);
+        Ok(())
+    }
+
+    /// Export every stored palette as a JSON object of names to [r, g, b] colors
+    pub fn export_json(&mut self, writer: &mut impl Write) -> Result<(), PaletteError> {
+        let names: Vec<String> = self.palette_names().iter().map(|name| name.to_string()).collect();
+        let mut palettes = BTreeMap::new();
+        for name in names {
+            let colors = self.load_palette_colors(name.clone())?;
+            palettes.insert(name, colors);
+        }
+        serde_json::to_writer_pretty(writer, &palettes)?;
+        Ok(())
+    }
+
+    /// Export a stored palette as an empty 8x8 indexed Aseprite sprite, index 0 is transparent
+    pub fn export_aseprite_palette(&mut self, name: &str, writer: &mut impl Write) -> Result<(), PaletteError> {
+        let colors = self.load_palette_colors(name.to_string())?;
+        if colors.is_empty() || colors.len() > 256 {
+            return Err(PaletteError::Malformed(format!("an Aseprite palette can't have {} colors", colors.len())));
+        }
+        writer.write_all(&encode_aseprite(&colors, name))?;
+        Ok(())
+    }
+
+    /// Import `count` little endian BGR555 values from a raw dump, the counterpart of
+    /// `export_raw` and the same bytes `read_palette` reads from the ROM
+    pub fn import_raw(&mut self, name: String, reader: &mut impl Read, count: usize) -> Result<(), PaletteError> {
+        let mut bytes = vec![0u8; count * 2];
+        reader.read_exact(&mut bytes)?;
+        let colors = decode_palette(&bytes)?;
+        self.store_palette_i32(name, colors);
+        Ok(())
+    }
+
+    /// Import the palette of an Aseprite sprite, from its palette chunk or the old style one
+    /// in files from before Aseprite 1.2, keeping each color's alpha
+    pub fn import_aseprite_palette(&mut self, name: String, reader: &mut impl Read) -> Result<(), PaletteError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let colors = decode_aseprite_palette(&bytes)?;
+        self.store_palette_colors(name, colors);
+        Ok(())
+    }
+
+    /// Import a palette from a JSON array of [r, g, b] colors written by `export_palette_json`
+    pub fn import_palette_json(&mut self, name: String, reader: &mut impl Read) -> Result<(), PaletteError> {
+        let colors: Vec<Color> = serde_json::from_reader(reader)?;
+        self.store_palette_colors(name, colors);
+        Ok(())
+    }
+
+    /// Store every palette from a JSON object written by `export_json`
+    pub fn import_json(&mut self, reader: &mut impl Read) -> Result<(), PaletteError> {
+        let palettes: BTreeMap<String, Vec<Color>> = serde_json::from_reader(reader)?;
+        for (name, colors) in palettes {
+            self.store_palette_colors(name, colors);
+        }
+        Ok(())
+    }
+
+    /// Format the bytes of a stored palette as they sit in the ROM, 8 colors to a line
+    pub fn hex_dump(&self, name: &str) -> Result<String, PaletteError> {
+        let colors = self.get_palette(name)?;
+        let lines: Vec<String> = colors.chunks(8).enumerate().map(|(line, chunk)| {
+            let pairs: Vec<String> = chunk.iter()
+                .map(|value| format!("{:02X} {:02X}", value & 0x00FF, (value & 0xFF00) >> 8))
+                .collect();
+            format!("{:02X}: {}", line * 16, pairs.join("  "))
+        }).collect();
+        Ok(lines.join("\n"))
+    }
+
+    /// Export a stored palette as a C array of raw GBA values
+    pub fn export_c_array(&mut self, name: &str, writer: &mut impl Write) -> Result<(), PaletteError> {
+        let colors = self.load_palette_i32(name.to_string())?;
+        writeln!(writer, "const u16 {}[{}] = {{", palette_identifier(name), colors.len())?;
+        for chunk in colors.chunks(8) {
+            let values: Vec<String> = chunk.iter().map(|&value| format!("0x{:04X}", value)).collect();
+            writeln!(writer, "    {},", values.join(", "))?;
+        }
+        writeln!(writer, "}};")?;
+        Ok(())
+    }
+
+    /// Import a palette from a C array of raw GBA values written by `export_c_array`
+    pub fn import_c_array(&mut self, name: String, reader: &mut impl Read) -> Result<(), PaletteError> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+
+        let start = text.find('{').ok_or_else(|| PaletteError::Malformed("missing {".to_string()))?;
+        let end = text.rfind('}').filter(|&end| end > start)
+            .ok_or_else(|| PaletteError::Malformed("missing }".to_string()))?;
+        let mut colors = Vec::new();
+        for value in text[start + 1..end].split(',').map(|value| value.trim()).filter(|value| !value.is_empty()) {
+            let color = gba_from_hex(value).map_err(|error| PaletteError::Malformed(format!("{}: {}", value, error)))?;
+            colors.push(color);
+        }
+        self.store_palette_i32(name, colors);
+        Ok(())
+    }
+
+    /// Export a stored palette as assembly `.hword` directives of raw GBA values
+    pub fn export_asm(&mut self, name: &str, writer: &mut impl Write) -> Result<(), PaletteError> {
+        let colors = self.load_palette_i32(name.to_string())?;
+        writeln!(writer, "{}:", palette_identifier(name))?;
+        for value in colors.iter() {
+            writeln!(writer, "    .hword 0x{:04X}", value)?;
+        }
+        Ok(())
+    }
+
+    pub fn print_palette(&mut self, character: &Character) -> Result<(), PaletteError> {
+        let converted_colors = self.load_palette_colors(character.name.to_string())?;
+        println!("v== {} ==v", character.name);
+        for convcol in converted_colors.iter() {
+            println!("{}", convcol)
+        }
+        println!("^== {} ==^", character.name);
+        Ok(())
+    }
+
+    /// Print a stored palette as truecolor blocks, pass `color: false` when the output isn't a terminal
+    pub fn print_palette_ansi(&mut self, name: &str, color: bool) -> Result<(), PaletteError> {
+        print!("{}", self.format_palette_ansi(name, color)?);
+        Ok(())
+    }
+
+    /// One line per color with an ANSI 24-bit background block, the index and the hex code
+    pub fn format_palette_ansi(&mut self, name: &str, color: bool) -> Result<String, PaletteError> {
+        let colors = self.load_palette_colors(name.to_string())?;
+        let mut output = String::new();
+        for (index, c) in colors.iter().enumerate() {
+            if color {
+                output.push_str(&format!("\x1b[48;2;{};{};{}m  \x1b[0m ", c.r, c.g, c.b));
+            }
+            output.push_str(&format!("{:>3} {}\n", index, c.to_hex()));
+        }
+        Ok(output)
+    }
+}
+
+/// a character whose palette differs, with the index and both colors of every changed slot
+pub type PaletteDiff = (String, Vec<(usize, Color, Color)>);
+
+/// Read every character palette from two ROMs and compare them, returning each character whose
+/// palette differs along with the index and both colors of every slot that changed
+pub fn diff_roms<A, B>(a: &mut PaletteManager<A>, b: &mut PaletteManager<B>) -> Result<Vec<PaletteDiff>, PaletteError>
+    where A: Read + Write + Seek, B: Read + Write + Seek {
+    a.read_palettes()?;
+    b.read_palettes()?;
+
+    let mut differences = Vec::new();
+    for character in CHARACTERS.iter() {
+        let a_colors = a.load_palette_i32(character.name.to_string())?;
+        let b_colors = b.load_palette_i32(character.name.to_string())?;
+        let changes = diff_values(&a_colors, &b_colors)?;
+        if !changes.is_empty() {
+            differences.push((character.name.to_string(), a.decode_changes(&changes)));
+        }
+    }
+    Ok(differences)
+}
+
+/// the index and both values of every slot that differs between two palettes of the same length
+fn diff_values(a: &[i32], b: &[i32]) -> Result<Vec<(usize, i32, i32)>, PaletteError> {
+    if a.len() != b.len() {
+        return Err(PaletteError::WrongLength { expected: a.len(), actual: b.len() });
+    }
+    Ok(a.iter().zip(b.iter())
+        .enumerate()
+        .filter(|&(_, (a_value, b_value))| a_value != b_value)
+        .map(|(index, (&a_value, &b_value))| (index, a_value, b_value))
+        .collect())
+}
+
+/// the key a palette is stored under, names are matched ignoring case so "Sonic" and "sonic" are one palette
+fn palette_key(name: &str) -> String {
+    name.to_lowercase()
+}
+
+/// make sure a palette offset isn't inside the ROM header
+fn check_offset(name: &str, offset: u64) -> Result<u64, PaletteError> {
+    if offset < MIN_PALETTE_OFFSET {
+        return Err(PaletteError::OffsetInHeader { character: name.to_string(), offset });
+    }
+    Ok(offset)
+}
+
+/// make sure a palette of `length` colors fits exactly where the character's palette goes,
+/// anything longer would spill over whatever follows it in the ROM
+fn check_length(character: &Character, length: usize) -> Result<(), PaletteError> {
+    if length != character.color_count {
+        return Err(PaletteError::WrongLength { expected: character.color_count, actual: length });
+    }
+    Ok(())
+}
+
+/// make sure every character's palette is 16 colors for a 4bpp sprite or 256 for an 8bpp one
+pub fn check_character_table(characters: &[Character]) -> Result<(), PaletteError> {
+    match characters.iter().find(|character| character.color_count != 16 && character.color_count != 256) {
+        Some(character) => Err(PaletteError::BadColorCount { character: character.name.to_string(), count: character.color_count }),
+        None => Ok(()),
+    }
+}
+
+/// decode little endian BGR555 pairs from the ROM
+fn decode_palette(bytes: &[u8]) -> Result<Vec<i32>, PaletteError> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(PaletteError::Malformed(format!("{} bytes is half a color short", bytes.len())));
+    }
+    Ok(bytes.chunks(2).map(|pair| gba_from_le_bytes([pair[0], pair[1]])).collect())
+}
+
+const ASEPRITE_MAGIC: u16 = 0xA5E0;
+const ASEPRITE_FRAME_MAGIC: u16 = 0xF1FA;
+const ASEPRITE_HEADER_SIZE: usize = 128;
+const ASEPRITE_FRAME_HEADER_SIZE: usize = 16;
+const ASEPRITE_OLD_PALETTE_CHUNK: u16 = 0x0004;
+const ASEPRITE_LAYER_CHUNK: u16 = 0x2004;
+const ASEPRITE_PALETTE_CHUNK: u16 = 0x2019;
+
+/// build a one frame indexed Aseprite file with a single empty layer and the palette
+fn encode_aseprite(colors: &[Color], layer: &str) -> Vec<u8> {
+    fn push_u16(bytes: &mut Vec<u8>, value: u16) {
+        bytes.extend_from_slice(&[value as u8, (value >> 8) as u8]);
+    }
+    fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+        bytes.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+    }
+    fn push_chunk(bytes: &mut Vec<u8>, kind: u16, data: &[u8]) {
+        push_u32(bytes, data.len() as u32 + 6);
+        push_u16(bytes, kind);
+        bytes.extend_from_slice(data);
+    }
+
+    let mut layer_chunk = Vec::new();
+    // visible and editable, a normal layer at the top level with normal blending
+    for &value in [3u16, 0, 0, 0, 0, 0].iter() {
+        push_u16(&mut layer_chunk, value);
+    }
+    layer_chunk.extend_from_slice(&[0xFF, 0, 0, 0]);
+    push_u16(&mut layer_chunk, layer.len() as u16);
+    layer_chunk.extend_from_slice(layer.as_bytes());
+
+    let mut palette_chunk = Vec::new();
+    push_u32(&mut palette_chunk, colors.len() as u32);
+    push_u32(&mut palette_chunk, 0);
+    push_u32(&mut palette_chunk, colors.len() as u32 - 1);
+    palette_chunk.extend_from_slice(&[0; 8]);
+    for (index, color) in colors.iter().enumerate() {
+        // no name
+        push_u16(&mut palette_chunk, 0);
+        let alpha = if index == 0 { 0 } else { color.a };
+        palette_chunk.extend_from_slice(&[color.r as u8, color.g as u8, color.b as u8, alpha]);
+    }
+
+    let mut chunks = Vec::new();
+    push_chunk(&mut chunks, ASEPRITE_LAYER_CHUNK, &layer_chunk);
+    push_chunk(&mut chunks, ASEPRITE_PALETTE_CHUNK, &palette_chunk);
+
+    let mut frame = Vec::with_capacity(ASEPRITE_FRAME_HEADER_SIZE + chunks.len());
+    push_u32(&mut frame, (ASEPRITE_FRAME_HEADER_SIZE + chunks.len()) as u32);
+    push_u16(&mut frame, ASEPRITE_FRAME_MAGIC);
+    push_u16(&mut frame, 2);
+    // frame duration in milliseconds
+    push_u16(&mut frame, 100);
+    frame.extend_from_slice(&[0, 0]);
+    push_u32(&mut frame, 2);
+    frame.extend_from_slice(&chunks);
+
+    let mut file = Vec::with_capacity(ASEPRITE_HEADER_SIZE + frame.len());
+    push_u32(&mut file, (ASEPRITE_HEADER_SIZE + frame.len()) as u32);
+    push_u16(&mut file, ASEPRITE_MAGIC);
+    // one frame, the canvas size, 8 bits per pixel for indexed color
+    for &value in [1u16, TILE_SIZE as u16, TILE_SIZE as u16, 8].iter() {
+        push_u16(&mut file, value);
+    }
+    // flags and the old speed field, then two reserved words
+    push_u32(&mut file, 1);
+    push_u16(&mut file, 100);
+    push_u32(&mut file, 0);
+    push_u32(&mut file, 0);
+    // the transparent index, then 3 unused bytes
+    file.extend_from_slice(&[0, 0, 0, 0]);
+    push_u16(&mut file, if colors.len() == 256 { 0 } else { colors.len() as u16 });
+    // square pixels, then the grid position and size
+    file.extend_from_slice(&[1, 1]);
+    for &value in [0u16, 0, 16, 16].iter() {
+        push_u16(&mut file, value);
+    }
+    file.resize(ASEPRITE_HEADER_SIZE, 0);
+    file.extend_from_slice(&frame);
+    file
+}
+
+/// read the palette out of the first frame of an Aseprite file
+fn decode_aseprite_palette(bytes: &[u8]) -> Result<Vec<Color>, PaletteError> {
+    let truncated = || PaletteError::Malformed("Aseprite file ended early".to_string());
+    let u16_at = |at: usize| bytes.get(at..at + 2).map(|b| b[0] as u16 | (b[1] as u16) << 8).ok_or_else(truncated);
+    let u32_at = |at: usize| bytes.get(at..at + 4)
+        .map(|b| b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+        .ok_or_else(truncated);
+
+    if u16_at(4)? != ASEPRITE_MAGIC {
+        return Err(PaletteError::Malformed("not an Aseprite file".to_string()));
+    }
+    let frame = ASEPRITE_HEADER_SIZE;
+    if u16_at(frame + 4)? != ASEPRITE_FRAME_MAGIC {
+        return Err(PaletteError::Malformed("missing Aseprite frame".to_string()));
+    }
+    let chunk_count = match u32_at(frame + 12)? {
+        0 => u16_at(frame + 6)? as u32,
+        count => count,
+    };
+
+    let mut old_palette = None;
+    let mut position = frame + ASEPRITE_FRAME_HEADER_SIZE;
+    for _ in 0..chunk_count {
+        let size = u32_at(position)? as usize;
+        if size < 6 {
+            return Err(PaletteError::Malformed(format!("Aseprite chunk of {} bytes", size)));
+        }
+        let data = position + 6;
+        match u16_at(position + 4)? {
+            ASEPRITE_PALETTE_CHUNK => {
+                let count = cmp::min(u32_at(data)? as usize, 256);
+                let first = u32_at(data + 4)? as usize;
+                let last = u32_at(data + 8)? as usize;
+                let mut colors = vec![Color::rgb(0, 0, 0); count];
+                let mut entry = data + 20;
+                for index in first..last.saturating_add(1) {
+                    let flags = u16_at(entry)?;
+                    let rgba = bytes.get(entry + 2..entry + 6).ok_or_else(truncated)?;
+                    if let Some(color) = colors.get_mut(index) {
+                        *color = Color::rgba(rgba[0] as i32, rgba[1] as i32, rgba[2] as i32, rgba[3]);
+                    }
+                    entry += 6;
+                    // skip the color's name
+                    if flags & 1 != 0 {
+                        entry += 2 + u16_at(entry)? as usize;
+                    }
+                }
+                return Ok(colors);
+            }
+            ASEPRITE_OLD_PALETTE_CHUNK if old_palette.is_none() => {
+                let mut colors = vec![Color::rgb(0, 0, 0); 256];
+                let mut used = 0;
+                let mut index = 0;
+                let mut entry = data + 2;
+                for _ in 0..u16_at(data)? {
+                    let header = bytes.get(entry..entry + 2).ok_or_else(truncated)?;
+                    index += header[0] as usize;
+                    let count = if header[1] == 0 { 256 } else { header[1] as usize };
+                    entry += 2;
+                    for _ in 0..count {
+                        let rgb = bytes.get(entry..entry + 3).ok_or_else(truncated)?;
+                        if let Some(color) = colors.get_mut(index) {
+                            *color = Color::rgb(rgb[0] as i32, rgb[1] as i32, rgb[2] as i32);
+                            used = cmp::max(used, index + 1);
+                        }
+                        index += 1;
+                        entry += 3;
+                    }
+                }
+                colors.truncate(used);
+                old_palette = Some(colors);
+            }
+            _ => (),
+        }
+        position += size;
+    }
+
+    // only files from before Aseprite 1.2 lack the new chunk
+    old_palette.ok_or_else(|| PaletteError::Malformed("no palette in the Aseprite file".to_string()))
+}
+
+/// draw each palette as a row of `swatch_size` squares and encode the image as PNG
+fn write_swatch_png(rows: &[Vec<Color>], writer: &mut impl Write, swatch_size: u32) -> Result<(), PaletteError> {
+    let columns = rows.iter().map(|colors| colors.len()).max().unwrap_or(0) as u32;
+    let mut image = ImageBuffer::<Rgba<u8>, Vec<u8>>::new(columns * swatch_size, rows.len() as u32 * swatch_size);
+
+    for (row, colors) in rows.iter().enumerate() {
+        for (column, color) in colors.iter().enumerate() {
+            // index 0 is the transparent color on the GBA
+            let alpha = if column == 0 { 0 } else { color.a };
+            let pixel = Rgba { data: [color.r as u8, color.g as u8, color.b as u8, alpha] };
+            for y in 0..swatch_size {
+                for x in 0..swatch_size {
+                    image.put_pixel(column as u32 * swatch_size + x, row as u32 * swatch_size + y, pixel);
+                }
+            }
+        }
+    }
+
+    let (width, height) = image.dimensions();
+    PNGEncoder::new(writer).encode(&image.into_raw(), width, height, RGBA(8))?;
+    Ok(())
+}
+
+/// read the colors back out of a PNG strip of square swatches, sampling the center of each
+pub fn decode_png_swatch(bytes: &[u8]) -> Result<Vec<Color>, PaletteError> {
+    let image = image::load_from_memory_with_format(bytes, ImageFormat::PNG)
+        .map_err(|error| PaletteError::Malformed(error.to_string()))?
+        .to_rgba();
+
+    let (width, swatch_size) = image.dimensions();
+    if swatch_size == 0 {
+        return Err(PaletteError::Malformed("empty image".to_string()));
+    }
+    Ok((0..width / swatch_size).map(|column| {
+        let pixel = image.get_pixel(column * swatch_size + swatch_size / 2, swatch_size / 2).data;
+        Color::rgba(pixel[0] as i32, pixel[1] as i32, pixel[2] as i32, pixel[3])
+    }).collect())
+}
+
+/// whether raw palette bytes could be a real palette: not blank, and the unused top bit of
+/// every color clear, which rules out free space and most code and tile data
+fn looks_like_palette(bytes: &[u8]) -> bool {
+    bytes.iter().any(|&byte| byte != 0) && bytes.chunks(2).all(|pair| pair.len() == 2 && pair[1] & 0x80 == 0)
+}
+
+/// encode colors as the little endian BGR555 pairs the ROM stores, keeping the low 16 bits of each
+fn encode_palette(colors: &[i32]) -> Vec<u8> {
+    colors.iter().flat_map(|&value| gba_to_le_bytes(value).to_vec()).collect()
+}
+
+/// fail on the first value outside the 15 bits the GBA reads
+fn check_gba_colors(colors: &[i32]) -> Result<(), PaletteError> {
+    match colors.iter().enumerate().find(|&(_, &value)| !(0..=GBA_COLOR_MASK).contains(&value)) {
+        Some((index, &value)) => Err(PaletteError::InvalidGbaColor { index, value }),
+        None => Ok(()),
+    }
+}
+
+/// whether `name` joined onto a directory stays a file directly inside it
+fn is_plain_file_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    let single = matches!((components.next(), components.next()), (Some(Component::Normal(_)), None));
+    single && !name.contains(['/', '\\'])
+}
+
+/// turn a palette name into a C identifier, "E-102" becomes "e_102_pal"
+fn palette_identifier(name: &str) -> String {
+    let mut identifier: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    if identifier.chars().next().is_none_or(|c| c.is_ascii_digit()) {
+        identifier.insert(0, '_');
+    }
+    identifier.push_str("_pal");
+    identifier
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cmp;
+    use std::fs;
+    use std::io::Cursor;
+    use image::ImageFormat;
+
+    use ::testing;
+    use ::data::SONIC_DATA;
+    use std::fs::OpenOptions;
+
+    #[test]
+    fn loading_a_missing_palette_is_an_error() {
+        let manager = PaletteManager::from_bytes(Vec::new());
+        match manager.load_palette_i32("Sonic".to_string()) {
+            Err(PaletteError::PaletteNotFound(ref name)) => assert_eq!(name, "Sonic"),
+            other => panic!("expected PaletteNotFound, got {:?}", other),
+        }
+        assert!(manager.load_palette_colors("Sonic".to_string()).is_err());
+        assert!(manager.palette("Sonic").is_err());
+    }
+
+    #[test]
+    fn single_colors_can_be_read_and_replaced() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x0000, 0x7FFF]);
+        assert_eq!(manager.get_color("Sonic", 1).unwrap(), Color::rgb(255, 255, 255));
+
+        manager.set_color("Sonic", 0, Color::rgb(255, 0, 0)).unwrap();
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x001F, 0x7FFF]);
+        assert_eq!(manager.get_color("Sonic", 0).unwrap(), Color::rgb(255, 0, 0));
+
+        match manager.get_color("Sonic", 2) {
+            Err(PaletteError::IndexOutOfBounds { index: 2, len: 2, .. }) => (),
+            other => panic!("expected IndexOutOfBounds, got {:?}", other),
+        }
+        assert!(manager.set_color("Sonic", 2, Color::rgb(0, 0, 0)).is_err());
+        assert!(manager.set_color("Tails", 0, Color::rgb(0, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn jasc_pal_exports_list_every_color() {
+        let path = testing::temp_path("jasc.gba");
+        let file = File::create(&path).unwrap();
+        let mut manager = PaletteManager::new(Arc::new(Mutex::new(file)));
+        manager.store_palette_i32("Sonic".to_string(), vec![0x0000, 0x001F, 0x7FFF]);
+        let mut file = Vec::new();
+        manager.export_jasc_pal("Sonic", &mut file).unwrap();
+        assert_eq!(String::from_utf8(file).unwrap(), "JASC-PAL\n0100\n3\n0 0 0\n255 0 0\n255 255 255\n");
+        assert!(manager.export_jasc_pal("Tails", &mut Vec::new()).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn gimp_palettes_import_with_comments_and_names() {
+        let mut text = "GIMP Palette\nName: Test\nColumns: 16\n#\n".to_string();
+        for index in 0..16 {
+            text.push_str(&format!("{} 0 {}\tIndex {}\n", index * 17, 255 - index * 17, index));
+        }
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.import_gpl("Test".to_string(), &mut text.as_bytes()).unwrap();
+        let colors = manager.load_palette_colors("Test".to_string()).unwrap();
+        assert_eq!(colors.len(), 16);
+        assert_eq!(colors[0], Color::rgb(0, 0, 255));
+        assert_eq!(colors[15], Color::rgb(255, 0, 0));
+
+        let mut exported = Vec::new();
+        manager.export_gpl("Test", &mut exported).unwrap();
+        manager.import_gpl("Again".to_string(), &mut &exported[..]).unwrap();
+        assert_eq!(manager.load_palette_i32("Again".to_string()).unwrap(), manager.load_palette_i32("Test".to_string()).unwrap());
+
+        assert!(manager.import_gpl("Short".to_string(), &mut &b"GIMP Palette\n0 0 0\n"[..]).is_err());
+        assert!(manager.import_gpl("Bad".to_string(), &mut &b"JASC-PAL\n"[..]).is_err());
+    }
+
+    #[test]
+    fn write_palettes_writes_every_stored_character() {
+        let file = testing::rom_file(testing::blank_rom());
+        let mut manager = PaletteManager::new(file.clone());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x001F; 16]);
+        manager.store_palette_i32("Tails".to_string(), vec![0x03E0; 16]);
+        let mut calls = Vec::new();
+        manager.write_palettes_with_progress(|done, total| calls.push((done, total))).unwrap();
+
+        assert_eq!(palette_in(&file, SONIC_DATA.palette_offset, 16), vec![0x001F; 16]);
+        assert_eq!(palette_in(&file, TAILS_DATA.palette_offset, 16), vec![0x03E0; 16]);
+        assert_eq!(palette_in(&file, SHADOW_DATA.palette_offset, 16), vec![0; 16]);
+        assert_eq!(calls.len(), CHARACTERS.len());
+        assert_eq!(calls.last(), Some(&(CHARACTERS.len(), CHARACTERS.len())));
+    }
+
+    #[test]
+    fn a_truncated_palette_fails_to_read() {
+        let path = testing::temp_path("truncated.gba");
+        let end = SONIC_DATA.palette_offset as usize + 10;
+        fs::write(&path, vec![0x1Fu8; end]).unwrap();
+        let file = File::open(&path).unwrap();
+        let mut manager = PaletteManager::new(Arc::new(Mutex::new(file)));
+        assert!(manager.read_palette(&SONIC_DATA).is_err());
+        assert!(manager.load_palette_i32("Sonic".to_string()).is_err());
+
+        fs::write(&path, vec![0x1Fu8; end + 22]).unwrap();
+        manager.read_palette(&SONIC_DATA).unwrap();
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x1F1F; 16]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reads_drop_the_unused_top_bit() {
+        let mut bytes = testing::blank_rom();
+        let start = SONIC_DATA.palette_offset as usize;
+        for byte in bytes[start..start + 32].iter_mut() {
+            *byte = 0xFF;
+        }
+        let mut manager = PaletteManager::from_bytes(bytes);
+        manager.read_palette(&SONIC_DATA).unwrap();
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x7FFF; 16]);
+    }
+
+    #[test]
+    fn any_seekable_stream_can_back_a_manager() {
+        let file = Arc::new(Mutex::new(testing::CountingRom::new(Cursor::new(testing::blank_rom()))));
+        let mut manager = PaletteManager::new(file.clone());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x1234; 16]);
+        manager.write_palette(&SONIC_DATA).unwrap();
+        manager.read_palette(&SONIC_DATA).unwrap();
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x1234; 16]);
+        assert!(file.lock().unwrap().reads > 0);
+
+        let bytes = file.lock().unwrap().inner.get_ref().clone();
+        let start = SONIC_DATA.palette_offset as usize;
+        assert_eq!(&bytes[start..start + 2], &[0x34, 0x12]);
+    }
+
+    /// a ROM that hands out one byte per read, like a slow pipe
+    struct TrickleRom(Cursor<Vec<u8>>);
+
+    impl Read for TrickleRom {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            let end = cmp::min(buf.len(), 1);
+            self.0.read(&mut buf[..end])
+        }
+    }
+
+    impl Write for TrickleRom {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    impl Seek for TrickleRom {
+        fn seek(&mut self, from: SeekFrom) -> Result<u64, Error> {
+            self.0.seek(from)
+        }
+    }
+
+    #[test]
+    fn short_reads_are_retried() {
+        let mut bytes = testing::blank_rom();
+        let start = SONIC_DATA.palette_offset as usize;
+        for (index, byte) in bytes[start..start + 32].iter_mut().enumerate() {
+            *byte = index as u8;
+        }
+        let mut whole = PaletteManager::new(testing::rom_file(bytes.clone()));
+        whole.read_palette(&SONIC_DATA).unwrap();
+        let mut trickle = PaletteManager::new(Arc::new(Mutex::new(TrickleRom(Cursor::new(bytes)))));
+        trickle.read_palette(&SONIC_DATA).unwrap();
+        assert_eq!(trickle.load_palette_i32("Sonic".to_string()).unwrap(), whole.load_palette_i32("Sonic".to_string()).unwrap());
+        assert_eq!(whole.load_palette_i32("Sonic".to_string()).unwrap()[0], 0x0100);
+    }
+
+    #[test]
+    fn palettes_can_be_read_from_any_offset() {
+        let mut bytes = testing::blank_rom();
+        bytes[0x123456..0x12345A].copy_from_slice(&[0x1F, 0x00, 0xFF, 0x7F]);
+        let mut manager = PaletteManager::from_bytes(bytes);
+        manager.read_palette_at("Loose".to_string(), 0x123456, 2).unwrap();
+        assert_eq!(manager.load_palette_i32("Loose".to_string()).unwrap(), vec![0x001F, 0x7FFF]);
+        assert!(!manager.dirty_names().contains(&"Loose"));
+        assert!(manager.read_palette_at("Past".to_string(), testing::ROM_SIZE as u64 - 2, 2).is_err());
+    }
+
+    /// the palette `count` colors long at `offset` of an in memory ROM
+    fn palette_in(file: &Arc<Mutex<Cursor<Vec<u8>>>>, offset: u64, count: usize) -> Vec<i32> {
+        let start = offset as usize;
+        decode_palette(&testing::contents(file)[start..start + 2 * count]).unwrap()
+    }
+
+    #[test]
+    fn write_dirty_only_writes_edited_palettes() {
+        let bytes = testing::random_rom(11);
+        let file = testing::rom_file(bytes.clone());
+        let mut manager = PaletteManager::new(file.clone());
+        manager.read_palettes().unwrap();
+        assert!(manager.dirty_names().is_empty());
+
+        manager.set_color_gba("Tails", 0, 0x7FFF).unwrap();
+        assert_eq!(manager.dirty_names(), vec!["Tails"]);
+        // an edit to the ROM behind the manager's back shows which palettes get rewritten
+        let shadow = SHADOW_DATA.palette_offset as usize;
+        file.lock().unwrap().get_mut()[shadow] ^= 0x01;
+
+        manager.write_dirty().unwrap();
+        assert!(manager.dirty_names().is_empty());
+        assert_eq!(palette_in(&file, TAILS_DATA.palette_offset, 1), vec![0x7FFF]);
+        assert_eq!(testing::contents(&file)[shadow], bytes[shadow] ^ 0x01);
+    }
+
+    #[test]
+    fn undo_and_redo_restore_the_exact_colors() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x0000, 0x7FFF]);
+        manager.set_color_gba("Sonic", 1, 0x001F).unwrap();
+        assert_eq!((manager.undo_depth(), manager.redo_depth()), (2, 0));
+
+        manager.undo().unwrap();
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x0000, 0x7FFF]);
+        assert_eq!(manager.redo_depth(), 1);
+        manager.redo().unwrap();
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x0000, 0x001F]);
+
+        manager.undo().unwrap();
+        manager.undo().unwrap();
+        assert!(!manager.has_palette("Sonic"));
+        assert!(manager.undo().is_err());
+    }
+
+    #[test]
+    fn a_new_edit_truncates_the_redo_stack() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x0000, 0x7FFF]);
+        manager.set_color_gba("Sonic", 0, 0x0001).unwrap();
+        manager.set_color_gba("Sonic", 0, 0x0002).unwrap();
+        manager.undo().unwrap();
+        manager.undo().unwrap();
+        assert_eq!(manager.redo_depth(), 2);
+
+        manager.set_color_gba("Sonic", 1, 0x0003).unwrap();
+        assert_eq!(manager.redo_depth(), 0);
+        assert!(manager.redo().is_err());
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x0000, 0x0003]);
+    }
+
+    #[test]
+    fn the_history_limit_drops_the_oldest_edits() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.set_history_limit(2);
+        manager.store_palette_i32("Sonic".to_string(), vec![0]);
+        for value in 1..5 {
+            manager.set_color_gba("Sonic", 0, value).unwrap();
+        }
+        assert_eq!(manager.undo_depth(), 2);
+        manager.undo().unwrap();
+        manager.undo().unwrap();
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn json_dumps_hold_every_palette_by_name() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x001F, 0x7C00]);
+        manager.store_palette_i32("Tails".to_string(), vec![0x03E0]);
+        let mut dump = Vec::new();
+        manager.export_json(&mut dump).unwrap();
+        let text = String::from_utf8(dump.clone()).unwrap();
+        assert!(text.contains("\"Sonic\"") && text.contains("\"Tails\""));
+
+        let mut restored = PaletteManager::from_bytes(Vec::new());
+        restored.import_json(&mut &dump[..]).unwrap();
+        assert_eq!(restored.palette_names(), vec!["Sonic", "Tails"]);
+        assert_eq!(restored.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x001F, 0x7C00]);
+        assert!(restored.import_json(&mut &b"{\"Sonic\": [[1, 2]]}"[..]).is_err());
+    }
+
+    #[test]
+    fn nearest_index_finds_the_closest_color() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_colors("Sonic".to_string(), vec![
+            Color::rgb(0, 0, 0), Color::rgb(255, 0, 0), Color::rgb(0, 0, 255), Color::rgb(255, 0, 0),
+        ]);
+        assert_eq!(manager.nearest_index("Sonic", Color::rgb(200, 30, 20)).unwrap(), 1);
+        assert_eq!(manager.nearest_index("Sonic", Color::rgb(20, 20, 200)).unwrap(), 2);
+        assert_eq!(manager.nearest_index("Sonic", Color::rgb(5, 5, 5)).unwrap(), 0);
+        manager.store_palette_i32("Empty".to_string(), Vec::new());
+        assert!(manager.nearest_index("Empty", Color::rgb(0, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn reading_in_an_unmapped_region_fails() {
+        let mut manager = PaletteManager::from_bytes(testing::blank_rom());
+        manager.set_region(Region::Japan);
+        match manager.read_palette(&SONIC_DATA) {
+            Err(PaletteError::UnsupportedRegion(Region::Japan)) => (),
+            other => panic!("expected an unsupported region, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn roms_from_unmapped_regions_open_but_their_palettes_are_refused() {
+        let mut europe = testing::blank_rom();
+        europe[rom::HEADER_GAME_CODE as usize + 3] = b'P';
+        let mut manager = PaletteManager::new_validated(testing::rom_file(europe.clone())).unwrap();
+        assert_eq!(manager.region(), Region::Europe);
+        match manager.read_palette(&SONIC_DATA) {
+            Err(PaletteError::UnsupportedRegion(Region::Europe)) => (),
+            other => panic!("expected an unsupported region, got {:?}", other),
+        }
+        // palettes at offsets the caller gives still work
+        manager.read_palette_at("Sonic".to_string(), SONIC_DATA.palette_offset, 16).unwrap();
+        let strict = PaletteManagerBuilder::new().strict(true).build(testing::rom_file(europe)).unwrap();
+        assert_eq!(strict.region(), Region::Europe);
+
+        let manager = PaletteManager::new_validated(testing::rom_file(testing::blank_rom())).unwrap();
+        assert_eq!(manager.region(), Region::Usa);
+    }
+
+    /// a manager over `path` opened for writing, the way the GUI and command line open ROMs
+    fn open_file(path: &Path) -> PaletteManager {
+        PaletteManager::new(Arc::new(Mutex::new(OpenOptions::new().read(true).write(true).open(path).unwrap())))
+    }
+
+    #[test]
+    fn the_auto_backup_holds_the_bytes_from_before_the_first_edit() {
+        let path = testing::temp_path("backup.gba");
+        let backup = testing::temp_path("backup.gba.bak");
+        let original = testing::blank_rom();
+        fs::write(&path, &original).unwrap();
+
+        let mut manager = open_file(&path);
+        manager.set_auto_backup(Some(backup.clone()));
+        manager.store_palette_i32("Sonic".to_string(), vec![0x001F; 16]);
+        manager.write_palette(&SONIC_DATA).unwrap();
+        drop(manager);
+        assert!(fs::read(&backup).unwrap() == original);
+        assert!(fs::read(&path).unwrap() != original);
+
+        // a second session backs up to the same path, which must not copy the edited ROM over it
+        let mut manager = open_file(&path);
+        manager.set_auto_backup(Some(backup.clone()));
+        manager.store_palette_i32("Sonic".to_string(), vec![0x03E0; 16]);
+        manager.write_palette(&SONIC_DATA).unwrap();
+        drop(manager);
+        assert!(fs::read(&backup).unwrap() == original);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&backup).unwrap();
+    }
+
+    #[test]
+    fn backup_leaves_the_stream_position_alone() {
+        let file = testing::rom_file(vec![1, 2, 3, 4]);
+        file.lock().unwrap().set_position(3);
+        let manager = PaletteManager::new(file.clone());
+        let path = testing::temp_path("position.bak");
+        manager.backup(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), testing::contents(&file));
+        assert_eq!(file.lock().unwrap().position(), 3);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn in_memory_roms_hand_back_what_was_written() {
+        let mut manager = PaletteManager::from_bytes(testing::blank_rom());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x7FFF; 16]);
+        manager.write_palette(&SONIC_DATA).unwrap();
+        let bytes = manager.into_bytes();
+        assert_eq!(bytes.len(), testing::ROM_SIZE);
+        let start = SONIC_DATA.palette_offset as usize;
+        assert_eq!(&bytes[start..start + 4], &[0xFF, 0x7F, 0xFF, 0x7F]);
+
+        // a ROM still shared with someone else comes back as a copy
+        let file = testing::rom_file(vec![1, 2, 3]);
+        let manager = PaletteManager::new(file.clone());
+        assert_eq!(manager.into_bytes(), testing::contents(&file));
+    }
+
+    #[test]
+    fn diffs_list_the_slots_that_differ() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("A".to_string(), vec![0x0000, 0x001F, 0x7FFF]);
+        manager.store_palette_i32("B".to_string(), vec![0x0000, 0x7C00, 0x7FFF]);
+        assert_eq!(manager.diff("A", "B").unwrap(), vec![(1, Color::rgb(255, 0, 0), Color::rgb(0, 0, 255))]);
+        assert!(manager.diff("A", "A").unwrap().is_empty());
+
+        manager.store_palette_i32("C".to_string(), vec![0x0000]);
+        match manager.diff("A", "C") {
+            Err(PaletteError::WrongLength { expected: 3, actual: 1 }) => (),
+            other => panic!("expected WrongLength, got {:?}", other),
+        }
+        assert!(manager.diff("A", "Missing").is_err());
+    }
+
+    #[test]
+    fn cloned_palettes_are_independent_copies() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x001F, 0x03E0]);
+        manager.clone_palette("Sonic", "Backup".to_string()).unwrap();
+        manager.set_color_gba("Sonic", 0, 0x7C00).unwrap();
+        assert_eq!(manager.load_palette_i32("Backup".to_string()).unwrap(), vec![0x001F, 0x03E0]);
+
+        match manager.clone_palette_checked("Sonic", "backup".to_string()) {
+            Err(PaletteError::PaletteExists(ref name)) => assert_eq!(name, "backup"),
+            other => panic!("expected PaletteExists, got {:?}", other),
+        }
+        manager.clone_palette("Sonic", "Backup".to_string()).unwrap();
+        assert_eq!(manager.load_palette_i32("Backup".to_string()).unwrap(), vec![0x7C00, 0x03E0]);
+        assert!(manager.clone_palette("Missing", "Other".to_string()).is_err());
+        assert!(!manager.has_palette("Other"));
+    }
+
+    #[test]
+    fn one_palette_can_be_applied_to_many_characters() {
+        let file = testing::rom_file(testing::blank_rom());
+        let mut manager = PaletteManager::new(file.clone());
+        manager.store_palette_i32("Custom".to_string(), vec![0x1234; 16]);
+        manager.apply_palette_to("Custom", &[&KNUCKLES_DATA, &ROUGE_DATA]).unwrap();
+        assert_eq!(palette_in(&file, KNUCKLES_DATA.palette_offset, 16), vec![0x1234; 16]);
+        assert_eq!(palette_in(&file, ROUGE_DATA.palette_offset, 16), vec![0x1234; 16]);
+        assert_eq!(manager.load_palette_i32("Rouge".to_string()).unwrap(), vec![0x1234; 16]);
+
+        // a palette too short for one target writes none of them
+        manager.store_palette_i32("Short".to_string(), vec![0x7FFF; 4]);
+        assert!(manager.apply_palette_to("Short", &[&TAILS_DATA]).is_err());
+        assert_eq!(palette_in(&file, TAILS_DATA.palette_offset, 16), vec![0; 16]);
+    }
+
+    #[test]
+    fn stored_palettes_are_listed_with_their_length() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        assert!(manager.palette_names().is_empty());
+        manager.store_palette_i32("Tails".to_string(), vec![0; 16]);
+        manager.store_palette_i32("Amy".to_string(), vec![0; 3]);
+        assert_eq!(manager.palette_names(), vec!["Amy", "Tails"]);
+        assert_eq!(manager.palette_len("tails"), Some(16));
+        assert_eq!(manager.palette_len("Amy"), Some(3));
+        assert_eq!(manager.palette_len("Sonic"), None);
+    }
+
+    #[test]
+    fn removing_palettes_drops_their_writes_and_history() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0; 2]);
+        manager.store_palette_i32("Tails".to_string(), vec![0; 2]);
+        manager.set_color_gba("Sonic", 0, 1).unwrap();
+
+        assert_eq!(manager.remove_palette("SONIC"), Some(vec![1, 0]));
+        assert_eq!(manager.remove_palette("Sonic"), None);
+        assert!(!manager.dirty_names().contains(&"Sonic"));
+        assert_eq!(manager.palette_names(), vec!["Tails"]);
+        // the undo entries left are Tails' store, Sonic's went with it
+        assert_eq!(manager.undo_depth(), 1);
+
+        manager.clear();
+        assert!(manager.palette_names().is_empty());
+        assert!(manager.dirty_names().is_empty());
+        assert_eq!(manager.undo_depth(), 0);
+        assert!(manager.undo().is_err());
+    }
+
+    #[test]
+    fn brightness_changes_every_color_but_index_0() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x0000, 0x0000, 0x7FFF, 0x001F]);
+        manager.adjust_brightness("Sonic", 40).unwrap();
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![
+            0x0000, encode_gba(Color::rgb(40, 40, 40)), 0x7FFF, encode_gba(Color::rgb(255, 40, 40)),
+        ]);
+        manager.adjust_brightness("Sonic", -255).unwrap();
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0; 4]);
+        assert!(manager.adjust_brightness("Missing", 1).is_err());
+    }
+
+    #[test]
+    fn hue_rotation_cycles_the_primaries_and_leaves_grays() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x001F, 0x001F, 0x03E0, 0x7C00, 0x4210]);
+        manager.rotate_hue("Sonic", 120.0).unwrap();
+        // red to green to blue and back, index 0 and the gray stay
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x001F, 0x03E0, 0x7C00, 0x001F, 0x4210]);
+        manager.rotate_hue("Sonic", -480.0).unwrap();
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x001F, 0x001F, 0x03E0, 0x7C00, 0x4210]);
+    }
+
+    #[test]
+    fn grayscale_uses_the_luminance_weights() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x001F, 0x001F, 0x03E0, 0x7C00, 0x7FFF]);
+        manager.to_grayscale("Sonic").unwrap();
+        let gray = |level| encode_gba(Color::rgb(level, level, level));
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x001F, gray(76), gray(150), gray(29), 0x7FFF]);
+        for color in manager.load_palette_colors("Sonic".to_string()).unwrap()[1..].iter() {
+            assert!(color.r == color.g && color.g == color.b, "{} isn't gray", color);
+        }
+    }
+
+    #[test]
+    fn inverting_twice_gives_the_palette_back() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        let colors = vec![0x0000, 0x0000, 0x7FFF, 0x001F, 0x1234];
+        manager.store_palette_i32("Sonic".to_string(), colors.clone());
+        manager.invert("Sonic").unwrap();
+        let inverted = manager.load_palette_i32("Sonic".to_string()).unwrap();
+        // the BGR555 inverse flips every channel bit
+        assert_eq!(inverted, vec![0x0000, 0x7FFF, 0x0000, 0x7FE0, 0x1234 ^ 0x7FFF]);
+        manager.invert("Sonic").unwrap();
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), colors);
+    }
+
+    #[test]
+    fn gradients_run_from_start_to_end() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_gradient("Fade".to_string(), Color::rgb(0, 0, 0), Color::rgb(255, 0, 0), 16);
+        let values = manager.load_palette_i32("Fade".to_string()).unwrap();
+        assert_eq!(values.len(), 16);
+        assert_eq!(values[0], 0x0000);
+        assert_eq!(values[15], 0x001F);
+        assert!(values.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", values);
+        assert!(values.iter().all(|value| value & !0x1F == 0));
+
+        manager.store_gradient("One".to_string(), Color::rgb(0, 0, 255), Color::rgb(255, 0, 0), 1);
+        assert_eq!(manager.load_palette_i32("One".to_string()).unwrap(), vec![0x7C00]);
+        manager.store_gradient("None".to_string(), Color::rgb(0, 0, 0), Color::rgb(0, 0, 0), 0);
+        assert_eq!(manager.palette_len("None"), Some(0));
+    }
+
+    #[test]
+    fn hex_dumps_show_the_rom_bytes_8_colors_a_line() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        let mut colors = vec![0x0000; 9];
+        colors[0] = 0x7C1F;
+        colors[8] = 0x0123;
+        manager.store_palette_i32("Sonic".to_string(), colors);
+        assert_eq!(manager.hex_dump("Sonic").unwrap(),
+            "00: 1F 7C  00 00  00 00  00 00  00 00  00 00  00 00  00 00\n10: 23 01");
+        assert!(manager.hex_dump("Missing").is_err());
+    }
+
+    #[test]
+    fn c_arrays_and_assembly_hold_the_raw_values() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("E-102".to_string(), (0..9).collect());
+        let mut c = Vec::new();
+        manager.export_c_array("E-102", &mut c).unwrap();
+        assert_eq!(String::from_utf8(c.clone()).unwrap(), "const u16 e_102_pal[9] = {\n    \
+            0x0000, 0x0001, 0x0002, 0x0003, 0x0004, 0x0005, 0x0006, 0x0007,\n    0x0008,\n};\n");
+        manager.import_c_array("Copy".to_string(), &mut &c[..]).unwrap();
+        assert_eq!(manager.load_palette_i32("Copy".to_string()).unwrap(), (0..9).collect::<Vec<i32>>());
+        assert!(manager.import_c_array("Bad".to_string(), &mut &b"{ 0x8000 }"[..]).is_err());
+        assert!(manager.import_c_array("Bad".to_string(), &mut &b"0x0001"[..]).is_err());
+
+        let mut asm = Vec::new();
+        manager.export_asm("E-102", &mut asm).unwrap();
+        let asm = String::from_utf8(asm).unwrap();
+        assert!(asm.starts_with("e_102_pal:\n    .hword 0x0000\n"));
+        assert_eq!(asm.lines().count(), 10);
+        assert_eq!(palette_identifier("1st"), "_1st_pal");
+    }
+
+    #[test]
+    fn managers_sharing_a_rom_across_threads_read_the_right_palettes() {
+        let bytes = testing::random_rom(38);
+        let mut expected = PaletteManager::from_bytes(bytes.clone());
+        expected.read_palettes().unwrap();
+        let file = Arc::new(Mutex::new(testing::CountingRom::new(Cursor::new(bytes))));
+
+        let handles: Vec<_> = [SONIC_DATA, SHADOW_DATA].iter().map(|&character| {
+            let file = file.clone();
+            thread::spawn(move || {
+                let mut manager = PaletteManager::new(file);
+                (0..200).map(|_| {
+                    manager.read_palette(&character).unwrap();
+                    manager.load_palette_i32(character.name.to_string()).unwrap()
+                }).collect::<Vec<_>>()
+            })
+        }).collect();
+        for (handle, name) in handles.into_iter().zip(["Sonic", "Shadow"].iter()) {
+            let colors = expected.load_palette_i32(name.to_string()).unwrap();
+            assert!(handle.join().unwrap().iter().all(|read| *read == colors), "{} was read torn", name);
+        }
+        // one seek and one read for each palette
+        let file = file.lock().unwrap();
+        assert_eq!((file.seeks, file.reads), (400, 400));
+    }
+
+    #[test]
+    fn bulk_and_per_character_reads_store_the_same_palettes() {
+        let bytes = testing::random_rom(39);
+        let mut bulk = PaletteManager::from_bytes(bytes.clone());
+        bulk.read_palettes_bulk().unwrap();
+        let mut single = PaletteManager::from_bytes(bytes);
+        single.read_palettes().unwrap();
+
+        assert_eq!(bulk.palette_names(), single.palette_names());
+        for character in CHARACTERS.iter() {
+            let colors = bulk.load_palette_i32(character.name.to_string()).unwrap();
+            assert_eq!(colors, single.load_palette_i32(character.name.to_string()).unwrap());
+        }
+        assert!(bulk.dirty_names().is_empty());
+    }
+
+    #[test]
+    fn the_bulk_read_covers_the_us_table_in_one_seek() {
+        let file = Arc::new(Mutex::new(testing::CountingRom::new(Cursor::new(testing::random_rom(1)))));
+        let mut manager = PaletteManager::new(file.clone());
+        manager.read_palettes_bulk().unwrap();
+        assert_eq!(file.lock().unwrap().seeks, 1);
+
+        file.lock().unwrap().seeks = 0;
+        manager.read_palettes().unwrap();
+        assert_eq!(file.lock().unwrap().seeks, CHARACTERS.len());
+    }
+
+    #[test]
+    fn the_bulk_read_includes_extra_palette_slots() {
+        let character = Character { extra_palette_offsets: &[0x47B000, 0x47B020], ..SONIC_DATA };
+        let bytes = testing::random_rom(2);
+        let mut bulk = PaletteManager::from_bytes(bytes.clone());
+        bulk.read_characters_bulk(&[character]).unwrap();
+        let mut single = PaletteManager::from_bytes(bytes);
+        single.read_palette(&character).unwrap();
+
+        assert_eq!(bulk.palette_names(), vec!["Sonic", "Sonic#1", "Sonic#2"]);
+        for name in ["Sonic", "Sonic#1", "Sonic#2"].iter() {
+            assert_eq!(bulk.load_palette_ref(name).unwrap(), single.load_palette_ref(name).unwrap());
+        }
+    }
+
+    #[test]
+    fn the_bulk_read_falls_back_when_palettes_are_far_apart() {
+        let far = Character { name: "Far", palette_offset: 0xBF2058, ..SONIC_DATA };
+        let file = Arc::new(Mutex::new(testing::CountingRom::new(Cursor::new(testing::random_rom(3)))));
+        let mut manager = PaletteManager::new(file.clone());
+        manager.read_characters_bulk(&[SONIC_DATA, far]).unwrap();
+        assert_eq!(file.lock().unwrap().seeks, 2);
+        assert!(manager.has_palette("Far"));
+    }
+
+    /// cargo test --release -- --ignored --nocapture bulk_read_timing
+    #[test]
+    #[ignore]
+    fn bulk_read_timing() {
+        use std::time::Instant;
+        let path = testing::temp_path("timing.gba");
+        fs::write(&path, testing::random_rom(4)).unwrap();
+        let mut manager = open_file(&path);
+        let runs = 200;
+
+        let timer = Instant::now();
+        for _ in 0..runs {
+            manager.read_palettes().unwrap();
+        }
+        let single = timer.elapsed() / runs;
+        let timer = Instant::now();
+        for _ in 0..runs {
+            manager.read_palettes_bulk().unwrap();
+        }
+        let bulk = timer.elapsed() / runs;
+        println!("per character {:?}, bulk {:?} for {} characters", single, bulk, CHARACTERS.len());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn shuffling_keeps_index_0_and_depends_only_on_the_seed() {
+        let colors: Vec<i32> = (0..16).map(|value| value * 0x421).collect();
+        let shuffled = |seed| {
+            let mut manager = PaletteManager::from_bytes(Vec::new());
+            manager.store_palette_i32("Sonic".to_string(), colors.clone());
+            manager.shuffle_palette("Sonic", seed).unwrap();
+            manager.load_palette_i32("Sonic".to_string()).unwrap()
+        };
+        let first = shuffled(41);
+        assert_eq!(first, shuffled(41));
+        assert!(first != colors);
+        assert_eq!(first[0], colors[0]);
+        let mut sorted = first.clone();
+        sorted.sort();
+        assert_eq!(sorted, colors);
+    }
+
+    #[test]
+    fn randomizing_the_rom_is_reproducible_from_the_seed() {
+        let randomized = |seed| {
+            let mut manager = PaletteManager::from_bytes(testing::random_rom(42));
+            manager.read_palettes().unwrap();
+            manager.randomize_all(seed).unwrap();
+            manager.into_bytes()
+        };
+        let rom = randomized(1);
+        assert_eq!(rom, randomized(1));
+        assert!(rom != randomized(2));
+        assert!(rom != testing::random_rom(42));
+
+        // each character only depends on its own derived seed
+        let mut sonic = PaletteManager::from_bytes(testing::random_rom(42));
+        sonic.read_palette(&SONIC_DATA).unwrap();
+        sonic.shuffle_palette("Sonic", random::derive_seed(1, "Sonic")).unwrap();
+        let start = SONIC_DATA.palette_offset as usize;
+        assert_eq!(decode_palette(&rom[start..start + 32]).unwrap(), sonic.load_palette_i32("Sonic".to_string()).unwrap());
+    }
+
+    #[test]
+    fn swapping_exchanges_two_palettes_in_the_rom() {
+        let bytes = testing::random_rom(43);
+        let file = testing::rom_file(bytes.clone());
+        let mut manager = PaletteManager::new(file.clone());
+        manager.read_palette(&SONIC_DATA).unwrap();
+        manager.read_palette(&SHADOW_DATA).unwrap();
+        let sonic = manager.load_palette_i32("Sonic".to_string()).unwrap();
+        let shadow = manager.load_palette_i32("Shadow".to_string()).unwrap();
+
+        manager.swap_palettes(&SONIC_DATA, &SHADOW_DATA).unwrap();
+        assert_eq!(palette_in(&file, SONIC_DATA.palette_offset, 16), shadow);
+        assert_eq!(palette_in(&file, SHADOW_DATA.palette_offset, 16), sonic);
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), shadow);
+
+        manager.swap_palettes(&SONIC_DATA, &SONIC_DATA).unwrap();
+        assert_eq!(palette_in(&file, SONIC_DATA.palette_offset, 16), shadow);
+        assert!(manager.swap_palettes(&SONIC_DATA, &TAILS_DATA).is_err());
+    }
+
+    #[test]
+    fn a_palette_edit_makes_a_one_record_patch() {
+        let original = testing::random_rom(44);
+        let mut manager = PaletteManager::from_bytes(original.clone());
+        manager.read_palette(&SONIC_DATA).unwrap();
+        manager.set_color_gba("Sonic", 2, 0x7FFF).unwrap();
+        manager.write_palette(&SONIC_DATA).unwrap();
+
+        let patch = ::patch::diff_to_ips(&original, &manager.into_bytes()).unwrap();
+        let offset = SONIC_DATA.palette_offset as usize + 4;
+        assert!(patch.len() <= 5 + 5 + 2 + 3, "{} bytes", patch.len());
+        let mut rom = original.clone();
+        ::patch::apply_ips(&mut rom, &patch).unwrap();
+        assert_eq!(&rom[offset..offset + 2], &[0xFF, 0x7F]);
+    }
+
+    /// Sonic with a palette pointer, in a ROM laid out like the US release: the palette at its
+    /// real offset, a pointer table entry pointing at it, and free space at the end of the ROM
+    fn pointed_rom() -> (Character, Vec<u8>) {
+        let character = Character { pointer_location: Some(0x100000), ..SONIC_DATA };
+        let mut bytes = testing::random_rom(47);
+        bytes[0x100000..0x100004].copy_from_slice(&rom::rom_pointer_bytes(SONIC_DATA.palette_offset));
+        for byte in bytes[0xF00000..].iter_mut() {
+            *byte = rom::FREE_SPACE_BYTE;
+        }
+        (character, bytes)
+    }
+
+    #[test]
+    fn repointing_moves_the_palette_and_rewrites_the_pointer() {
+        let (character, bytes) = pointed_rom();
+        let file = testing::rom_file(bytes);
+        let mut manager = PaletteManager::new(file.clone());
+        manager.read_palette(&character).unwrap();
+        let colors = manager.load_palette_i32("Sonic".to_string()).unwrap();
+
+        let new_offset = rom::find_free_space(&mut *file.lock().unwrap(), 32, 4).unwrap().unwrap();
+        assert_eq!(new_offset, 0xF00000);
+        manager.repoint_palette(&character, new_offset).unwrap();
+
+        let contents = testing::contents(&file);
+        assert_eq!(&contents[0x100000..0x100004], &[0x00, 0x00, 0xF0, 0x08]);
+        assert_eq!(rom::read_rom_pointer(&mut *file.lock().unwrap(), 0x100000).unwrap(), new_offset);
+        assert_eq!(decode_palette(&contents[0xF00000..0xF00020]).unwrap(), colors);
+
+        // later reads and writes follow the palette to its new home
+        manager.set_color_gba("Sonic", 0, 0x7FFF).unwrap();
+        manager.write_palette(&character).unwrap();
+        assert_eq!(&testing::contents(&file)[0xF00000..0xF00002], &[0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn repointing_without_a_known_pointer_fails() {
+        let mut manager = PaletteManager::from_bytes(testing::random_rom(5));
+        manager.read_palette(&SONIC_DATA).unwrap();
+        match manager.repoint_palette(&SONIC_DATA, 0xF00000) {
+            Err(PaletteError::NoPointer(ref name)) => assert_eq!(name, "Sonic"),
+            other => panic!("expected no pointer, got {:?}", other),
+        }
+        assert!(CHARACTERS.iter().all(|character| character.pointer().is_none()));
+    }
+
+    #[test]
+    fn palettes_of_256_colors_read_and_write_whole() {
+        let wide = Character { color_count: 256, ..SONIC_DATA };
+        let bytes = testing::random_rom(48);
+        let file = testing::rom_file(bytes.clone());
+        let mut manager = PaletteManager::new(file.clone());
+        manager.read_palette(&wide).unwrap();
+        let start = SONIC_DATA.palette_offset as usize;
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), decode_palette(&bytes[start..start + 512]).unwrap());
+
+        manager.set_color_gba("Sonic", 255, 0x7FFF).unwrap();
+        manager.write_palette(&wide).unwrap();
+        assert_eq!(&testing::contents(&file)[start + 510..start + 512], &[0xFF, 0x7F]);
+
+        // a 16 color palette can't go where 256 are expected, or the other way around
+        manager.store_palette_i32("Sonic".to_string(), vec![0; 16]);
+        assert!(manager.write_palette(&wide).is_err());
+        manager.store_palette_i32("Sonic".to_string(), vec![0; 256]);
+        assert!(manager.write_palette(&SONIC_DATA).is_err());
+    }
+
+    #[test]
+    fn png_swatches_are_a_square_per_color() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        let colors: Vec<i32> = (0..16).map(|value| value * 0x421).collect();
+        manager.store_palette_i32("Sonic".to_string(), colors.clone());
+        let mut png = Vec::new();
+        manager.export_png_swatch("Sonic", &mut png, 8).unwrap();
+
+        let image = image::load_from_memory_with_format(&png, ImageFormat::PNG).unwrap().to_rgba();
+        assert_eq!(image.dimensions(), (16 * 8, 8));
+        assert_eq!(image.get_pixel(8 * 5 + 7, 7).data, [41, 41, 41, 0xFF]);
+
+        manager.import_png_swatch("Copy".to_string(), &mut &png[..]).unwrap();
+        assert_eq!(manager.load_palette_i32("Copy".to_string()).unwrap(), colors);
+        assert!(manager.import_png_swatch("Bad".to_string(), &mut &b"not a png"[..]).is_err());
+    }
+
+    #[test]
+    fn the_montage_has_a_row_per_palette() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("B".to_string(), vec![0x001F; 16]);
+        manager.store_palette_i32("A".to_string(), vec![0x7C00; 4]);
+        let mut png = Vec::new();
+        manager.export_montage_png(&mut png).unwrap();
+
+        let image = image::load_from_memory_with_format(&png, ImageFormat::PNG).unwrap().to_rgba();
+        let size = MONTAGE_SWATCH_SIZE;
+        assert_eq!(image.dimensions(), (16 * size, 2 * size));
+        // rows follow the sorted names, shorter palettes leave the rest of their row clear
+        assert_eq!(image.get_pixel(size, 0).data, [0, 0, 255, 0xFF]);
+        assert_eq!(image.get_pixel(size, size).data, [255, 0, 0, 0xFF]);
+        assert_eq!(image.get_pixel(5 * size, 0).data[3], 0);
+    }
+
+    #[test]
+    fn images_quantize_to_the_nearest_stored_color() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_colors("Sonic".to_string(), vec![Color::rgb(0, 0, 0), Color::rgb(255, 0, 0), Color::rgb(0, 0, 255)]);
+        let rgba = [
+            250, 10, 0, 0xFF,
+            0, 0, 240, 0xFF,
+            255, 0, 0, 0,
+            8, 8, 8, 0xFF,
+        ];
+        assert_eq!(manager.quantize_image("Sonic", &rgba, 2, 2).unwrap(), vec![1, 2, 0, 0]);
+
+        match manager.quantize_image("Sonic", &rgba, 3, 2) {
+            Err(PaletteError::ImageSize { expected: 24, actual: 16 }) => {}
+            other => panic!("expected an image size error, got {:?}", other),
+        }
+        assert!(manager.quantize_image("Tails", &rgba, 2, 2).is_err());
+    }
+
+    #[test]
+    fn palettes_from_images_keep_the_background_first() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        let rgba = [
+            0, 255, 0, 0,
+            255, 0, 0, 0xFF,
+            255, 0, 0, 0xFF,
+            0, 0, 255, 0xFF,
+        ];
+        manager.store_palette_from_image("Sprite".to_string(), &rgba, 16);
+        assert_eq!(manager.load_palette_colors("Sprite".to_string()).unwrap(),
+                   vec![Color::rgb(0, 255, 0), Color::rgb(255, 0, 0), Color::rgb(0, 0, 255)]);
+
+        // with nothing transparent the most common color comes first
+        let opaque = [0, 0, 255, 0xFF, 255, 0, 0, 0xFF, 255, 0, 0, 0xFF];
+        manager.store_palette_from_image("Opaque".to_string(), &opaque, 2);
+        assert_eq!(manager.load_palette_colors("Opaque".to_string()).unwrap(), vec![Color::rgb(255, 0, 0), Color::rgb(0, 0, 255)]);
+        // a single color is the average purple, rounded to what the GBA can show
+        manager.store_palette_from_image("Opaque".to_string(), &opaque, 1);
+        assert_eq!(manager.load_palette_colors("Opaque".to_string()).unwrap(), vec![Color::rgb(173, 0, 82)]);
+    }
+
+    #[test]
+    fn ansi_output_has_a_block_per_color_only_when_asked() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x0000, 0x001F]);
+        assert_eq!(manager.format_palette_ansi("Sonic", false).unwrap(), "  0 #000000\n  1 #FF0000\n");
+        assert_eq!(manager.format_palette_ansi("Sonic", true).unwrap(),
+                   "\x1b[48;2;0;0;0m  \x1b[0m   0 #000000\n\x1b[48;2;255;0;0m  \x1b[0m   1 #FF0000\n");
+        assert!(manager.format_palette_ansi("Tails", false).is_err());
+    }
+
+    #[test]
+    fn simulating_a_deficiency_leaves_the_palette_alone() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x001F, 0x03E0]);
+        let before = manager.load_palette_colors("Sonic".to_string()).unwrap();
+        let simulated = manager.simulate_cvd("Sonic", CvdType::Deuteranopia).unwrap();
+        assert_eq!(simulated, before.iter().map(|&color| simulate_cvd(color, CvdType::Deuteranopia)).collect::<Vec<_>>());
+        assert_eq!(manager.load_palette_colors("Sonic".to_string()).unwrap(), before);
+    }
+
+    #[test]
+    fn colors_are_replaced_by_their_gba_value() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x001F, 0x7FFF, 0x001F, 0x03E0]);
+        // 252 and 255 round to the same 5 bit red
+        assert_eq!(manager.replace_color("Sonic", Color::rgb(252, 0, 0), Color::rgb(0, 0, 255)).unwrap(), 2);
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x7C00, 0x7FFF, 0x7C00, 0x03E0]);
+
+        let depth = manager.undo_depth();
+        assert_eq!(manager.replace_color("Sonic", Color::rgb(255, 0, 0), Color::rgb(0, 0, 0)).unwrap(), 0);
+        assert_eq!(manager.undo_depth(), depth);
+        assert!(manager.replace_color("Tails", Color::rgb(0, 0, 0), Color::rgb(0, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn websafe_snapping_stores_the_gba_colors_nearest_the_cube() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        let original = vec![0x0010, 0x0010, 0x7FFF, 0x4210, 0x1234, 0x6B5A, 0x2D6B];
+        manager.store_palette_i32("Sonic".to_string(), original.clone());
+        manager.snap_websafe("Sonic").unwrap();
+
+        // every channel is a web-safe level as near as the GBA gets to it, index 0 is kept as it is
+        let levels: Vec<i32> = WEBSAFE_LEVELS.iter().map(|&level| decode_gba(encode_gba(Color::rgb(level, 0, 0))).r).collect();
+        let snapped = manager.load_palette_i32("Sonic".to_string()).unwrap();
+        assert_eq!(snapped[0], original[0]);
+        for &value in snapped[1..].iter() {
+            let color = decode_gba(value);
+            for &channel in [color.r, color.g, color.b].iter() {
+                assert!(levels.contains(&channel), "{} in {:#06X} isn't near a web-safe level", channel, value);
+            }
+        }
+        // 0x10 is a red of 132, which is nearest 153 on the cube
+        assert_eq!(snapped[1], encode_gba(Color::rgb(153, 0, 0)));
+
+        manager.snap_websafe("Sonic").unwrap();
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), snapped);
+    }
+
+    #[test]
+    fn checked_stores_reject_values_past_15_bits() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32_checked("Sonic".to_string(), vec![0x0000, 0x7FFF]).unwrap();
+        match manager.store_palette_i32_checked("Sonic".to_string(), vec![0x0000, 0x7FFF, 0x8000]) {
+            Err(PaletteError::InvalidGbaColor { index: 2, value: 0x8000 }) => {}
+            other => panic!("expected InvalidGbaColor, got {:?}", other),
+        }
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x0000, 0x7FFF]);
+
+        // the unchecked store keeps whatever it's given
+        manager.store_palette_i32("Sonic".to_string(), vec![-1]);
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![-1]);
+    }
+
+    #[test]
+    fn unique_colors_keep_the_first_of_each() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x7FFF, 0x001F, 0x7FFF, 0x03E0, 0x001F, 0x7FFF]);
+        assert_eq!(manager.unique_colors("Sonic").unwrap(), vec![Color::rgb(255, 255, 255), Color::rgb(255, 0, 0), Color::rgb(0, 255, 0)]);
+        assert!(manager.unique_colors("Tails").is_err());
+
+        let mut set = HashSet::new();
+        assert!(set.insert(Color::rgb(1, 2, 3)));
+        assert!(!set.insert(Color::rgb(1, 2, 3)));
+        assert!(set.insert(Color::rgb(3, 2, 1)));
+    }
+
+    #[test]
+    fn only_characters_already_read_are_iterated() {
+        let bytes = testing::random_rom(59);
+        let mut manager = PaletteManager::new(testing::rom_file(bytes.clone()));
+        manager.read_palette(&TAILS_DATA).unwrap();
+        manager.read_palette(&SONIC_DATA).unwrap();
+        manager.store_palette_i32("Custom".to_string(), vec![0; 16]);
+
+        let entries: Vec<_> = manager.iter_character_palettes().collect();
+        let names: Vec<_> = entries.iter().map(|&(name, _, _)| name).collect();
+        assert_eq!(names, vec!["Sonic", "Tails"]);
+        let start = TAILS_DATA.palette_offset as usize;
+        let tails = decode_palette(&bytes[start..start + 32]).unwrap();
+        assert_eq!(entries[1].1, TAILS_DATA.palette_offset);
+        assert_eq!(entries[1].2, tails.iter().map(|&value| decode_gba(value)).collect::<Vec<_>>());
+    }
+
+    /// a ROM that remembers how many bytes were written since the last flush
+    struct BufferedRom {
+        inner: Cursor<Vec<u8>>,
+        unflushed: usize,
+        flushes: usize,
+    }
+
+    impl Read for BufferedRom {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl Write for BufferedRom {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            self.unflushed += buf.len();
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            self.unflushed = 0;
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    impl Seek for BufferedRom {
+        fn seek(&mut self, from: SeekFrom) -> Result<u64, Error> {
+            self.inner.seek(from)
+        }
+    }
+
+    #[test]
+    fn writes_are_flushed_and_files_can_be_synced() {
+        let file = Arc::new(Mutex::new(BufferedRom { inner: Cursor::new(testing::blank_rom()), unflushed: 0, flushes: 0 }));
+        let mut manager = PaletteManager::new(file.clone());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x1234; 16]);
+        manager.write_palette(&SONIC_DATA).unwrap();
+        {
+            let rom = file.lock().unwrap();
+            assert_eq!((rom.unflushed, rom.flushes), (0, 1));
+        }
+
+        let path = testing::temp_path("sync.gba");
+        fs::write(&path, testing::blank_rom()).unwrap();
+        let mut manager = open_file(&path);
+        manager.store_palette_i32("Sonic".to_string(), vec![0x1234; 16]);
+        manager.write_palette(&SONIC_DATA).unwrap();
+        manager.sync().unwrap();
+        let offset = SONIC_DATA.palette_offset as usize;
+        assert_eq!(&fs::read(&path).unwrap()[offset..offset + 2], &[0x34, 0x12]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tiles_render_through_a_stored_palette() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x7FFF, 0x001F, 0x7C00]);
+        let mut indices = [[1u8; TILE_SIZE]; TILE_SIZE];
+        indices[0][0] = 0;
+        indices[7][7] = 2;
+
+        let rgb = manager.render_tile("Sonic", &indices).unwrap();
+        assert_eq!(rgb.len(), TILE_SIZE * TILE_SIZE * 3);
+        assert_eq!(&rgb[..6], &[255, 255, 255, 255, 0, 0]);
+        assert_eq!(&rgb[rgb.len() - 3..], &[0, 0, 255]);
+
+        let rgba = manager.render_tile_rgba("Sonic", &indices).unwrap();
+        assert_eq!(&rgba[..8], &[255, 255, 255, 0, 255, 0, 0, 0xFF]);
+
+        indices[3][3] = 3;
+        match manager.render_tile("Sonic", &indices) {
+            Err(PaletteError::IndexOutOfBounds { index: 3, len: 3, .. }) => {}
+            other => panic!("expected an out of bounds index, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn repointed_palettes_are_found_after_reopening() {
+        let (character, bytes) = pointed_rom();
+        let file = testing::rom_file(bytes.clone());
+        let mut manager = PaletteManager::new(file.clone());
+        manager.read_palette(&character).unwrap();
+        manager.set_color_gba("Sonic", 1, 0x7C1F).unwrap();
+        manager.repoint_palette(&character, 0xF00000).unwrap();
+        let colors = manager.load_palette_i32("Sonic".to_string()).unwrap();
+        drop(manager);
+
+        // the old copy is still there, only the pointer says where the palette is now
+        let contents = testing::contents(&file);
+        let old = SONIC_DATA.palette_offset as usize;
+        assert_eq!(&contents[old..old + 32], &bytes[old..old + 32]);
+        let mut reopened = PaletteManager::new(file.clone());
+        reopened.read_palette(&character).unwrap();
+        assert_eq!(reopened.load_palette_i32("Sonic".to_string()).unwrap(), colors);
+        reopened.store_palette_i32("Sonic".to_string(), vec![0x03E0; 16]);
+        reopened.write_palette(&character).unwrap();
+        assert_eq!(palette_in(&file, 0xF00000, 16), vec![0x03E0; 16]);
+        assert_eq!(&testing::contents(&file)[old..old + 32], &bytes[old..old + 32]);
+
+        // a pointer location that doesn't hold a ROM pointer is an error, not a read somewhere else
+        let mut broken = bytes;
+        broken[0x100003] = 0x02;
+        match PaletteManager::from_bytes(broken).read_palette(&character) {
+            Err(PaletteError::Pointer(PointerError::NotInRom(pointer))) => assert_eq!(pointer, 0x0200_0000 | SONIC_DATA.palette_offset as u32),
+            other => panic!("expected a bad pointer, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn palettes_of_the_wrong_length_are_not_written() {
+        let bytes = testing::random_rom(67);
+        let file = testing::rom_file(bytes.clone());
+        let mut manager = PaletteManager::new(file.clone());
+        for &length in [15, 17].iter() {
+            manager.store_palette_i32("Sonic".to_string(), vec![0x7FFF; length]);
+            match manager.write_palette(&SONIC_DATA) {
+                Err(PaletteError::WrongLength { expected: 16, actual }) => assert_eq!(actual, length),
+                other => panic!("expected a wrong length, got {:?}", other),
+            }
+        }
+        assert!(testing::contents(&file) == bytes);
+    }
+
+    #[test]
+    fn palette_names_match_in_any_case() {
+        let file = testing::rom_file(testing::blank_rom());
+        let mut manager = PaletteManager::new(file.clone());
+        manager.store_palette_i32("sonic".to_string(), vec![0x1234; 16]);
+        assert!(manager.has_palette("SONIC"));
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x1234; 16]);
+        assert_eq!(manager.palette_names(), vec!["sonic"]);
+
+        // the character table spells it "Sonic", which still finds the palette
+        manager.write_palette(&SONIC_DATA).unwrap();
+        let offset = SONIC_DATA.palette_offset as usize;
+        assert_eq!(&testing::contents(&file)[offset..offset + 2], &[0x34, 0x12]);
+    }
+
+    #[test]
+    fn undo_restores_the_display_name() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0]);
+        manager.store_palette_i32("SONIC".to_string(), vec![1]);
+        assert_eq!(manager.palette_names(), vec!["SONIC"]);
+
+        manager.undo().unwrap();
+        assert_eq!(manager.palette_names(), vec!["Sonic"]);
+        manager.redo().unwrap();
+        assert_eq!(manager.palette_names(), vec!["SONIC"]);
+        manager.undo().unwrap();
+        manager.undo().unwrap();
+        assert!(manager.palette_names().is_empty());
+        assert!(manager.display_names.is_empty());
+    }
+
+    #[test]
+    fn dry_runs_record_writes_without_making_them() {
+        let file = testing::rom_file(testing::blank_rom());
+        let mut manager = PaletteManager::new(file.clone());
+        manager.set_dry_run(true);
+        manager.store_palette_i32("Sonic".to_string(), vec![0x7FFF; 16]);
+        manager.write_palette(&SONIC_DATA).unwrap();
+        manager.write_palette_verified(&SONIC_DATA).unwrap();
+
+        let expected = (SONIC_DATA.palette_offset, [0xFF, 0x7F].repeat(16));
+        assert_eq!(manager.pending_writes(), &[expected.clone(), expected][..]);
+        assert!(testing::contents(&file) == testing::blank_rom());
+        assert_eq!(manager.dirty_names(), vec!["Sonic"]);
+
+        manager.clear_pending_writes();
+        assert!(manager.pending_writes().is_empty());
+        manager.set_dry_run(false);
+        manager.write_palette(&SONIC_DATA).unwrap();
+        assert!(manager.pending_writes().is_empty());
+        assert!(manager.dirty_names().is_empty());
+    }
+
+    #[test]
+    fn rom_diffs_list_every_changed_slot() {
+        let original = testing::blank_rom();
+        let mut modded = original.clone();
+        let offset = TAILS_DATA.palette_offset as usize;
+        modded[offset + 6..offset + 8].copy_from_slice(&[0x1F, 0x00]);
+        modded[offset + 30..offset + 32].copy_from_slice(&[0xFF, 0x7F]);
+
+        let mut a = PaletteManager::from_bytes(original.clone());
+        let mut b = PaletteManager::from_bytes(modded);
+        let black = Color::rgb(0, 0, 0);
+        assert_eq!(diff_roms(&mut a, &mut b).unwrap(), vec![
+            ("Tails".to_string(), vec![(3, black, Color::rgb(255, 0, 0)), (15, black, Color::rgb(255, 255, 255))]),
+        ]);
+        assert!(diff_roms(&mut a, &mut PaletteManager::from_bytes(original)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn the_builder_applies_each_option() {
+        let path = testing::temp_path("builder.bak");
+        let manager = PaletteManagerBuilder::new()
+            .region(Region::Japan)
+            .dry_run(true)
+            .auto_backup(path.clone())
+            .cache_capacity(4)
+            .build(testing::rom_file(testing::blank_rom()))
+            .unwrap();
+        assert_eq!(manager.region(), Region::Japan);
+        assert!(manager.dry_run);
+        assert_eq!(manager.auto_backup, Some(path));
+
+        // left alone, everything is the same as `new`
+        let manager = PaletteManagerBuilder::new().build(testing::rom_file(Vec::new())).unwrap();
+        assert_eq!(manager.region(), Region::Usa);
+        assert!(!manager.dry_run);
+        assert_eq!(manager.auto_backup, None);
+    }
+
+    #[test]
+    fn recoloring_leaves_the_transparent_color_unless_told_otherwise() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x001F, 0x001F]);
+        manager.invert("Sonic").unwrap();
+        manager.to_grayscale("Sonic").unwrap();
+        manager.adjust_brightness("Sonic", 40).unwrap();
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap()[0], 0x001F);
+
+        manager.set_preserve_transparency(false);
+        manager.store_palette_i32("Tails".to_string(), vec![0x001F, 0x001F]);
+        manager.invert("Tails").unwrap();
+        assert_eq!(manager.load_palette_i32("Tails".to_string()).unwrap(), vec![0x7FE0, 0x7FE0]);
+    }
+
+    /// a ROM that accepts writes and throws them away, like a flaky flash cart writer
+    struct DroppingRom(Cursor<Vec<u8>>);
+
+    impl Read for DroppingRom {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for DroppingRom {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    impl Seek for DroppingRom {
+        fn seek(&mut self, from: SeekFrom) -> Result<u64, Error> {
+            self.0.seek(from)
+        }
+    }
+
+    #[test]
+    fn verified_writes_catch_dropped_writes() {
+        let mut manager = PaletteManager::new(Arc::new(Mutex::new(DroppingRom(Cursor::new(testing::blank_rom())))));
+        manager.store_palette_i32("Sonic".to_string(), vec![0x1234; 16]);
+        match manager.write_palette_verified(&SONIC_DATA) {
+            Err(PaletteError::VerifyFailed { ref character, offset }) => {
+                assert_eq!((character.as_str(), offset), ("Sonic", SONIC_DATA.palette_offset));
+            }
+            other => panic!("expected a failed verify, got {:?}", other),
+        }
+        assert_eq!(manager.dirty_names(), vec!["Sonic"]);
+
+        let mut manager = PaletteManager::from_bytes(testing::blank_rom());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x1234; 16]);
+        manager.write_palette_verified(&SONIC_DATA).unwrap();
+        assert!(manager.dirty_names().is_empty());
+    }
+
+    #[test]
+    fn every_palette_is_exported_to_its_own_file() {
+        let dir = testing::temp_path("export_all");
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x7C00; 16]);
+        manager.store_palette_i32("Tails".to_string(), vec![0x03FF; 16]);
+        manager.export_all(&dir, PaletteFormat::Gpl).unwrap();
+
+        let mut files: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        files.sort();
+        assert_eq!(files, vec!["Sonic.gpl", "Tails.gpl"]);
+        let mut expected = Vec::new();
+        manager.export_gpl("Tails", &mut expected).unwrap();
+        assert_eq!(fs::read(dir.join("Tails.gpl")).unwrap(), expected);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(PaletteFormat::from_extension("PNG"), Some(PaletteFormat::PngSwatch));
+        assert_eq!(PaletteFormat::from_extension("bmp"), None);
+    }
+
+    #[test]
+    fn exports_never_leave_the_directory() {
+        let dir = testing::temp_path("export_escape");
+        for &name in ["../Sonic", "a/b", "a\\b", "..", ".", "/tmp/Sonic", ""].iter() {
+            let mut manager = PaletteManager::from_bytes(Vec::new());
+            manager.store_palette_i32("Tails".to_string(), vec![0x03FF; 16]);
+            manager.store_palette_i32(name.to_string(), vec![0x7C00; 16]);
+            match manager.export_all(&dir.join("palettes"), PaletteFormat::Json) {
+                Err(PaletteError::UnsafeFileName(ref rejected)) => assert_eq!(rejected, name),
+                other => panic!("expected UnsafeFileName for {:?}, got {:?}", name, other),
+            }
+        }
+        assert!(!dir.exists());
+        assert!(is_plain_file_name("E-102..gamma"));
+    }
+
+    #[test]
+    fn directories_import_by_file_name_and_list_the_failures() {
+        let dir = testing::temp_path("import_all");
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x7C00; 16]);
+        manager.store_palette_i32("Tails".to_string(), vec![0x03FF; 16]);
+        manager.export_all(&dir, PaletteFormat::Json).unwrap();
+        fs::write(dir.join("notes.txt"), "not a palette").unwrap();
+
+        let mut imported = PaletteManager::from_bytes(Vec::new());
+        assert_eq!(imported.import_all(&dir, PaletteFormat::Json).unwrap(), vec!["Sonic", "Tails"]);
+        assert_eq!(imported.load_palette_i32("Tails".to_string()).unwrap(), vec![0x03FF; 16]);
+
+        fs::write(dir.join("Broken.json"), "{").unwrap();
+        match imported.import_all(&dir, PaletteFormat::Json) {
+            Err(PaletteError::ImportFailed(ref failures)) => {
+                assert_eq!(failures.len(), 1);
+                assert_eq!(failures[0].0, dir.join("Broken.json"));
+            }
+            other => panic!("expected a failed import, got {:?}", other),
+        }
+        let (names, failures) = imported.import_all_lenient(&dir, PaletteFormat::Json).unwrap();
+        assert_eq!((names, failures.len()), (vec!["Sonic".to_string(), "Tails".to_string()], 1));
+        assert!(!imported.has_palette("Broken"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn blends_mix_each_channel_by_the_ratio() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Black".to_string(), vec![0x0000, 0x001F]);
+        manager.store_palette_i32("White".to_string(), vec![0x7FFF, 0x7C00]);
+        manager.blend_palettes("Black", "White", 0.5, "Gray".to_string()).unwrap();
+        assert_eq!(manager.load_palette_colors("Gray".to_string()).unwrap(), vec![decode_gba(0x4210), decode_gba(0x4010)]);
+
+        for &(t, ref expected) in [(0.0, vec![0x0000, 0x001F]), (1.0, vec![0x7FFF, 0x7C00])].iter() {
+            manager.blend_palettes("Black", "White", t, "Mix".to_string()).unwrap();
+            assert_eq!(&manager.load_palette_i32("Mix".to_string()).unwrap(), expected);
+        }
+
+        manager.store_palette_i32("Short".to_string(), vec![0x0000]);
+        assert!(manager.blend_palettes("Black", "Short", 0.5, "Mix".to_string()).is_err());
+    }
+
+    #[test]
+    fn palette_distance_is_the_mean_over_the_slots() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("A".to_string(), vec![0x0000, 0x0000, 0x001F]);
+        manager.store_palette_i32("B".to_string(), vec![0x0000, 0x001F, 0x001F]);
+        assert_eq!(manager.palette_distance("A", "B", ColorMetric::EuclideanRgb).unwrap(), 255.0 / 3.0);
+        assert_eq!(manager.palette_distance("A", "A", ColorMetric::Ciede2000).unwrap(), 0.0);
+
+        manager.store_palette_i32("Empty".to_string(), Vec::new());
+        assert_eq!(manager.palette_distance("Empty", "Empty", ColorMetric::Ciede2000).unwrap(), 0.0);
+        assert!(manager.palette_distance("A", "Empty", ColorMetric::EuclideanRgb).is_err());
+    }
+
+    /// a ROM that refuses writes starting at one offset, like a bad sector
+    struct BadSectorRom {
+        inner: Cursor<Vec<u8>>,
+        bad: u64,
+    }
+
+    impl Read for BadSectorRom {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl Write for BadSectorRom {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            if self.inner.position() == self.bad {
+                return Err(Error::other("bad sector"));
+            }
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    impl Seek for BadSectorRom {
+        fn seek(&mut self, from: SeekFrom) -> Result<u64, Error> {
+            self.inner.seek(from)
+        }
+    }
+
+    #[test]
+    fn transactions_write_everything_or_nothing() {
+        let file = testing::rom_file(testing::blank_rom());
+        let mut manager = PaletteManager::new(file.clone());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x001F; 16]);
+        manager.store_palette_i32("Tails".to_string(), vec![0x03E0; 16]);
+
+        let failed = manager.transaction(|manager| {
+            manager.write_palette(&SONIC_DATA)?;
+            manager.write_palette(&TAILS_DATA)?;
+            Err(PaletteError::Malformed("changed my mind".to_string()))
+        });
+        assert!(failed.is_err());
+        assert!(testing::contents(&file) == testing::blank_rom());
+        assert_eq!(manager.dirty_names(), vec!["Sonic", "Tails"]);
+
+        manager.transaction(|manager| {
+            manager.write_palette(&SONIC_DATA)?;
+            // nested transactions join the outer one
+            manager.transaction(|manager| manager.write_palette(&TAILS_DATA))
+        }).unwrap();
+        assert_eq!(palette_in(&file, SONIC_DATA.palette_offset, 16), vec![0x001F; 16]);
+        assert_eq!(palette_in(&file, TAILS_DATA.palette_offset, 16), vec![0x03E0; 16]);
+        assert!(manager.dirty_names().is_empty());
+
+        // a write failing while the batch is made puts back the ones before it
+        let rom = Arc::new(Mutex::new(BadSectorRom { inner: Cursor::new(testing::blank_rom()), bad: TAILS_DATA.palette_offset }));
+        let mut manager = PaletteManager::new(rom.clone());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x001F; 16]);
+        manager.store_palette_i32("Tails".to_string(), vec![0x03E0; 16]);
+        assert!(manager.transaction(|manager| {
+            manager.write_palette(&SONIC_DATA)?;
+            manager.write_palette(&TAILS_DATA)
+        }).is_err());
+        assert!(*rom.lock().unwrap().inner.get_ref() == testing::blank_rom());
+        assert_eq!(manager.dirty_names(), vec!["Sonic", "Tails"]);
+    }
+
+    #[test]
+    fn verified_writes_in_a_transaction_are_checked_after_commit() {
+        let file = testing::rom_file(testing::blank_rom());
+        let mut manager = PaletteManager::new(file.clone());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x1234; 16]);
+        manager.transaction(|manager| {
+            manager.write_palette_verified(&SONIC_DATA)?;
+            // nothing has reached the ROM yet
+            assert_eq!(&testing::contents(&file)[0x47AFB8..0x47AFBA], &[0, 0]);
+            Ok(())
+        }).unwrap();
+        assert_eq!(&testing::contents(&file)[0x47AFB8..0x47AFBA], &[0x34, 0x12]);
+
+        let mut manager = PaletteManager::new(Arc::new(Mutex::new(DroppingRom(Cursor::new(testing::blank_rom())))));
+        manager.store_palette_i32("Sonic".to_string(), vec![0x1234; 16]);
+        let result = manager.transaction(|manager| manager.write_palette_verified(&SONIC_DATA));
+        assert!(matches!(result, Err(PaletteError::VerifyFailed { .. })));
+        assert_eq!(manager.dirty_names(), vec!["Sonic"]);
+    }
+
+    #[test]
+    fn failed_transactions_put_the_stored_palettes_back() {
+        let file = testing::rom_file(testing::blank_rom());
+        let mut manager = PaletteManager::new(file.clone());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x001F; 16]);
+        let undo_depth = manager.undo_depth();
+
+        assert!(manager.transaction(|manager| {
+            manager.store_palette_i32("Tails".to_string(), vec![0x03E0; 16]);
+            manager.write_palette(&TAILS_DATA)?;
+            manager.set_color_gba("Sonic", 0, 0x7C00)?;
+            Err(PaletteError::Malformed("changed my mind".to_string()))
+        }).is_err());
+        assert_eq!(manager.palette_names(), vec!["Sonic"]);
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x001F; 16]);
+        assert_eq!((manager.undo_depth(), manager.redo_depth()), (undo_depth, 0));
+
+        // the verified write is overwritten later in the batch, so its read back fails once the
+        // writes are made and they're all put back
+        match manager.transaction(|manager| {
+            manager.store_palette_i32("Tails".to_string(), vec![0x03E0; 16]);
+            manager.write_palette(&TAILS_DATA)?;
+            manager.write_palette_verified(&SONIC_DATA)?;
+            manager.store_palette_i32("Sonic".to_string(), vec![0x7FFF; 16]);
+            manager.write_palette(&SONIC_DATA)
+        }) {
+            Err(PaletteError::VerifyFailed { ref character, .. }) => assert_eq!(character, "Sonic"),
+            other => panic!("expected VerifyFailed, got {:?}", other),
+        }
+        assert!(testing::contents(&file) == testing::blank_rom());
+        assert_eq!(manager.palette_names(), vec!["Sonic"]);
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x001F; 16]);
+        assert_eq!(manager.dirty_names(), vec!["Sonic"]);
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn roms_are_found_in_and_saved_to_zips() {
+        let path = testing::temp_path("roms.zip");
+        let rom: Vec<u8> = (0..0x1000).map(|i| (i * 7) as u8).collect();
+        PaletteManager::from_bytes(rom.clone()).save_to_zip(&path, "Sonic Battle.GBA").unwrap();
+        let manager = PaletteManager::from_zip(&path, None).unwrap();
+        assert_eq!(manager.into_bytes(), rom);
+
+        // with two ROMs in the archive, one has to be picked
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        for &name in ["a.gba", "readme.txt", "b.gba"].iter() {
+            writer.start_file(name, FileOptions::default()).unwrap();
+            writer.write_all(name.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+        match PaletteManager::from_zip(&path, None) {
+            Err(PaletteError::Archive(ref message)) => assert!(message.starts_with("2 .gba files")),
+            other => panic!("expected an archive error, got {:?}", other.err()),
+        }
+        assert_eq!(PaletteManager::from_zip(&path, Some("b.gba")).unwrap().into_bytes(), b"b.gba".to_vec());
+        assert!(PaletteManager::from_zip(&path, Some("c.gba")).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn palette_views_index_and_iterate_decoded_colors() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x001F, 0x03E0, 0x7C00]);
+        let palette = manager.palette("sonic").unwrap();
+        assert_eq!((palette.len(), palette.is_empty()), (3, false));
+        assert_eq!(palette[1], Color::rgb(0, 255, 0));
+        assert_eq!(palette.get(2), Some(Color::rgb(0, 0, 255)));
+        assert_eq!(palette.get(3), None);
+        assert_eq!(palette.values(), &[0x001F, 0x03E0, 0x7C00]);
+
+        let colors: Vec<Color> = palette.into_iter().collect();
+        assert_eq!(colors, manager.load_palette_colors("Sonic".to_string()).unwrap());
+        assert!(manager.palette("Tails").is_err());
+    }
+
+    #[test]
+    fn fingerprints_are_stable_and_follow_the_order() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x001F, 0x7C00]);
+        manager.store_palette_i32("Copy".to_string(), vec![0x001F, 0x7C00]);
+        manager.store_palette_i32("Swapped".to_string(), vec![0x7C00, 0x001F]);
+
+        // FNV-1a of each value's four little endian bytes, so this never changes between runs
+        assert_eq!(manager.palette_fingerprint("Sonic").unwrap(), 0x167217EE4A5BC906);
+        assert_eq!(manager.palette_fingerprint("Copy").unwrap(), 0x167217EE4A5BC906);
+        assert_ne!(manager.palette_fingerprint("Swapped").unwrap(), 0x167217EE4A5BC906);
+        assert!(manager.palette_fingerprint("Tails").is_err());
+    }
+
+    #[test]
+    fn peeking_leaves_the_stored_palette_alone() {
+        let bytes = testing::random_rom(85);
+        let mut manager = PaletteManager::from_bytes(bytes.clone());
+        let start = SONIC_DATA.palette_offset as usize;
+        let in_rom: Vec<Color> = decode_palette(&bytes[start..start + 32]).unwrap().into_iter().map(decode_gba).collect();
+        assert_eq!(manager.peek_palette(&SONIC_DATA).unwrap(), in_rom);
+        assert!(!manager.has_palette("Sonic"));
+
+        manager.store_palette_i32("Sonic".to_string(), vec![0x7FFF; 16]);
+        assert_eq!(manager.peek_palette(&SONIC_DATA).unwrap(), in_rom);
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x7FFF; 16]);
+        assert_eq!(manager.dirty_names(), vec!["Sonic"]);
+    }
+
+    #[test]
+    fn presets_are_stored_and_written() {
+        let file = testing::rom_file(testing::blank_rom());
+        let mut manager = PaletteManager::new(file.clone());
+        manager.apply_preset(&TAILS_DATA, "GRAYSCALE").unwrap();
+        assert_eq!(manager.load_palette_i32("Tails".to_string()).unwrap(), presets::PALETTE_PRESETS[0].1.to_vec());
+        let offset = TAILS_DATA.palette_offset as usize;
+        assert_eq!(&testing::contents(&file)[offset + 30..offset + 32], &[0xFF, 0x7F]);
+
+        match manager.apply_preset(&TAILS_DATA, "neon") {
+            Err(PaletteError::UnknownPreset { ref name, ref available }) => {
+                assert_eq!(name, "neon");
+                assert_eq!(available, &presets::preset_names());
+            }
+            other => panic!("expected an unknown preset, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn progress_is_reported_after_every_character() {
+        let mut manager = PaletteManager::from_bytes(testing::blank_rom());
+        let mut calls = Vec::new();
+        manager.read_palettes_with_progress(|done, total| calls.push((done, total))).unwrap();
+        let expected: Vec<_> = (1..=CHARACTERS.len()).map(|done| (done, CHARACTERS.len())).collect();
+        assert_eq!(calls, expected);
+
+        calls.clear();
+        manager.write_palettes_with_progress(|done, total| calls.push((done, total))).unwrap();
+        assert_eq!(calls, expected);
+    }
+
+    #[test]
+    fn alpha_survives_a_png_swatch_but_index_0_is_transparent() {
+        let colors = vec![Color::rgb(1, 2, 3), Color::rgba(10, 20, 30, 128), Color::rgba(40, 50, 60, 0), Color::rgb(70, 80, 90)];
+        let mut png = Vec::new();
+        write_swatch_png(slice::from_ref(&colors), &mut png, 4).unwrap();
+        let decoded = decode_png_swatch(&png).unwrap();
+
+        assert!(decoded[0].eq_rgba(&Color::rgba(1, 2, 3, 0)));
+        for (decoded, color) in decoded.iter().zip(colors.iter()).skip(1) {
+            assert!(decoded.eq_rgba(color), "{:?} came back as {:?}", color, decoded);
+        }
+    }
+
+    #[test]
+    fn open_strict_checks_the_rom_size() {
+        let manager = PaletteManager::open_strict(testing::rom_file(testing::blank_rom())).unwrap();
+        assert_eq!(manager.region(), Region::Usa);
+
+        let mut small = testing::blank_rom();
+        small.truncate(0x1000);
+        match PaletteManager::open_strict(testing::rom_file(small)) {
+            Err(PaletteError::Rom(RomError::BadSize(0x1000))) => (),
+            other => panic!("expected a bad size, got {:?}", other.err()),
+        }
+
+        let mut odd = testing::blank_rom();
+        odd.truncate(12 << 20);
+        match PaletteManager::open_strict(testing::rom_file(odd)) {
+            Err(PaletteError::Rom(RomError::OddSize(size))) => assert_eq!(size, 12 << 20),
+            other => panic!("expected an odd size, got {:?}", other.err()),
+        }
+
+        let mut wrong_game = testing::blank_rom();
+        wrong_game[rom::HEADER_GAME_CODE as usize..rom::HEADER_GAME_CODE as usize + 4].copy_from_slice(b"AXVE");
+        assert!(matches!(PaletteManager::open_strict(testing::rom_file(wrong_game)),
+            Err(PaletteError::Rom(RomError::WrongGame { .. }))));
+    }
+
+    #[test]
+    fn the_character_table_needs_gba_sized_palettes() {
+        check_character_table(&CHARACTERS).unwrap();
+        check_character_table(&[Character { color_count: 256, ..SONIC_DATA }]).unwrap();
+        match check_character_table(&[SONIC_DATA, Character { name: "Odd", color_count: 17, ..SONIC_DATA }]) {
+            Err(PaletteError::BadColorCount { ref character, count: 17 }) => assert_eq!(character, "Odd"),
+            other => panic!("expected a bad color count, got {:?}", other),
+        }
+        assert!(check_character_table(&[Character { color_count: 0, ..SONIC_DATA }]).is_err());
+    }
+
+    #[test]
+    fn unloaded_characters_shrink_as_palettes_are_read() {
+        let mut manager = PaletteManager::from_bytes(testing::blank_rom());
+        assert_eq!(manager.unloaded_characters().len(), CHARACTERS.len());
+        manager.read_palette(&SONIC_DATA).unwrap();
+        manager.store_palette_i32("tails".to_string(), vec![0; 16]);
+        let names: Vec<&str> = manager.unloaded_characters().iter().map(|character| character.name).collect();
+        assert_eq!(names.len(), CHARACTERS.len() - 2);
+        assert!(!names.contains(&"Sonic") && !names.contains(&"Tails"));
+
+        manager.read_palettes().unwrap();
+        assert!(manager.unloaded_characters().is_empty());
+    }
+
+    #[test]
+    fn borrowed_palettes_are_the_stored_values() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x001F, 0x7FFF]);
+        let first = manager.load_palette_ref("Sonic").unwrap();
+        let second = manager.load_palette_ref("SONIC").unwrap();
+        assert_eq!(first, &[0x001F, 0x7FFF]);
+        // both borrow the one stored copy
+        assert_eq!(first.as_ptr(), second.as_ptr());
+        assert!(matches!(manager.load_palette_ref("Tails"), Err(PaletteError::PaletteNotFound(_))));
+    }
+
+    #[test]
+    fn staged_edits_wait_for_a_commit() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x0000, 0x0000]);
+        let depth = manager.undo_depth();
+
+        manager.stage_color("Sonic", 1, Color::rgb(255, 0, 0)).unwrap();
+        assert_eq!(manager.staged_palette("Sonic").unwrap(), &[0x0000, 0x001F]);
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x0000, 0x0000]);
+        assert_eq!(manager.staged_names(), vec!["Sonic"]);
+        assert!(matches!(manager.stage_color("Sonic", 2, Color::rgb(0, 0, 0)), Err(PaletteError::IndexOutOfBounds { index: 2, len: 2, .. })));
+
+        manager.discard_staged();
+        assert_eq!(manager.staged_palette("Sonic").unwrap(), &[0x0000, 0x0000]);
+        assert!(manager.staged_names().is_empty());
+
+        manager.stage_color("Sonic", 0, Color::rgb(0, 0, 255)).unwrap();
+        manager.commit_staged();
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), vec![0x7C00, 0x0000]);
+        assert_eq!(manager.dirty_names(), vec!["Sonic"]);
+        assert_eq!(manager.undo_depth(), depth + 1);
+        assert!(manager.staged_names().is_empty());
+    }
+
+    #[test]
+    fn common_palettes_are_read_under_their_names() {
+        let bytes = testing::random_rom(95);
+        let mut manager = PaletteManager::from_bytes(bytes.clone());
+        manager.read_common_palettes().unwrap();
+        for palette in COMMON_PALETTES.iter() {
+            let start = palette.offset as usize;
+            let expected = decode_palette(&bytes[start..start + 2 * palette.color_count]).unwrap();
+            assert_eq!(manager.load_palette_i32(palette.name.to_string()).unwrap(), expected, "{}", palette.name);
+        }
+        assert!(manager.dirty_names().is_empty());
+
+        let mut japan = PaletteManager::from_bytes(bytes);
+        japan.set_region(Region::Japan);
+        assert!(matches!(japan.read_common_palettes(), Err(PaletteError::UnsupportedRegion(Region::Japan))));
+    }
+
+    #[test]
+    fn colors_load_through_a_shared_borrow() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x001F, 0x03E0]);
+        let (a, b) = (&manager, &manager);
+        assert_eq!(a.load_palette_colors("Sonic".to_string()).unwrap(), b.load_palette_colors("sonic".to_string()).unwrap());
+        assert_eq!(a.load_palette_colors("Sonic".to_string()).unwrap(), vec![Color::rgb(255, 0, 0), Color::rgb(0, 255, 0)]);
+        assert!(b.load_palette_colors("Tails".to_string()).is_err());
+    }
+
+    #[test]
+    fn loading_and_storing_colors_goes_through_the_cache() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x7C00, 0x03E0, 0x7C00]);
+        assert!(!manager.color_cache.lock().unwrap().contains_gba(0x7C00));
+        let colors = manager.load_palette_colors("Sonic".to_string()).unwrap();
+        assert_eq!(colors, vec![decode_gba(0x7C00), decode_gba(0x03E0), decode_gba(0x7C00)]);
+        assert!(manager.color_cache.lock().unwrap().contains_gba(0x7C00));
+
+        manager.store_palette_colors("Tails".to_string(), vec![Color::rgb(0, 0, 255)]);
+        assert!(manager.color_cache.lock().unwrap().contains_rgb(Color::rgb(0, 0, 255)));
+        assert_eq!(manager.load_palette_i32("Tails".to_string()).unwrap(), vec![0x7C00]);
+    }
+
+    #[test]
+    fn raw_exports_swap_each_pair_for_big_endian() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Sonic".to_string(), vec![0x7C1F, 0x0123]);
+        let mut little = Vec::new();
+        manager.export_raw("Sonic", ByteOrder::Little, &mut little).unwrap();
+        assert_eq!(little, vec![0x1F, 0x7C, 0x23, 0x01]);
+        let mut big = Vec::new();
+        manager.export_raw("Sonic", ByteOrder::Big, &mut big).unwrap();
+        assert_eq!(big, vec![0x7C, 0x1F, 0x01, 0x23]);
+        assert!(manager.export_raw("Tails", ByteOrder::Big, &mut big).is_err());
+    }
+
+    #[test]
+    fn animations_run_until_a_block_stops_looking_like_a_palette() {
+        let mut rom = vec![0u8; 0x200];
+        for frame in 0..3 {
+            let start = 0x40 + frame * 32;
+            for i in 0..16 {
+                rom[start + 2 * i..start + 2 * i + 2].copy_from_slice(&gba_to_le_bytes((frame * 16 + i) as i32 + 1));
+            }
+        }
+        let mut manager = PaletteManager::from_bytes(rom.clone());
+        // the fourth block is blank
+        assert_eq!(manager.detect_animation_frames("Glow", 0x40, 8).unwrap(), 3);
+        assert_eq!(manager.load_palette_i32("Glow#2".to_string()).unwrap(), (33..49).collect::<Vec<i32>>());
+        assert!(!manager.has_palette("Glow#3"));
+        assert_eq!(manager.detect_animation_frames("Short", 0x40, 2).unwrap(), 2);
+
+        // a set top bit means code or tiles rather than colors
+        rom[0x41] = 0x80;
+        let mut manager = PaletteManager::from_bytes(rom);
+        assert_eq!(manager.detect_animation_frames("Glow", 0x40, 8).unwrap(), 0);
+        // running off the end of the ROM just ends the animation
+        assert_eq!(manager.detect_animation_frames("End", 0x1F0, 8).unwrap(), 0);
+    }
+
+    #[test]
+    fn animation_gifs_have_a_palette_per_frame() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Glow#0".to_string(), vec![0x001F, 0x0000]);
+        manager.store_palette_i32("Glow#1".to_string(), vec![0x7C00, 0x7FFF]);
+        let mut gif_bytes = Vec::new();
+        manager.export_animation_gif(&["Glow#0", "Glow#1"], &mut gif_bytes, 150).unwrap();
+
+        let mut decoder = gif::Decoder::new(&gif_bytes[..]);
+        decoder.set(gif::ColorOutput::Indexed);
+        let mut reader = decoder.read_info().unwrap();
+        let mut palettes = Vec::new();
+        while let Some(frame) = reader.read_next_frame().unwrap() {
+            assert_eq!((frame.width as u32, frame.height as u32, frame.delay), (2 * MONTAGE_SWATCH_SIZE, MONTAGE_SWATCH_SIZE, 15));
+            assert_eq!(frame.buffer[MONTAGE_SWATCH_SIZE as usize], 1);
+            palettes.push(frame.palette.clone().unwrap());
+        }
+        assert_eq!(palettes, vec![vec![255, 0, 0, 0, 0, 0], vec![0, 0, 255, 255, 255, 255]]);
+
+        manager.store_palette_i32("Glow#2".to_string(), vec![0x0000]);
+        assert!(matches!(manager.export_animation_gif(&["Glow#0", "Glow#2"], &mut Vec::new(), 100),
+                         Err(PaletteError::FrameLength { expected: 2, found: 1, .. })));
+        assert!(manager.export_animation_gif(&[], &mut Vec::new(), 100).is_err());
+    }
+
+    #[test]
+    fn sorted_views_keep_each_color_with_its_index() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        let values = vec![0x7C00, 0x001F, 0x7FFF, 0x03E0, 0x0000];
+        manager.store_palette_i32("Sonic".to_string(), values.clone());
+        let order = |manager: &PaletteManager<_>, key| -> Vec<usize> {
+            manager.sorted_colors("Sonic", key).unwrap().iter().map(|&(index, _)| index).collect()
+        };
+
+        // grays come first in the order they appear, then red, green and blue by hue
+        assert_eq!(order(&manager, SortKey::Hue), vec![2, 4, 1, 3, 0]);
+        assert_eq!(order(&manager, SortKey::Luminance), vec![4, 0, 1, 3, 2]);
+        assert_eq!(order(&manager, SortKey::RawValue), vec![4, 1, 3, 0, 2]);
+        assert_eq!(manager.sorted_colors("Sonic", SortKey::RawValue).unwrap()[0], (4, Color::rgb(0, 0, 0)));
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), values);
+    }
+
+    #[test]
+    fn every_palette_slot_is_read_and_written_under_its_own_name() {
+        let character = Character { extra_palette_offsets: &[0x47B000, 0x47B020], ..SONIC_DATA };
+        assert_eq!((character.palette_slots(), character.slot_name(0), character.slot_name(2)), (3, "Sonic".to_string(), "Sonic#2".to_string()));
+
+        let bytes = testing::random_rom(105);
+        let file = testing::rom_file(bytes.clone());
+        let mut manager = PaletteManager::new(file.clone());
+        manager.read_palette(&character).unwrap();
+        assert_eq!(manager.load_palette_i32("Sonic#1".to_string()).unwrap(), decode_palette(&bytes[0x47B000..0x47B020]).unwrap());
+
+        manager.set_color_gba("Sonic#2", 0, 0x7FFF).unwrap();
+        manager.remove_palette("Sonic#1");
+        manager.write_palette(&character).unwrap();
+        let written = testing::contents(&file);
+        assert_eq!(&written[0x47B020..0x47B022], &[0xFF, 0x7F]);
+        // a slot that isn't stored is left as it is
+        assert_eq!(&written[0x47B000..0x47B020], &bytes[0x47B000..0x47B020]);
+
+        assert!(matches!(manager.read_palette_slot(&character, 3), Err(PaletteError::IndexOutOfBounds { index: 3, len: 3, .. })));
+        manager.set_region(Region::Japan);
+        assert!(matches!(manager.write_palette_slot(&character, 1), Err(PaletteError::UnsupportedRegion(_))));
+    }
+
+    #[test]
+    fn colors_are_found_across_every_stored_palette() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        manager.store_palette_i32("Tails".to_string(), vec![0x7C00, 0x0000, 0x7C00]);
+        manager.store_palette_i32("Sonic".to_string(), vec![0x0000, 0x7C00]);
+        manager.store_palette_i32("Shadow".to_string(), vec![0x7C01]);
+
+        // exact matches compare GBA values, so 252 blue is the same as 255
+        assert_eq!(manager.find_color(Color::rgb(0, 0, 252), 0),
+                   vec![("Sonic".to_string(), 1), ("Tails".to_string(), 0), ("Tails".to_string(), 2)]);
+        // 0x7C01 decodes with a red of 8
+        assert_eq!(manager.find_color(Color::rgb(0, 0, 255), 8).len(), 4);
+        assert_eq!(manager.find_color(Color::rgb(0, 0, 255), 7).len(), 3);
+        assert!(manager.find_color(Color::rgb(255, 255, 255), 10).is_empty());
+    }
+
+    #[test]
+    fn reverting_rereads_the_rom_and_forgets_the_edits() {
+        let bytes = testing::random_rom(108);
+        let mut manager = PaletteManager::from_bytes(bytes.clone());
+        manager.read_palette(&SONIC_DATA).unwrap();
+        manager.read_palette(&TAILS_DATA).unwrap();
+        let original = manager.load_palette_i32("Sonic".to_string()).unwrap();
+        let tails = manager.load_palette_i32("Tails".to_string()).unwrap();
+        manager.set_color_gba("Sonic", 0, 0x7FFF).unwrap();
+        manager.set_color_gba("Tails", 0, 0x7FFF).unwrap();
+        manager.stage_color("Sonic", 1, Color::rgb(0, 0, 0)).unwrap();
+
+        manager.revert(&SONIC_DATA).unwrap();
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), original);
+        assert_eq!(manager.dirty_names(), vec!["Tails"]);
+        assert!(manager.staged_names().is_empty());
+        // only the other character's edit is left to undo
+        manager.undo().unwrap();
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap(), original);
+        assert_eq!(manager.load_palette_i32("Tails".to_string()).unwrap(), tails);
+    }
+
+    #[test]
+    fn index_usage_counts_the_pixels_of_every_frame() {
+        let frame = FRAME_SIZE * FRAME_SIZE * TILE_4BPP_BYTES;
+        let character = Character { sprite_offset: 0x200000, sprite_frames: &[1, 1], ..SONIC_DATA };
+        let mut bytes = testing::blank_rom();
+        // two pixels a byte, the low nibble first
+        for byte in bytes[0x200000..0x200000 + frame].iter_mut() {
+            *byte = 0x21;
+        }
+        for byte in bytes[0x200000 + frame..0x200000 + 2 * frame].iter_mut() {
+            *byte = 0x33;
+        }
+        bytes[0x200000 + 2 * frame] = 0xFF;
+
+        let manager = PaletteManager::from_bytes(bytes);
+        let usage = manager.index_usage(&character).unwrap();
+        let pixels = frame as u32;
+        let mut expected = [0u32; PALETTE_SIZE];
+        expected[1] = pixels;
+        expected[2] = pixels;
+        expected[3] = 2 * pixels;
+        assert_eq!(usage, expected);
+        assert_eq!(usage.iter().sum::<u32>(), 4 * pixels);
+    }
+
+    #[test]
+    fn index_usage_refuses_unknown_frame_counts() {
+        let manager = PaletteManager::from_bytes(testing::blank_rom());
+        match manager.index_usage(&EMERL_DATA) {
+            Err(PaletteError::UnknownSpriteFrames(ref name)) => assert_eq!(name, "Emerl"),
+            other => panic!("expected UnknownSpriteFrames, got {:?}", other.map(|_| ())),
+        }
+        // a -1 anywhere would shrink the sum and silently drop frames
+        let character = Character { sprite_frames: &[4, -1, 4], ..SONIC_DATA };
+        assert!(manager.index_usage(&character).is_err());
+    }
+
+    #[test]
+    fn aseprite_files_carry_the_palette_both_ways() {
+        let mut manager = PaletteManager::from_bytes(Vec::new());
+        let values: Vec<i32> = (0..16).map(|value| value * 0x0842).collect();
+        manager.store_palette_i32("Sonic".to_string(), values.clone());
+        let mut file = Vec::new();
+        manager.export_aseprite_palette("Sonic", &mut file).unwrap();
+        assert_eq!(&file[..6], &[file.len() as u8, (file.len() >> 8) as u8, 0, 0, 0xE0, 0xA5]);
+        manager.import_aseprite_palette("Copy".to_string(), &mut &file[..]).unwrap();
+        assert_eq!(manager.load_palette_i32("Copy".to_string()).unwrap(), values);
+
+        // files from before Aseprite 1.2 only have the old chunk, 2 colors starting at index 1 here
+        let mut old = vec![0u8; ASEPRITE_HEADER_SIZE];
+        old[4..6].copy_from_slice(&[0xE0, 0xA5]);
+        old.extend_from_slice(&[0, 0, 0, 0, 0xFA, 0xF1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        old.extend_from_slice(&[16, 0, 0, 0, 0x04, 0x00, 1, 0, 1, 2, 255, 0, 0, 0, 0, 255]);
+        manager.import_aseprite_palette("Old".to_string(), &mut &old[..]).unwrap();
+        assert_eq!(manager.load_palette_i32("Old".to_string()).unwrap(), vec![0x0000, 0x001F, 0x7C00]);
+
+        assert!(manager.import_aseprite_palette("Bad".to_string(), &mut &file[..100]).is_err());
+        assert!(manager.import_aseprite_palette("Bad".to_string(), &mut &b"PNG"[..]).is_err());
+        manager.store_palette_i32("Empty".to_string(), Vec::new());
+        assert!(manager.export_aseprite_palette("Empty", &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn palettes_inside_the_header_are_refused() {
+        let bytes = testing::random_rom(111);
+        let file = testing::rom_file(bytes.clone());
+        let mut manager = PaletteManager::new(file.clone());
+        for &offset in [0, MIN_PALETTE_OFFSET - 1].iter() {
+            let broken = Character { palette_offset: offset, ..SONIC_DATA };
+            assert!(matches!(manager.read_palette(&broken), Err(PaletteError::OffsetInHeader { offset: found, .. }) if found == offset));
+            manager.store_palette_i32("Sonic".to_string(), vec![0x7FFF; 16]);
+            assert!(matches!(manager.write_palette(&broken), Err(PaletteError::OffsetInHeader { .. })));
+        }
+        assert!(testing::contents(&file) == bytes);
+
+        let lowest = Character { palette_offset: MIN_PALETTE_OFFSET, ..SONIC_DATA };
+        manager.write_palette(&lowest).unwrap();
+    }
+
+    #[test]
+    fn unchecked_writes_keep_the_unused_bit() {
+        let file = testing::rom_file(testing::blank_rom());
+        let mut manager = PaletteManager::new(file.clone());
+        manager.read_palette(&SONIC_DATA).unwrap();
+        manager.set_color_gba("Sonic", 3, 0xFC1F).unwrap();
+        assert_eq!(manager.load_palette_i32("Sonic".to_string()).unwrap()[3], 0x7C1F);
+        assert!(manager.store_palette_i32_checked("Sonic".to_string(), vec![0x7FFF, 0x8000]).is_err());
+
+        let mut colors = vec![0x7FFF; 16];
+        colors[5] = 0x8000;
+        colors[6] = 0x1_801F;
+        manager.store_palette_i32("Sonic".to_string(), colors);
+        manager.write_palette(&SONIC_DATA).unwrap();
+        let offset = SONIC_DATA.palette_offset as usize;
+        assert_eq!(&testing::contents(&file)[offset + 10..offset + 14], &[0x00, 0x80, 0x1F, 0x80]);
+
+        // the GBA ignores the top bit, so reading it back drops it
+        let mut reopened = PaletteManager::new(file);
+        reopened.read_palette(&SONIC_DATA).unwrap();
+        assert_eq!(&reopened.load_palette_i32("Sonic".to_string()).unwrap()[5..7], &[0x0000, 0x001F]);
+    }
+
+    #[test]
+    fn parallel_reads_match_the_sequential_ones() {
+        let path = testing::temp_path("parallel.gba");
+        let bytes = testing::random_rom(113);
+        fs::write(&path, &bytes).unwrap();
+        let mut sequential = PaletteManager::from_bytes(bytes.clone());
+        sequential.read_palettes().unwrap();
+
+        for &threads in [0, 1, 3, 64].iter() {
+            let mut manager = open_file(&path);
+            manager.file.lock().unwrap().seek(SeekFrom::Start(7)).unwrap();
+            manager.read_palettes_parallel(threads).unwrap();
+            assert_eq!(manager.palette_names(), sequential.palette_names());
+            for name in sequential.palette_names() {
+                assert_eq!(manager.load_palette_ref(name).unwrap(), sequential.load_palette_ref(name).unwrap(), "{} with {} threads", name, threads);
+            }
+            // positional reads leave the file where it was
+            assert_eq!(manager.file.lock().unwrap().stream_position().unwrap(), 7);
+        }
+
+        fs::write(&path, vec![0u8; 0x1000]).unwrap();
+        assert!(matches!(open_file(&path).read_palettes_parallel(4), Err(PaletteError::Io { at: Some(_), .. })));
+
+        // the palette furthest into the ROM is cut off while the others read fine, none are kept
+        let last = CHARACTERS.iter().map(|character| character.palette_offset).max().unwrap();
+        fs::write(&path, &bytes[..last as usize]).unwrap();
+        let mut manager = open_file(&path);
+        assert!(manager.read_palettes_parallel(CHARACTERS.len()).is_err());
+        assert!(manager.palette_names().is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn io_errors_name_the_palette_and_offset() {
+        let mut manager = PaletteManager::from_bytes(vec![0u8; 0x1000]);
+        match manager.read_palette(&SONIC_DATA) {
+            Err(error @ PaletteError::Io { at: Some(_), .. }) => {
+                assert_eq!(error.to_string(), format!("io error on Sonic's palette at {:#X}: failed to fill whole buffer", SONIC_DATA.palette_offset));
+                if let PaletteError::Io { ref source, at: Some((ref character, offset)) } = error {
+                    assert_eq!((character.as_str(), offset, source.kind()), ("Sonic", SONIC_DATA.palette_offset, ErrorKind::UnexpectedEof));
+                }
+            }
+            other => panic!("expected an io error with context, got {:?}", other),
+        }
+
+        // a ROM opened read only fails on the write
+        let path = testing::temp_path("read_only.gba");
+        fs::write(&path, testing::blank_rom()).unwrap();
+        let mut manager = PaletteManager::new(Arc::new(Mutex::new(File::open(&path).unwrap())));
+        manager.store_palette_i32("Tails".to_string(), vec![0; 16]);
+        match manager.write_palette(&TAILS_DATA) {
+            Err(PaletteError::Io { at: Some((ref character, offset)), .. }) => assert_eq!((character.as_str(), offset), ("Tails", TAILS_DATA.palette_offset)),
+            other => panic!("expected an io error with context, got {:?}", other),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn deduplicating_points_copies_at_the_first_palette() {
+        let (sonic, mut bytes) = pointed_rom();
+        let knuckles = Character { pointer_location: Some(0x100004), ..KNUCKLES_DATA };
+        let shadow = Character { pointer_location: Some(0x100008), ..SHADOW_DATA };
+        bytes[0x100004..0x100008].copy_from_slice(&rom::rom_pointer_bytes(KNUCKLES_DATA.palette_offset));
+        bytes[0x100008..0x10000C].copy_from_slice(&rom::rom_pointer_bytes(SHADOW_DATA.palette_offset));
+        let file = testing::rom_file(bytes.clone());
+        let mut manager = PaletteManager::new(file.clone());
+        let characters = [sonic, knuckles, shadow];
+        for character in characters.iter() {
+            manager.read_palette(character).unwrap();
+        }
+        let colors = manager.load_palette_i32("Sonic".to_string()).unwrap();
+        manager.store_palette_i32("Knuckles".to_string(), colors.clone());
+
+        let merged = manager.deduplicate_characters(&characters).unwrap();
+        assert_eq!(merged, vec![("Knuckles".to_string(), "Sonic".to_string())]);
+        let sonic_offset = SONIC_DATA.palette_offset;
+        assert_eq!(rom::read_rom_pointer(&mut *file.lock().unwrap(), 0x100004).unwrap(), sonic_offset);
+        assert_eq!(rom::read_rom_pointer(&mut *file.lock().unwrap(), 0x100000).unwrap(), sonic_offset);
+        assert_eq!(rom::read_rom_pointer(&mut *file.lock().unwrap(), 0x100008).unwrap(), SHADOW_DATA.palette_offset);
+        let contents = testing::contents(&file);
+        let sonic_start = sonic_offset as usize;
+        assert_eq!(decode_palette(&contents[sonic_start..sonic_start + 32]).unwrap(), colors);
+        let knuckles_start = KNUCKLES_DATA.palette_offset as usize;
+        assert_eq!(&contents[knuckles_start..knuckles_start + 32], &bytes[knuckles_start..knuckles_start + 32]);
+
+        manager.read_palette(&characters[1]).unwrap();
+        assert_eq!(manager.load_palette_i32("Knuckles".to_string()).unwrap(), colors);
+        // they share an offset now, so there's nothing left to merge
+        assert!(manager.deduplicate_characters(&characters).unwrap().is_empty());
+    }
+
+    #[test]
+    fn deduplicating_without_pointers_fails() {
+        let mut manager = PaletteManager::from_bytes(testing::blank_rom());
+        manager.read_palettes().unwrap();
+        match manager.deduplicate() {
+            Err(PaletteError::NoPointer(ref name)) => assert_eq!(name, "Sonic"),
+            other => panic!("expected NoPointer, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn raw_dumps_import_like_rom_bytes() {
+        let bytes = testing::random_rom(116);
+        let start = SONIC_DATA.palette_offset as usize;
+        let dump = &bytes[start..start + 32];
+        let mut manager = PaletteManager::from_bytes(bytes.clone());
+        manager.import_raw("Dump".to_string(), &mut &dump[..], 16).unwrap();
+        manager.read_palette(&SONIC_DATA).unwrap();
+        assert_eq!(manager.load_palette_i32("Dump".to_string()).unwrap(), manager.load_palette_i32("Sonic".to_string()).unwrap());
+
+        let mut exported = Vec::new();
+        manager.export_raw("Dump", ByteOrder::Little, &mut exported).unwrap();
+        // the unused top bit of each color is dropped on the way in
+        let masked: Vec<u8> = dump.iter().enumerate().map(|(i, &byte)| if i % 2 == 1 { byte & 0x7F } else { byte }).collect();
+        assert_eq!(exported, masked);
+
+        assert!(manager.import_raw("Short".to_string(), &mut &dump[..31], 16).is_err());
+        assert!(!manager.has_palette("Short"));
     }
 }
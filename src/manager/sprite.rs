@@ -15,9 +15,9 @@ use ::engine::*;
 use ::manager::*;
 
 // colors used for the background in spritesheets
-const PURPLE_1: Color = Color { r: 255, g: 0, b: 250 };
-const PURPLE_2: Color = Color { r: 185, g: 0, b: 255 };
-const PURPLE_3: Color = Color { r: 185, g: 0, b: 185 }; // no frame
+const PURPLE_1: Color = Color::rgb(255, 0, 250);
+const PURPLE_2: Color = Color::rgb(185, 0, 255);
+const PURPLE_3: Color = Color::rgb(185, 0, 185); // no frame
 
 /// normal character sprites are 6x6 sections
 pub const FRAME_SIZE: usize = 6;
@@ -78,7 +78,8 @@ impl Spritesheet {
     /// convert an image to a spritesheet
     pub fn from_img(image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>, character: &Character) -> Result<(Spritesheet, Vec<Color>), Error> {
         let mut spritesheet = Spritesheet::new();
-        let mut palette = vec![Color { r: 0, g: 248, b: 248 }];
+        // full cyan, 0x7FE0, which comes back the same after being stored as a GBA color
+        let mut palette = vec![Color::rgb(0, 255, 255)];
 
         for (animation_index, frames) in character.sprite_frames.iter().enumerate() {
             let mut animation = Animation::new();
@@ -96,7 +97,7 @@ impl Spritesheet {
 
                                 // convert to our color struct
                                 let rgb = image.get_pixel_mut(ix as u32, iy as u32).data;
-                                let color = Color { r: rgb[0] as i32, g: rgb[1] as i32, b: rgb[2] as i32 };
+                                let color = Color::rgb(rgb[0] as i32, rgb[1] as i32, rgb[2] as i32);
 
                                 let mut color_index;
 
@@ -126,7 +127,7 @@ impl Spritesheet {
         }
 
         while palette.len() < 16 {
-            palette.push(Color { r: 0, g: 0, b: 0 });
+            palette.push(Color::rgb(0, 0, 0));
         }
 
         Ok((spritesheet, palette))
@@ -359,7 +360,8 @@ impl SpriteManager {
     pub fn save_spritesheet(&self, palette_manager: &mut palette::PaletteManager, character: &Character) -> Result<(), Error> {
         let spritesheet_o = self.spritesheets.get(&character.name.to_string());
         if let Some(spritesheet) = spritesheet_o {
-            let palette = palette_manager.load_palette_colors(character.name.to_string());
+            let palette = palette_manager.load_palette_colors(character.name.to_string())
+                .map_err(|e| Error::new(ErrorKind::NotFound, e.to_string()))?;
             spritesheet.to_img(&palette[..]).save(format!("roms/sprites/{}.png", character.name))?;
         }
         Ok(())
@@ -0,0 +1,217 @@
+use std::error;
+use std::fmt;
+
+const IPS_HEADER: &[u8] = b"PATCH";
+const IPS_FOOTER: &[u8] = b"EOF";
+
+/// furthest offset a 3 byte IPS record can address
+pub const IPS_MAX_OFFSET: usize = 0xFFFFFF;
+
+/// longest data a single IPS record can hold
+const IPS_MAX_RECORD: usize = 0xFFFF;
+
+/// a record starting here would be read back as the "EOF" footer
+const IPS_EOF_OFFSET: usize = 0x454F46;
+
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    /// the patch doesn't start with "PATCH"
+    MissingHeader,
+    /// the patch ended in the middle of a record or before "EOF"
+    UnexpectedEnd,
+    /// a strict patch tried to write past the end of the ROM
+    OutOfBounds { offset: usize, length: usize },
+    /// a change lies past `IPS_MAX_OFFSET`, which the 3 byte record offsets can't reach
+    OffsetTooLarge(usize),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PatchError::MissingHeader => write!(f, "not an IPS patch"),
+            PatchError::UnexpectedEnd => write!(f, "IPS patch ended early"),
+            PatchError::OutOfBounds { offset, length } =>
+                write!(f, "IPS record of {} bytes at {:#X} is past the end of the ROM", length, offset),
+            PatchError::OffsetTooLarge(offset) =>
+                write!(f, "a change at {:#X} is past {:#X}, the furthest an IPS patch's 24 bit offsets reach", offset, IPS_MAX_OFFSET),
+        }
+    }
+}
+
+impl error::Error for PatchError {}
+
+/// build an IPS patch that turns `original` into `modified`, one record per run of changed bytes
+///
+/// IPS can't shrink a file, so bytes past the end of a shorter `modified` are ignored.
+/// fails if a change lies past `IPS_MAX_OFFSET`, which IPS can't address
+pub fn diff_to_ips(original: &[u8], modified: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut patch = IPS_HEADER.to_vec();
+    let changed = |i: usize| original.get(i) != Some(&modified[i]);
+
+    let mut position = 0;
+    while position < modified.len() {
+        if !changed(position) {
+            position += 1;
+            continue;
+        }
+
+        // back up a byte rather than start a record that looks like the footer
+        let start = if position == IPS_EOF_OFFSET { position - 1 } else { position };
+        let mut end = position;
+        while end < modified.len() && end - start < IPS_MAX_RECORD && changed(end) {
+            end += 1;
+        }
+        if start > IPS_MAX_OFFSET {
+            return Err(PatchError::OffsetTooLarge(start));
+        }
+
+        patch.extend_from_slice(&[(start >> 16) as u8, (start >> 8) as u8, start as u8]);
+        let length = end - start;
+        patch.extend_from_slice(&[(length >> 8) as u8, length as u8]);
+        patch.extend_from_slice(&modified[start..end]);
+        position = end;
+    }
+
+    patch.extend_from_slice(IPS_FOOTER);
+    Ok(patch)
+}
+
+/// apply an IPS patch, growing the ROM if a record writes past its end
+pub fn apply_ips(rom: &mut Vec<u8>, patch: &[u8]) -> Result<(), PatchError> {
+    apply_ips_records(rom, patch, false)
+}
+
+/// apply an IPS patch, failing if a record writes past the end of the ROM
+pub fn apply_ips_strict(rom: &mut Vec<u8>, patch: &[u8]) -> Result<(), PatchError> {
+    apply_ips_records(rom, patch, true)
+}
+
+fn apply_ips_records(rom: &mut Vec<u8>, patch: &[u8], strict: bool) -> Result<(), PatchError> {
+    if !patch.starts_with(IPS_HEADER) {
+        return Err(PatchError::MissingHeader);
+    }
+
+    let mut position = IPS_HEADER.len();
+    let mut take = |length: usize| -> Result<&[u8], PatchError> {
+        let bytes = patch.get(position..position + length).ok_or(PatchError::UnexpectedEnd)?;
+        position += length;
+        Ok(bytes)
+    };
+
+    loop {
+        let offset_bytes = take(3)?;
+        if offset_bytes == IPS_FOOTER {
+            return Ok(());
+        }
+        let offset = (offset_bytes[0] as usize) << 16 | (offset_bytes[1] as usize) << 8 | offset_bytes[2] as usize;
+
+        let length_bytes = take(2)?;
+        let length = (length_bytes[0] as usize) << 8 | length_bytes[1] as usize;
+
+        // a zero length record is a run of one byte repeated
+        let data = if length == 0 {
+            let run_bytes = take(3)?;
+            let run_length = (run_bytes[0] as usize) << 8 | run_bytes[1] as usize;
+            vec![run_bytes[2]; run_length]
+        } else {
+            take(length)?.to_vec()
+        };
+
+        let end = offset + data.len();
+        if end > rom.len() {
+            if strict {
+                return Err(PatchError::OutOfBounds { offset, length: data.len() });
+            }
+            rom.resize(end, 0);
+        }
+        rom[offset..end].copy_from_slice(&data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_diff_applies_back_to_the_modified_rom() {
+        let original = vec![0u8; 0x100];
+        let mut modified = original.clone();
+        modified[0x10..0x14].copy_from_slice(&[1, 2, 3, 4]);
+        modified[0x80] = 9;
+        modified.extend_from_slice(&[7, 7]);
+
+        let patch = diff_to_ips(&original, &modified).unwrap();
+        assert!(patch.starts_with(b"PATCH") && patch.ends_with(b"EOF"));
+        let mut rom = original.clone();
+        apply_ips(&mut rom, &patch).unwrap();
+        assert_eq!(rom, modified);
+        assert_eq!(diff_to_ips(&original, &original).unwrap(), b"PATCHEOF".to_vec());
+    }
+
+    #[test]
+    fn a_change_past_16_mib_is_an_error() {
+        let original = vec![0u8; IPS_MAX_OFFSET + 2];
+        let mut modified = original.clone();
+        modified[IPS_MAX_OFFSET] = 1;
+        assert!(diff_to_ips(&original, &modified).is_ok());
+
+        modified[IPS_MAX_OFFSET + 1] = 1;
+        modified[IPS_MAX_OFFSET] = 0;
+        assert_eq!(diff_to_ips(&original, &modified), Err(PatchError::OffsetTooLarge(IPS_MAX_OFFSET + 1)));
+        assert!(PatchError::OffsetTooLarge(IPS_MAX_OFFSET + 1).to_string().contains("24 bit"));
+    }
+
+    #[test]
+    fn a_change_at_the_footer_offset_starts_a_byte_early() {
+        let original = vec![0u8; IPS_EOF_OFFSET + 4];
+        let mut modified = original.clone();
+        modified[IPS_EOF_OFFSET] = 0xAA;
+
+        let patch = diff_to_ips(&original, &modified).unwrap();
+        assert_eq!(&patch[5..8], &[0x45, 0x4F, 0x45]);
+        let mut rom = original.clone();
+        apply_ips(&mut rom, &patch).unwrap();
+        assert_eq!(rom, modified);
+    }
+
+    #[test]
+    fn long_runs_are_split_into_records() {
+        let original = vec![0u8; IPS_MAX_RECORD + 10];
+        let modified = vec![1u8; IPS_MAX_RECORD + 10];
+        let patch = diff_to_ips(&original, &modified).unwrap();
+        let mut rom = original.clone();
+        apply_ips_strict(&mut rom, &patch).unwrap();
+        assert_eq!(rom, modified);
+    }
+
+    #[test]
+    fn malformed_patches_are_errors() {
+        let mut rom = vec![0u8; 4];
+        assert_eq!(apply_ips(&mut rom, b"PATCX"), Err(PatchError::MissingHeader));
+        assert_eq!(apply_ips(&mut rom, b"PATCH\0\0"), Err(PatchError::UnexpectedEnd));
+        assert_eq!(apply_ips(&mut rom, b"PATCH\0\0\x02\0\x04\x01"), Err(PatchError::UnexpectedEnd));
+        assert_eq!(apply_ips_strict(&mut rom, b"PATCH\0\0\x03\0\x02\x01\x02EOF"),
+            Err(PatchError::OutOfBounds { offset: 3, length: 2 }));
+    }
+
+    #[test]
+    fn run_length_records_repeat_a_byte_and_grow_the_rom() {
+        let mut rom = vec![0u8; 4];
+        apply_ips(&mut rom, b"PATCH\0\0\x02\0\0\0\x04\xEEEOF").unwrap();
+        assert_eq!(rom, vec![0, 0, 0xEE, 0xEE, 0xEE, 0xEE]);
+    }
+
+    #[test]
+    fn two_changed_regions_give_two_records() {
+        let original = vec![0u8; 0x100];
+        let mut modified = original.clone();
+        modified[0x10..0x13].copy_from_slice(&[1, 2, 3]);
+        modified[0x80] = 9;
+        let patch = diff_to_ips(&original, &modified).unwrap();
+        let mut expected = b"PATCH".to_vec();
+        expected.extend_from_slice(&[0x00, 0x00, 0x10, 0x00, 0x03, 1, 2, 3]);
+        expected.extend_from_slice(&[0x00, 0x00, 0x80, 0x00, 0x01, 9]);
+        expected.extend_from_slice(b"EOF");
+        assert_eq!(patch, expected);
+    }
+}
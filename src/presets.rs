@@ -0,0 +1,46 @@
+// Bundled recolor presets, as raw GBA values ready to write over a 16 color character palette
+
+pub const PALETTE_PRESETS: &[(&str, [i32; 16])] = &[
+    // an even ramp from black to white
+    ("grayscale", [
+        0x0000, 0x0842, 0x1084, 0x18C6, 0x2108, 0x294A, 0x318C, 0x39CE,
+        0x4631, 0x4E73, 0x56B5, 0x5EF7, 0x6739, 0x6F7B, 0x77BD, 0x7FFF,
+    ]),
+    // the four greens of the original Game Boy screen, darkest first
+    ("game-boy", [
+        0x08E2, 0x08E2, 0x08E2, 0x08E2, 0x1986, 0x1986, 0x1986, 0x1986,
+        0x0AB1, 0x0AB1, 0x0AB1, 0x0AB1, 0x0AF3, 0x0AF3, 0x0AF3, 0x0AF3,
+    ]),
+    // a warm brown ramp like an old photograph
+    ("sepia", [
+        0x0022, 0x0464, 0x0886, 0x10C9, 0x150B, 0x192D, 0x1D6F, 0x21B1,
+        0x25D3, 0x2E15, 0x3257, 0x3679, 0x3ABB, 0x3EFD, 0x471F, 0x4B5F,
+    ]),
+];
+
+/// find a preset by name, ignoring case
+pub fn preset_by_name(name: &str) -> Option<&'static [i32; 16]> {
+    PALETTE_PRESETS.iter()
+        .find(|(preset, _)| preset.eq_ignore_ascii_case(name))
+        .map(|(_, colors)| colors)
+}
+
+/// the names of every bundled preset
+pub fn preset_names() -> Vec<&'static str> {
+    PALETTE_PRESETS.iter().map(|&(name, _)| name).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_are_found_by_name_and_hold_gba_colors() {
+        assert_eq!(preset_by_name("Game-Boy"), Some(&PALETTE_PRESETS[1].1));
+        assert!(preset_by_name("neon").is_none());
+        assert_eq!(preset_names(), vec!["grayscale", "game-boy", "sepia"]);
+        for &(name, ref colors) in PALETTE_PRESETS.iter() {
+            assert!(colors.iter().all(|&value| (0..=0x7FFF).contains(&value)), "{} has a value past 15 bits", name);
+        }
+    }
+}
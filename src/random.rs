@@ -0,0 +1,73 @@
+/// a small seeded generator (SplitMix64), the same seed gives the same numbers on every platform
+/// and every version so randomized palettes can be shared as a seed
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> SplitMix64 {
+        SplitMix64 { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// a number in `0..bound`, `bound` must not be 0
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    /// shuffle a slice in place (Fisher-Yates)
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i as u64 + 1) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+/// derive a seed for one named palette from a master seed, it only depends on the two inputs
+/// so the order palettes are randomized in doesn't matter
+pub fn derive_seed(seed: u64, name: &str) -> u64 {
+    let seed_bytes: Vec<u8> = (0..8).map(|i| (seed >> (i * 8)) as u8).collect();
+    let hash = fnv1a(seed_bytes.iter().chain(name.as_bytes()).cloned());
+    SplitMix64::new(hash).next_u64()
+}
+
+/// 64 bit FNV-1a, a hash that's the same on every run and every machine unlike the `HashMap` one
+pub fn fnv1a<I: IntoIterator<Item = u8>>(bytes: I) -> u64 {
+    bytes.into_iter().fold(0xCBF29CE484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001B3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_generator_matches_the_reference_splitmix64() {
+        let mut random = SplitMix64::new(0);
+        assert_eq!(random.next_u64(), 0xE220A8397B1DCDAF);
+        assert_eq!(fnv1a(Vec::new()), 0xCBF29CE484222325);
+        assert_eq!(fnv1a(b"a".iter().cloned()), 0xAF63DC4C8601EC8C);
+    }
+
+    #[test]
+    fn shuffles_are_permutations_fixed_by_the_seed() {
+        let shuffled = |seed| {
+            let mut items: Vec<u32> = (0..16).collect();
+            SplitMix64::new(seed).shuffle(&mut items);
+            items
+        };
+        assert_eq!(shuffled(41), shuffled(41));
+        assert!(shuffled(41) != shuffled(42));
+        let mut sorted = shuffled(41);
+        sorted.sort();
+        assert_eq!(sorted, (0..16).collect::<Vec<u32>>());
+        assert!((0..1000).all(|_| SplitMix64::new(7).below(3) < 3));
+    }
+}
@@ -0,0 +1,313 @@
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::io::{SeekFrom, Seek, Read, Error, Write};
+use std::result::Result;
+
+use ::data::Region;
+
+/// location of the 12 byte game title in the cartridge header
+pub const HEADER_TITLE: u64 = 0xA0;
+
+/// location of the 4 byte game code in the cartridge header
+pub const HEADER_GAME_CODE: u64 = 0xAC;
+
+/// the game code of every Sonic Battle release starts with this, the last byte is the region.
+/// The codes are the middle of the serials printed on the retail cartridges and listed in the
+/// No-Intro set: AGB-BSBE-USA, AGB-BSBP-EUR and AGB-BSBJ-JPN. The title isn't checked, only reported
+pub const SONIC_BATTLE_GAME_CODE: &str = "BSB";
+
+/// start of the cartridge header fields covered by the complement check
+pub const HEADER_CHECK_START: u64 = 0xA0;
+
+/// location of the header complement check byte
+pub const HEADER_COMPLEMENT: u64 = 0xBD;
+
+/// where the cartridge is mapped in the GBA address space, pointers into the ROM add this
+pub const ROM_BASE: u64 = 0x08000000;
+
+/// smallest ROM accepted by `check_rom_size`, anything less can't hold the header
+pub const MIN_ROM_SIZE: u64 = 1 << 20;
+
+/// largest ROM the GBA can map
+pub const MAX_ROM_SIZE: u64 = 32 << 20;
+
+/// unused space in the ROM is padded with this
+pub const FREE_SPACE_BYTE: u8 = 0xFF;
+
+#[derive(Debug)]
+pub enum RomError {
+    /// the header doesn't belong to Sonic Battle
+    WrongGame { found: String, expected: String },
+    /// the file is too small or too large to be a GBA cartridge
+    BadSize(u64),
+    /// the file is the right size for a cartridge, but not a power of two like every real one
+    OddSize(u64),
+    Io(Error),
+}
+
+impl From<Error> for RomError {
+    fn from(error: Error) -> RomError {
+        RomError::Io(error)
+    }
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomError::WrongGame { ref found, ref expected } =>
+                write!(f, "not a Sonic Battle ROM: found game {}, expected {}", found, expected),
+            RomError::BadSize(size) =>
+                write!(f, "a {} byte file isn't a GBA ROM, they're between {} and {} MiB",
+                    size, MIN_ROM_SIZE >> 20, MAX_ROM_SIZE >> 20),
+            RomError::OddSize(size) =>
+                write!(f, "a {} byte file isn't a GBA ROM, cartridge sizes are a power of two", size),
+            RomError::Io(ref error) => write!(f, "io error: {}", error),
+        }
+    }
+}
+
+impl error::Error for RomError {}
+
+#[derive(Debug)]
+pub enum PointerError {
+    /// the value doesn't point into the cartridge, like a RAM address or a null pointer
+    NotInRom(u32),
+    Io(Error),
+}
+
+impl From<Error> for PointerError {
+    fn from(error: Error) -> PointerError {
+        PointerError::Io(error)
+    }
+}
+
+impl fmt::Display for PointerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PointerError::NotInRom(pointer) => write!(f, "{:#010X} doesn't point into the ROM", pointer),
+            PointerError::Io(ref error) => write!(f, "io error: {}", error),
+        }
+    }
+}
+
+impl error::Error for PointerError {}
+
+/// read a fixed size text field from the cartridge header
+fn read_header_field(file: &mut (impl Read + Seek), offset: u64, length: usize) -> Result<String, Error> {
+    let mut field = vec![0u8; length];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut field)?;
+    Ok(String::from_utf8_lossy(&field).trim_end_matches('\0').to_string())
+}
+
+/// check the file is the size of a GBA cartridge, a power of two from 1 to 32 MiB,
+/// leaving the stream position where it was
+pub fn check_rom_size(file: &mut impl Seek) -> Result<u64, RomError> {
+    let position = file.stream_position()?;
+    let size = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(position))?;
+
+    if !(MIN_ROM_SIZE..=MAX_ROM_SIZE).contains(&size) {
+        return Err(RomError::BadSize(size));
+    }
+    if !size.is_power_of_two() {
+        return Err(RomError::OddSize(size));
+    }
+    Ok(size)
+}
+
+/// check the cartridge header to make sure the file is a Sonic Battle ROM
+pub fn validate_rom(file: &mut (impl Read + Seek)) -> Result<(), RomError> {
+    let title = read_header_field(file, HEADER_TITLE, 12)?;
+    let game_code = read_header_field(file, HEADER_GAME_CODE, 4)?;
+
+    // the game code identifies the game in every region, the title is only reported
+    if game_code.len() != 4 || !game_code.starts_with(SONIC_BATTLE_GAME_CODE) {
+        return Err(RomError::WrongGame {
+            found: format!("{} ({})", title, game_code),
+            expected: format!("{}*", SONIC_BATTLE_GAME_CODE),
+        });
+    }
+    Ok(())
+}
+
+/// detect the release from the region byte at the end of the game code
+pub fn detect_region(file: &mut (impl Read + Seek)) -> Result<Option<Region>, Error> {
+    let mut code = [0u8; 1];
+    file.seek(SeekFrom::Start(HEADER_GAME_CODE + 3))?;
+    file.read_exact(&mut code)?;
+    Ok(Region::from_game_code(code[0]))
+}
+
+/// recompute the header complement check byte, write it, and return it
+pub fn fix_header_checksum(file: &mut (impl Read + Write + Seek)) -> Result<u8, Error> {
+    let mut header = [0u8; (HEADER_COMPLEMENT - HEADER_CHECK_START) as usize];
+    file.seek(SeekFrom::Start(HEADER_CHECK_START))?;
+    file.read_exact(&mut header)?;
+
+    let sum = header.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    let complement = 0u8.wrapping_sub(sum.wrapping_add(0x19));
+
+    file.seek(SeekFrom::Start(HEADER_COMPLEMENT))?;
+    file.write_all(&[complement])?;
+    Ok(complement)
+}
+
+/// find the first run of at least `length` free space bytes starting on a multiple of `alignment`
+pub fn find_free_space(file: &mut (impl Read + Seek), length: usize, alignment: usize) -> Result<Option<u64>, Error> {
+    let alignment = cmp::max(1, alignment) as u64;
+
+    let mut contents = Vec::new();
+    let position = file.stream_position()?;
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut contents)?;
+    file.seek(SeekFrom::Start(position))?;
+
+    if length == 0 {
+        return Ok(Some(0));
+    }
+
+    let mut run_start = 0u64;
+    for (offset, &byte) in contents.iter().enumerate() {
+        let offset = offset as u64;
+        if byte != FREE_SPACE_BYTE {
+            run_start = offset + 1;
+            continue;
+        }
+
+        let aligned_start = run_start.div_ceil(alignment) * alignment;
+        if offset + 1 >= aligned_start + length as u64 {
+            return Ok(Some(aligned_start));
+        }
+    }
+    Ok(None)
+}
+
+/// encode a file offset as the little endian GBA pointer to it
+pub fn rom_pointer_bytes(offset: u64) -> [u8; 4] {
+    let pointer = offset | ROM_BASE;
+    [pointer as u8, (pointer >> 8) as u8, (pointer >> 16) as u8, (pointer >> 24) as u8]
+}
+
+/// read the little endian GBA pointer at `at` and return the file offset it points to
+pub fn read_rom_pointer(file: &mut (impl Read + Seek), at: u64) -> Result<u64, PointerError> {
+    let mut bytes = [0u8; 4];
+    file.seek(SeekFrom::Start(at))?;
+    file.read_exact(&mut bytes)?;
+
+    let pointer = bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24;
+    if pointer >> 24 != (ROM_BASE >> 24) as u32 {
+        return Err(PointerError::NotInRom(pointer));
+    }
+    Ok((pointer as u64) & !ROM_BASE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn the_header_complement_makes_the_bios_check_pass() {
+        let mut bytes = vec![0u8; 0xC0];
+        bytes[0xA0..0xAB].copy_from_slice(b"SONICBATTLE");
+        bytes[0xAC..0xB0].copy_from_slice(b"BSBE");
+        bytes[0xB0..0xB2].copy_from_slice(b"78");
+        bytes[0xB2] = 0x96;
+        bytes[0xBD] = 0x55;
+        let mut file = Cursor::new(bytes);
+        assert_eq!(fix_header_checksum(&mut file).unwrap(), 0x8E);
+
+        let bytes = file.into_inner();
+        assert_eq!(bytes[0xBD], 0x8E);
+        // the BIOS adds 0x19 to the bytes from 0xA0 through the complement and wants 0
+        let sum = bytes[0xA0..=0xBD].iter().fold(0x19u8, |sum, &b| sum.wrapping_add(b));
+        assert_eq!(sum, 0);
+
+        assert_eq!(fix_header_checksum(&mut Cursor::new(vec![0u8; 0xC0])).unwrap(), 0xE7);
+        assert!(fix_header_checksum(&mut Cursor::new(vec![0u8; 0xB0])).is_err());
+    }
+
+    #[test]
+    fn only_sonic_battle_headers_validate() {
+        let mut bytes = vec![0u8; 0xC0];
+        bytes[0xA0..0xAB].copy_from_slice(b"SONICBATTLE");
+        bytes[0xAC..0xB0].copy_from_slice(b"BSBJ");
+        assert!(validate_rom(&mut Cursor::new(bytes.clone())).is_ok());
+
+        bytes[0xAC..0xB0].copy_from_slice(b"BPEE");
+        match validate_rom(&mut Cursor::new(bytes)) {
+            Err(RomError::WrongGame { ref found, ref expected }) => {
+                assert_eq!(found, "SONICBATTLE (BPEE)");
+                assert_eq!(expected, "BSB*");
+            }
+            other => panic!("expected WrongGame, got {:?}", other),
+        }
+        assert!(matches!(validate_rom(&mut Cursor::new(vec![0u8; 0xA4])), Err(RomError::Io(_))));
+    }
+
+    #[test]
+    fn retail_game_codes_are_told_apart() {
+        // the game codes of the retail serials, written out rather than built from the constant
+        for &(code, region) in [(b"BSBE", Region::Usa), (b"BSBP", Region::Europe), (b"BSBJ", Region::Japan)].iter() {
+            let mut bytes = vec![0u8; 0xC0];
+            bytes[0xAC..0xB0].copy_from_slice(code);
+            assert!(validate_rom(&mut Cursor::new(bytes.clone())).is_ok());
+            assert_eq!(detect_region(&mut Cursor::new(bytes)).unwrap(), Some(region));
+        }
+
+        // the title, game code and maker code of Pokemon Emerald's header
+        let mut bytes = vec![0u8; 0xC0];
+        bytes[0xA0..0xAC].copy_from_slice(b"POKEMON EMER");
+        bytes[0xAC..0xB0].copy_from_slice(b"BPEE");
+        bytes[0xB0..0xB2].copy_from_slice(b"01");
+        match validate_rom(&mut Cursor::new(bytes)) {
+            Err(RomError::WrongGame { ref found, .. }) => assert_eq!(found, "POKEMON EMER (BPEE)"),
+            other => panic!("expected WrongGame, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn free_space_is_found_on_an_aligned_run() {
+        let mut bytes = vec![0u8; 64];
+        for byte in bytes[9..20].iter_mut() {
+            *byte = FREE_SPACE_BYTE;
+        }
+        for byte in bytes[40..64].iter_mut() {
+            *byte = FREE_SPACE_BYTE;
+        }
+        let mut file = Cursor::new(bytes);
+        file.set_position(5);
+        assert_eq!(find_free_space(&mut file, 8, 4).unwrap(), Some(12));
+        assert_eq!(find_free_space(&mut file, 8, 1).unwrap(), Some(9));
+        // 9..20 only has 8 bytes from 12, so 9 don't fit until 40
+        assert_eq!(find_free_space(&mut file, 9, 4).unwrap(), Some(40));
+        assert_eq!(find_free_space(&mut file, 8, 0).unwrap(), Some(9));
+        assert_eq!(find_free_space(&mut file, 25, 1).unwrap(), None);
+        assert_eq!(file.position(), 5);
+    }
+
+    #[test]
+    fn rom_pointers_lose_the_cartridge_base() {
+        let mut file = Cursor::new(vec![0x00, 0x34, 0x12, 0x47, 0x08, 0x78, 0x56, 0x34, 0x02, 0xFF]);
+        assert_eq!(read_rom_pointer(&mut file, 1).unwrap(), 0x471234);
+        // 0x02 is the top byte of work RAM
+        assert!(matches!(read_rom_pointer(&mut file, 5), Err(PointerError::NotInRom(0x02345678))));
+        assert!(matches!(read_rom_pointer(&mut file, 7), Err(PointerError::Io(_))));
+    }
+
+    #[test]
+    fn cartridge_sizes_are_powers_of_two_from_1_to_32_mib() {
+        for &size in [1u64 << 20, 4 << 20, 16 << 20, 32 << 20].iter() {
+            let mut file = Cursor::new(vec![0u8; size as usize]);
+            file.set_position(7);
+            assert_eq!(check_rom_size(&mut file).unwrap(), size);
+            assert_eq!(file.position(), 7);
+        }
+        assert!(matches!(check_rom_size(&mut Cursor::new(Vec::new())), Err(RomError::BadSize(0))));
+        assert!(matches!(check_rom_size(&mut Cursor::new(vec![0u8; (1 << 20) - 1])), Err(RomError::BadSize(_))));
+        assert!(matches!(check_rom_size(&mut Cursor::new(vec![0u8; (32 << 20) + 2])), Err(RomError::BadSize(_))));
+        assert!(matches!(check_rom_size(&mut Cursor::new(vec![0u8; 3 << 20])), Err(RomError::OddSize(_))));
+    }
+}
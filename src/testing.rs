@@ -0,0 +1,92 @@
+// Fixtures shared by the unit tests
+
+use std::env;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ::random::SplitMix64;
+use ::rom;
+
+/// the size of the US release
+pub const ROM_SIZE: usize = 16 << 20;
+
+static NEXT_PATH: AtomicUsize = AtomicUsize::new(0);
+
+/// an empty ROM the size of the US release with its game code, BSBE, and a stand-in title,
+/// zeroed outside of them
+pub fn blank_rom() -> Vec<u8> {
+    let mut bytes = vec![0u8; ROM_SIZE];
+    let title = rom::HEADER_TITLE as usize;
+    bytes[title..title + 11].copy_from_slice(b"SONICBATTLE");
+    let code = rom::HEADER_GAME_CODE as usize;
+    bytes[code..code + 4].copy_from_slice(b"BSBE");
+    bytes
+}
+
+/// `blank_rom` filled with seeded noise after the header, with the top bit of every color clear
+pub fn random_rom(seed: u64) -> Vec<u8> {
+    let mut bytes = blank_rom();
+    let mut random = SplitMix64::new(seed);
+    for pair in bytes[0xC0..].chunks_mut(2) {
+        let value = random.next_u64() as u16 & 0x7FFF;
+        pair[0] = value as u8;
+        pair[1] = (value >> 8) as u8;
+    }
+    bytes
+}
+
+/// an in memory ROM to share with a manager
+pub fn rom_file(bytes: Vec<u8>) -> Arc<Mutex<Cursor<Vec<u8>>>> {
+    Arc::new(Mutex::new(Cursor::new(bytes)))
+}
+
+/// the bytes currently in an in memory ROM
+pub fn contents(file: &Arc<Mutex<Cursor<Vec<u8>>>>) -> Vec<u8> {
+    file.lock().unwrap().get_ref().clone()
+}
+
+/// a path in the temp directory that no other test uses
+pub fn temp_path(name: &str) -> PathBuf {
+    let n = NEXT_PATH.fetch_add(1, Ordering::SeqCst);
+    env::temp_dir().join(format!("sbrx-{}-{}-{}", process::id(), n, name))
+}
+
+/// a ROM that counts the seeks and reads made on it
+pub struct CountingRom<S> {
+    pub inner: S,
+    pub seeks: usize,
+    pub reads: usize,
+}
+
+impl<S> CountingRom<S> {
+    pub fn new(inner: S) -> CountingRom<S> {
+        CountingRom { inner, seeks: 0, reads: 0 }
+    }
+}
+
+impl<S: Read> Read for CountingRom<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reads += 1;
+        self.inner.read(buf)
+    }
+}
+
+impl<S: Write> Write for CountingRom<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Seek> Seek for CountingRom<S> {
+    fn seek(&mut self, from: SeekFrom) -> io::Result<u64> {
+        self.seeks += 1;
+        self.inner.seek(from)
+    }
+}
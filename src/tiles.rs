@@ -0,0 +1,107 @@
+use std::error;
+use std::fmt;
+
+/// width and height of a GBA tile in pixels
+pub const TILE_SIZE: usize = 8;
+
+/// bytes in a 4bpp tile, two pixels per byte
+pub const TILE_4BPP_BYTES: usize = 32;
+
+#[derive(Debug, PartialEq)]
+pub enum TileError {
+    /// a 4bpp tile can only reference the first 16 colors of a palette
+    IndexTooLarge { x: usize, y: usize, index: u8 },
+    /// the data doesn't divide into whole tiles
+    PartialTile(usize),
+}
+
+impl fmt::Display for TileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TileError::IndexTooLarge { x, y, index } =>
+                write!(f, "index {} at ({}, {}) doesn't fit in 4 bits", index, x, y),
+            TileError::PartialTile(length) =>
+                write!(f, "{} bytes isn't a whole number of {} byte tiles", length, TILE_4BPP_BYTES),
+        }
+    }
+}
+
+impl error::Error for TileError {}
+
+/// unpack an 8x8 4bpp tile into palette indices, each byte holds two pixels with the left one in the low nibble
+pub fn decode_tile_4bpp(bytes: &[u8; TILE_4BPP_BYTES]) -> [[u8; TILE_SIZE]; TILE_SIZE] {
+    let mut indices = [[0u8; TILE_SIZE]; TILE_SIZE];
+    for (i, byte) in bytes.iter().enumerate() {
+        let y = i / (TILE_SIZE / 2);
+        let x = (i % (TILE_SIZE / 2)) * 2;
+        indices[y][x] = byte & 0x0F;
+        indices[y][x + 1] = byte >> 4;
+    }
+    indices
+}
+
+/// unpack a run of 4bpp tiles read from the ROM
+pub fn decode_tiles_4bpp(bytes: &[u8]) -> Result<Vec<[[u8; TILE_SIZE]; TILE_SIZE]>, TileError> {
+    if !bytes.len().is_multiple_of(TILE_4BPP_BYTES) {
+        return Err(TileError::PartialTile(bytes.len()));
+    }
+    Ok(bytes.chunks(TILE_4BPP_BYTES).map(|chunk| {
+        let mut tile = [0u8; TILE_4BPP_BYTES];
+        tile.copy_from_slice(chunk);
+        decode_tile_4bpp(&tile)
+    }).collect())
+}
+
+/// pack an 8x8 grid of palette indices into a 4bpp tile, the inverse of `decode_tile_4bpp`
+pub fn encode_tile_4bpp(indices: &[[u8; TILE_SIZE]; TILE_SIZE]) -> Result<[u8; TILE_4BPP_BYTES], TileError> {
+    let mut bytes = [0u8; TILE_4BPP_BYTES];
+    for (y, row) in indices.iter().enumerate() {
+        for (x, &index) in row.iter().enumerate() {
+            if index > 0x0F {
+                return Err(TileError::IndexTooLarge { x, y, index });
+            }
+            let byte = &mut bytes[y * (TILE_SIZE / 2) + x / 2];
+            *byte |= if x % 2 == 0 { index } else { index << 4 };
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_decode_low_nibble_first() {
+        let mut bytes = [0u8; TILE_4BPP_BYTES];
+        bytes[0] = 0x21;
+        bytes[3] = 0xF0;
+        bytes[31] = 0x5A;
+        let tile = decode_tile_4bpp(&bytes);
+        assert_eq!(tile[0], [1, 2, 0, 0, 0, 0, 0, 15]);
+        assert_eq!(tile[7], [0, 0, 0, 0, 0, 0, 10, 5]);
+        assert!(tile[1..7].iter().all(|row| *row == [0; TILE_SIZE]));
+
+        let mut run = bytes.to_vec();
+        run.extend_from_slice(&[0x11; TILE_4BPP_BYTES]);
+        let tiles = decode_tiles_4bpp(&run).unwrap();
+        assert_eq!(tiles, vec![tile, [[1; TILE_SIZE]; TILE_SIZE]]);
+        assert_eq!(decode_tiles_4bpp(&run[..40]), Err(TileError::PartialTile(40)));
+    }
+
+    #[test]
+    fn encoding_inverts_decoding_and_rejects_wide_indices() {
+        let mut bytes = [0u8; TILE_4BPP_BYTES];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = (i as u8).wrapping_mul(37);
+        }
+        assert_eq!(encode_tile_4bpp(&decode_tile_4bpp(&bytes)).unwrap(), bytes);
+
+        let mut indices = [[0u8; TILE_SIZE]; TILE_SIZE];
+        indices[2][3] = 7;
+        indices[2][2] = 9;
+        assert_eq!(encode_tile_4bpp(&indices).unwrap()[9], 0x79);
+        indices[4][5] = 16;
+        assert_eq!(encode_tile_4bpp(&indices), Err(TileError::IndexTooLarge { x: 5, y: 4, index: 16 }));
+    }
+}
@@ -0,0 +1,104 @@
+// ROM IO is traced through `log` when the "log" feature is on. Without it the macros
+// still type check their arguments but compile to nothing
+
+#[cfg(feature = "log")]
+macro_rules! io_debug {
+    ($($arg:tt)*) => { debug!($($arg)*) }
+}
+
+#[cfg(feature = "log")]
+macro_rules! io_warn {
+    ($($arg:tt)*) => { warn!($($arg)*) }
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! io_debug {
+    ($($arg:tt)*) => { if false { let _ = format!($($arg)*); } }
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! io_warn {
+    ($($arg:tt)*) => { if false { let _ = format!($($arg)*); } }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::data::SONIC_DATA;
+    use ::manager::palette::PaletteManager;
+    use ::testing;
+
+    #[cfg(feature = "log")]
+    mod capture {
+        use std::sync::Mutex;
+        use ::log::{self, Log, Metadata, Record, LevelFilter};
+
+        lazy_static! {
+            pub static ref LINES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        }
+
+        struct Capture;
+
+        impl Log for Capture {
+            fn enabled(&self, _: &Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &Record) {
+                LINES.lock().unwrap().push(format!("{} {}", record.level(), record.args()));
+            }
+
+            fn flush(&self) {}
+        }
+
+        static CAPTURE: Capture = Capture;
+
+        /// send every log line to `LINES`, the logger can only be set once so later calls do nothing
+        pub fn install() {
+            if log::set_logger(&CAPTURE).is_ok() {
+                log::set_max_level(LevelFilter::Debug);
+            }
+        }
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn reads_are_logged_with_their_offset() {
+        capture::install();
+        let mut manager = PaletteManager::from_bytes(testing::blank_rom());
+        manager.read_palette(&SONIC_DATA).unwrap();
+
+        let lines = capture::LINES.lock().unwrap();
+        assert!(lines.iter().any(|line| line.starts_with("DEBUG") && line.contains("Sonic") && line.contains("0x47AFB8")),
+            "no read line in {:?}", *lines);
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn failed_reads_are_logged_as_warnings() {
+        capture::install();
+        let mut manager = PaletteManager::from_bytes(vec![0u8; 0x100]);
+        assert!(manager.read_palette(&SONIC_DATA).is_err());
+
+        let lines = capture::LINES.lock().unwrap();
+        assert!(lines.iter().any(|line| line.starts_with("WARN") && line.contains("0x47AFB8")), "no warning in {:?}", *lines);
+    }
+
+    #[cfg(not(feature = "log"))]
+    #[test]
+    fn the_macros_compile_to_nothing_without_the_feature() {
+        use std::fmt;
+
+        struct Panics;
+
+        impl fmt::Display for Panics {
+            fn fmt(&self, _: &mut fmt::Formatter) -> fmt::Result {
+                panic!("a disabled trace macro formatted its arguments")
+            }
+        }
+
+        io_debug!("{}", Panics);
+        io_warn!("{}", Panics);
+        let mut manager = PaletteManager::from_bytes(testing::blank_rom());
+        manager.read_palette(&SONIC_DATA).unwrap();
+    }
+}